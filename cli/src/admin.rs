@@ -0,0 +1,36 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+/// Sends a single command to a running server's admin socket, from `CONNECT4000_ADMIN_SOCKET`, and
+/// prints the answer
+pub fn run_admin(command: &[String]) {
+    if command.is_empty() {
+        panic!("Provide an admin command, e.g. `players`, `kick <player id>` or `say <message>`");
+    }
+
+    let path = std::env::var("CONNECT4000_ADMIN_SOCKET").unwrap_or("admin.sock".to_string());
+    let token = std::env::var("CONNECT4000_ADMIN_TOKEN").expect("Set CONNECT4000_ADMIN_TOKEN!");
+
+    let mut stream = UnixStream::connect(&path).expect("Failed to connect to the admin socket!");
+    write!(stream, "auth {}\n{}\n", token, command.join(" ")).unwrap();
+
+    // The answer to authenticating, then to the command, each ends with `ok` or an error
+    let mut answers = 0;
+    for line in BufReader::new(stream).lines() {
+        let line = line.expect("Failed to read from the admin socket!");
+
+        if line.starts_with("error: ") {
+            eprintln!("{}", line);
+            std::process::exit(1);
+        }
+        if line == "ok" {
+            answers += 1;
+            if answers == 2 {
+                return;
+            }
+            continue;
+        }
+
+        println!("{}", line);
+    }
+}
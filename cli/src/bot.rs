@@ -0,0 +1,120 @@
+use connect4000_core::{Coins, Color};
+use connect4000_server::bot::Bot;
+use connect4000_server::BotStrategy;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// Why a bot couldn't come up with a move
+#[derive(Debug)]
+pub enum BotError {
+    Io(std::io::Error),
+    Exited,
+    InvalidColumn(String),
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BotError::Io(error) => write!(f, "couldn't talk to the bot command: {}", error),
+            BotError::Exited => write!(f, "the bot command exited"),
+            BotError::InvalidColumn(line) => {
+                write!(f, "the bot command answered {:?}, not a column", line)
+            }
+        }
+    }
+}
+
+/// The bot playing for the cli, one of the server's strategies or a program of the player's own
+pub enum PlayingBot {
+    Strategy(Box<dyn Bot>),
+    External(ExternalBot),
+}
+
+impl PlayingBot {
+    /// A built-in strategy by name, or a command to run as an external bot
+    pub fn from_arg(bot: &str) -> Self {
+        match BotStrategy::parse(bot) {
+            Some(strategy) => PlayingBot::Strategy(strategy.bot()),
+            None => PlayingBot::External(ExternalBot::spawn(bot)),
+        }
+    }
+
+    pub fn choose_column(&mut self, coins: &Coins, color: &Color) -> Result<u64, BotError> {
+        match self {
+            PlayingBot::Strategy(bot) => Ok(bot.choose_column(coins, color)),
+            PlayingBot::External(bot) => bot.choose_column(coins, color),
+        }
+    }
+}
+
+/// A bot played by another program. For every move it's sent the board as a line on its stdin,
+/// and answers with the column to drop its coin in as a line on its stdout.
+pub struct ExternalBot {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl ExternalBot {
+    /// Starts the program with `sh -c`, so the command can carry its own arguments
+    pub fn spawn(command: &str) -> Self {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to start the bot command!");
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+
+        ExternalBot {
+            child,
+            stdin,
+            stdout,
+        }
+    }
+}
+
+/// The bot's colour, then every column from the bottom up as colour numbers, `.` for an empty one
+fn board_line(coins: &Coins, color: &Color) -> String {
+    let mut line = color.serialize().to_string();
+
+    for column in coins {
+        line.push(' ');
+        if column.is_empty() {
+            line.push('.');
+        }
+        for coin in column {
+            line.push_str(&coin.color.serialize().to_string());
+        }
+    }
+
+    line
+}
+
+impl ExternalBot {
+    /// Sends the command the board and reads back its column, which the server checks like any
+    /// other move
+    pub fn choose_column(&mut self, coins: &Coins, color: &Color) -> Result<u64, BotError> {
+        writeln!(self.stdin, "{}", board_line(coins, color)).map_err(BotError::Io)?;
+
+        let mut line = String::new();
+        let read = self.stdout.read_line(&mut line).map_err(BotError::Io)?;
+        if read == 0 {
+            return Err(BotError::Exited);
+        }
+
+        let line = line.trim();
+        line.parse()
+            .map_err(|_| BotError::InvalidColumn(line.to_string()))
+    }
+}
+
+impl Drop for ExternalBot {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
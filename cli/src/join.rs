@@ -1,7 +1,18 @@
-use connect4000_core::{debug_render_game, Coin, Coins, Color};
-use connect4000_server::{ClientConfig, Command, Endpoint};
+use connect4000_core::{debug_print_game, Coin, Coins, Color, Game};
+use connect4000_server::transport::{self, RecvHalf, SendHalf};
+use connect4000_server::{
+    parse_color, CertificateHash, ClientConfig, CloseReason, Command, Credential, Endpoint,
+    MatchPreferences, ResumeToken, SnapshotEncoding, View, MAX_CHAT_LEN, MAX_NAME_LEN,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{Instrument, Span};
+use wtransport::tls::Sha256Digest;
 
-use crate::utils::clear_screen;
+use crate::bot::PlayingBot;
+use crate::utils::{clear_screen, decode_hex, encode_hex};
 
 pub fn get_user_column_input() -> u64 {
     let mut input = String::new();
@@ -15,121 +26,678 @@ pub fn get_user_column_input() -> u64 {
     input.unwrap() - 1
 }
 
-fn debug_print_snapshot(snapshot: &[u8], indent: usize) {
-    let mut new_coins = Coins::default();
+/// A column to drop a coin in, `r` to vote for a rematch, `c <colour>` to change colour before
+/// the first coin, or anything else to chat
+fn get_user_command() -> Option<Command> {
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).unwrap();
+    let input = input.trim();
+
+    if input.is_empty() {
+        return None;
+    }
+    if input == "r" {
+        return Some(Command::Rematch);
+    }
+    if let Some(color) = input.strip_prefix("c ").and_then(parse_color) {
+        return Some(Command::ChangeColor(color));
+    }
 
-    let winner_id = u64::from_be_bytes(snapshot.get(1..9).unwrap().try_into().unwrap());
+    match input.parse::<u64>() {
+        Ok(column) if column > 0 => Some(Command::PlayCoin(column - 1)),
+        _ => {
+            let mut message = input.to_string();
+            while message.len() > MAX_CHAT_LEN as usize {
+                message.pop();
+            }
+            Some(Command::Chat(message))
+        }
+    }
+}
 
-    let number_of_columns = u64::from_be_bytes(snapshot.get(9..17).unwrap().try_into().unwrap());
+fn read_u64(view: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(view.get(offset..offset + 8).unwrap().try_into().unwrap())
+}
 
-    let number_of_rows = u64::from_be_bytes(snapshot.get(17..25).unwrap().try_into().unwrap());
+fn print_closed(closed: &[u8]) {
+    match CloseReason::deserialize(closed.get(1).unwrap()) {
+        Some(CloseReason::GameFull) => println!("The game is full."),
+        Some(CloseReason::ServerFull) => println!("The server is full."),
+        Some(CloseReason::ServerShutdown) => println!("The server is shutting down."),
+        Some(CloseReason::Kicked) => println!("You were removed from the game."),
+        Some(CloseReason::GameEnded) => println!("The game was ended."),
+        Some(CloseReason::RateLimited) => println!("Too many commands, slow down."),
+        Some(CloseReason::TooManySessions) => println!("Too many sessions from your address."),
+        Some(CloseReason::FrameTooLarge) => println!("Sent a frame too large for the server."),
+        Some(CloseReason::NotAuthenticated) => {
+            println!("The server didn't accept your credential.")
+        }
+        Some(CloseReason::Replaced) => println!("You joined the game from somewhere else."),
+        None => println!("Closed by server."),
+    }
+}
 
-    let has_winner = winner_id != 0;
+fn print_server_message(message: &[u8]) {
+    println!(
+        "Server: {}",
+        String::from_utf8_lossy(message.get(9..).unwrap())
+    );
+}
 
-    let header_offset = 25;
+/// The name a player goes by, or their id if the server hasn't named them
+fn player_name(names: &HashMap<u64, String>, player_id: u64) -> String {
+    match names.get(&player_id) {
+        Some(name) => name.clone(),
+        None => format!("Player {}", player_id),
+    }
+}
 
-    for column_index in 0..number_of_columns {
-        let mut new_column = Vec::new();
+fn print_chat(chat: &[u8], names: &HashMap<u64, String>) {
+    // The time of day it was sent, in UTC
+    let secs = read_u64(chat, 9) / 1000;
+    println!(
+        "[{:02}:{:02}:{:02}] {}: {}",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        player_name(names, read_u64(chat, 1)),
+        String::from_utf8_lossy(chat.get(25..).unwrap())
+    );
+}
 
-        for row_index in 0..number_of_rows {
-            let index = (column_index * number_of_rows) + row_index;
-            let coin = snapshot.get(header_offset + index as usize).unwrap();
-            let coin = *coin;
-            if coin == 0 {
-                continue;
-            }
-            new_column.push(Coin {
-                color: Color::deserialize(&coin),
-                group: 0,
-            });
-        }
-        new_coins.push(new_column);
+fn print_clock(clock: &[u8]) {
+    let on_turn = read_u64(clock, 1);
+    if on_turn == 0 {
+        return;
     }
 
-    let render_rows = debug_render_game(&new_coins);
+    let turn_remaining = read_u64(clock, 9) as f64 / 1000.0;
+    println!("Player {} to move, {:.1}s left.", on_turn, turn_remaining);
 
-    let win_status_label = if has_winner {
-        format!("WINNER! Player {}", winner_id)
-    } else {
-        "PLAYING".to_owned()
-    };
+    for index in 0..read_u64(clock, 17) as usize {
+        let offset = 25 + index * 16;
+        let remaining = read_u64(clock, offset + 8) as f64 / 1000.0;
+        println!(
+            "  Player {} clock {:.1}s",
+            read_u64(clock, offset),
+            remaining
+        );
+    }
+}
+
+fn print_score(score: &[u8]) {
+    println!("Round {} score:", read_u64(score, 1) + 1);
+
+    for index in 0..read_u64(score, 9) as usize {
+        let offset = 17 + index * 16;
+        println!(
+            "  Player {} won {}",
+            read_u64(score, offset),
+            read_u64(score, offset + 8)
+        );
+    }
+}
 
-    let mut axis_label = String::new();
-    for n in 1..=new_coins.len() {
-        axis_label += format!("  {} ", n).as_str();
+fn winner_from_id(winner_id: u64) -> Game {
+    let mut game = Game::default();
+    if winner_id != 0 {
+        game.winner_id = Some(winner_id);
     }
+    game
+}
+
+/// The client's copy of the game, kept up to date from snapshots and the deltas that follow them
+struct Board {
+    game: Game,
+    coins: Coins,
+    sequence: u64,
+}
 
-    let breaker = (0..(axis_label.len() / 2))
-        .map(|_| "--")
-        .collect::<String>();
+impl Board {
+    fn from_snapshot(snapshot: &[u8]) -> Self {
+        let mut coins = Coins::default();
 
-    let indentation = (0..indent).map(|_| ' ').collect::<String>();
+        let winner_id = read_u64(snapshot, 1);
+        let number_of_columns = read_u64(snapshot, 9);
+        let number_of_rows = read_u64(snapshot, 17);
+        let sequence = read_u64(snapshot, 25);
 
-    println!();
-    println!("{}{}", indentation, win_status_label);
-    println!("{}{}", indentation, breaker);
-    println!("{}{}", indentation, axis_label);
-    println!("{}{}", indentation, breaker);
-    for row in render_rows {
-        println!("{}{}", indentation, row);
+        let header_offset = 33;
+
+        for column_index in 0..number_of_columns {
+            let mut new_column = Vec::new();
+
+            for row_index in 0..number_of_rows {
+                let index = (column_index * number_of_rows) + row_index;
+                let coin = snapshot.get(header_offset + index as usize).unwrap();
+                let coin = *coin;
+                if coin == 0 {
+                    continue;
+                }
+                new_column.push(Coin {
+                    color: Color::deserialize(&coin),
+                    group: 0,
+                });
+            }
+            coins.push(new_column);
+        }
+
+        Board {
+            game: winner_from_id(winner_id),
+            coins,
+            sequence,
+        }
     }
 
-    println!();
+    /// Applies a `CoinPlaced` view, returning false if any views were missed since the last one
+    fn apply_coin_placed(&mut self, coin_placed: &[u8]) -> bool {
+        let sequence = read_u64(coin_placed, 1);
+        let column = read_u64(coin_placed, 9);
+        let color = coin_placed.get(25).unwrap();
+        let winner_id = read_u64(coin_placed, 34);
+
+        if sequence <= self.sequence {
+            return true;
+        }
+        if sequence != self.sequence + 1 {
+            return false;
+        }
+
+        let column = self.coins.get_mut(column as usize);
+        if column.is_none() {
+            return false;
+        }
+        column.unwrap().push(Coin {
+            color: Color::deserialize(color),
+            group: 0,
+        });
+
+        self.game = winner_from_id(winner_id);
+        self.sequence = sequence;
+
+        true
+    }
+
+    fn render(&self) {
+        clear_screen();
+        debug_print_game(&self.game, &self.coins, 10);
+        println!("Press 1 to {} to drop your coin.", self.coins.len());
+        if self.game.winner_id.is_some() {
+            println!("Press r to vote for a rematch.");
+        }
+    }
 }
 
-fn render_snapshot(snapshot: &[u8]) {
-    clear_screen();
-    debug_print_snapshot(snapshot, 10);
-    println!("Press 1,2,3 or 4 to drop your coin.");
+/// The hash printed by `server serve`, pinned in place of validating the certificate chain
+fn pinned_certificate_hash() -> Option<CertificateHash> {
+    let hash = std::env::var("CONNECT4000_CERT_HASH").ok()?;
+
+    let hash = decode_hex(&hash.replace(':', ""))
+        .and_then(|hash| hash.try_into().ok())
+        .expect("Invalid certificate hash!");
+
+    Some(hash)
 }
 
-pub async fn join_server() {
-    let port = 4001;
+/// The server to connect to, `https://` for WebTransport or `ws://` for WebSocket
+fn server_url() -> String {
+    std::env::var("CONNECT4000_SERVER").unwrap_or("https://localhost:4001".to_string())
+}
 
-    let config = ClientConfig::builder()
-        .with_bind_default()
-        .with_no_cert_validation()
-        .build();
+async fn connect_to_server() -> (Box<dyn SendHalf>, Box<dyn RecvHalf>) {
+    let url = server_url();
 
     println!("Connecting to server..");
+
+    if url.starts_with("ws://") {
+        let (socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        println!("Connected to server!");
+
+        let (socket_tx, socket_rx) = transport::websocket(socket);
+        return (Box::new(socket_tx), Box::new(socket_rx));
+    }
+
+    let config = match pinned_certificate_hash() {
+        Some(hash) => ClientConfig::builder()
+            .with_bind_default()
+            .with_server_certificate_hashes([Sha256Digest::new(hash)])
+            .build(),
+        None => ClientConfig::builder()
+            .with_bind_default()
+            .with_native_certs()
+            .build(),
+    };
+
     let connection = Endpoint::client(config)
         .unwrap()
-        .connect(format!("https://localhost:{}", port))
+        .connect(url)
         .await
         .unwrap();
     println!("Connected to server!");
 
-    let (mut socket_tx, mut socket_rx) = connection.open_bi().await.unwrap().await.unwrap();
+    let (socket_tx, socket_rx) = connection.open_bi().await.unwrap().await.unwrap();
+    (Box::new(socket_tx), Box::new(socket_rx))
+}
+
+/// Covers everything the cli does against the server for one `join`, `match` or `spectate`,
+/// naming the game and player once the server hands them over
+pub fn session_span(action: &'static str) -> Span {
+    tracing::info_span!(
+        "session",
+        action,
+        server = server_url(),
+        game_id = tracing::field::Empty,
+        player_id = tracing::field::Empty,
+    )
+}
+
+/// The name to go by from `CONNECT4000_NAME`, or the user's login name
+fn display_name() -> String {
+    let mut name = std::env::var("CONNECT4000_NAME")
+        .or(std::env::var("USER"))
+        .unwrap_or_default();
+    while name.len() > MAX_NAME_LEN as usize {
+        name.pop();
+    }
+    name
+}
+
+/// Where the credential the server issued is kept, `CONNECT4000_CREDENTIAL_PATH` or a file in the
+/// home directory
+fn credential_path() -> PathBuf {
+    match std::env::var("CONNECT4000_CREDENTIAL_PATH") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => PathBuf::from(std::env::var("HOME").unwrap_or(".".to_string()))
+            .join(".connect4000-credential"),
+    }
+}
+
+/// Names the player, proving who they are with a saved credential. Without one the server issues
+/// a credential to save.
+fn identify() -> Command {
+    let credential = std::fs::read_to_string(credential_path())
+        .ok()
+        .map(|credential| {
+            decode_hex(credential.trim())
+                .and_then(|credential| credential.try_into().ok())
+                .expect("Invalid credential, remove it to be issued a new one!")
+        });
+
+    Command::Identify(display_name(), credential)
+}
+
+/// The colour to ask for on joining, from `CONNECT4000_COLOR`
+fn preferred_color() -> Option<Command> {
+    let color = std::env::var("CONNECT4000_COLOR").ok()?;
+    let color = parse_color(&color).expect("Invalid colour!");
+
+    Some(Command::ChangeColor(color))
+}
+
+fn save_credential(view: &[u8]) {
+    let credential: Credential = view.get(1..49).unwrap().try_into().unwrap();
+    let path = credential_path();
+    match std::fs::write(&path, encode_hex(&credential)) {
+        Ok(()) => println!("Saved your credential to {}", path.display()),
+        Err(error) => println!("Failed to save your credential: {}", error),
+    }
+}
+
+/// Reads the first view of the handshake, saving a credential issued ahead of it
+async fn read_greeting(socket_rx: &mut dyn RecvHalf) -> Vec<u8> {
+    loop {
+        let view = View::read(socket_rx).await.unwrap();
+        if *view.first().unwrap() != 23 {
+            return view;
+        }
+        save_credential(&view);
+    }
+}
+
+fn spawn_command_writer(mut socket_tx: Box<dyn SendHalf>) -> mpsc::Sender<Command> {
+    let (command_tx, mut command_rx) = mpsc::channel::<Command>(u8::MAX as usize);
+
+    let writer = async move {
+        // Ask for compact snapshots ahead of the handshake, boards can get very wide
+        let encoding = Command::SnapshotEncoding(SnapshotEncoding::DeflatedRuns);
+        if socket_tx.write_all(&encoding.serialize()).await.is_err() {
+            return;
+        }
+
+        while let Some(command) = command_rx.recv().await {
+            tracing::debug!(?command, "sending command");
+            if socket_tx.write_all(&command.serialize()).await.is_err() {
+                break;
+            }
+        }
+    };
+    tokio::spawn(writer.instrument(Span::current()));
+
+    command_tx
+}
+
+fn spawn_view_reader(
+    mut socket_rx: Box<dyn RecvHalf>,
+    command_tx: mpsc::Sender<Command>,
+) -> JoinHandle<()> {
+    let reader = async move {
+        let mut board: Option<Board> = None;
+        let mut names: HashMap<u64, String> = HashMap::new();
+
+        loop {
+            let view = View::read(socket_rx.as_mut()).await;
+            if view.is_none() {
+                println!("Disconnected from server.");
+                std::process::exit(0);
+            }
+            let view = view.unwrap();
+
+            match view.first().unwrap() {
+                1 => {
+                    let snapshot = Board::from_snapshot(&view);
+                    snapshot.render();
+                    board = Some(snapshot);
+                }
+                11 => {
+                    let snapshot = View::expand_snapshot(&view).unwrap();
+                    let snapshot = Board::from_snapshot(&snapshot);
+                    snapshot.render();
+                    board = Some(snapshot);
+                }
+                3 => {
+                    let player_id = read_u64(&view, 1);
+                    println!("{} left the game.", player_name(&names, player_id));
+                }
+                7 => {
+                    println!("{} spectating.", read_u64(&view, 1));
+                }
+                8 => {
+                    let applied = match board.as_mut() {
+                        Some(board) => board.apply_coin_placed(&view),
+                        None => false,
+                    };
+
+                    if applied {
+                        board.as_ref().unwrap().render();
+                    } else {
+                        tracing::debug!("missed a view, requesting a resync");
+                        board = None;
+                        let _ = command_tx.send(Command::Resync).await;
+                    }
+                }
+                12 => {
+                    print_closed(&view);
+                    std::process::exit(0);
+                }
+                13 => {
+                    print_clock(&view);
+                }
+                15 => {
+                    println!(
+                        "{} of {} players voted for a rematch.",
+                        read_u64(&view, 1),
+                        read_u64(&view, 9)
+                    );
+                }
+                16 => {
+                    print_score(&view);
+                }
+                19 => {
+                    print_server_message(&view);
+                }
+                21 => {
+                    print_chat(&view, &names);
+                }
+                24 => {
+                    let player_id = read_u64(&view, 1);
+                    let name = String::from_utf8_lossy(view.get(19..).unwrap()).into_owned();
+                    println!(
+                        "Player {} {:?} is {}.",
+                        player_id,
+                        Color::deserialize(view.get(9).unwrap()),
+                        name
+                    );
+                    names.insert(player_id, name);
+                }
+                26 => {
+                    println!(
+                        "{} changed colour to {:?}.",
+                        player_name(&names, read_u64(&view, 1)),
+                        Color::deserialize(view.get(9).unwrap())
+                    );
+                }
+                payload_type => {
+                    tracing::debug!(payload_type, "ignoring view");
+                }
+            }
+        }
+    }
+    .instrument(Span::current());
+    tokio::spawn(reader)
+}
+
+pub async fn join_server(resume_token: Option<&String>) {
+    let (socket_tx, socket_rx) = connect_to_server().await;
+    let command_tx = spawn_command_writer(socket_tx);
 
-    let mut joined_buffer = vec![0; 1 + 8 + 1];
-    socket_rx.read(&mut joined_buffer).await.unwrap().unwrap();
-    let payload_type = joined_buffer.first().unwrap();
+    let handshake = match resume_token {
+        Some(token) => {
+            let token: ResumeToken = decode_hex(token)
+                .and_then(|token| token.try_into().ok())
+                .expect("Invalid resume token!");
+            Command::Resume(token)
+        }
+        None => Command::Join,
+    };
+    command_tx.send(identify()).await.unwrap();
+    if let Some(color) = preferred_color() {
+        command_tx.send(color).await.unwrap();
+    }
+    command_tx.send(handshake).await.unwrap();
+
+    play(socket_rx, command_tx).await;
+}
+
+pub async fn find_match(columns: Option<&String>, win_size: Option<&String>) {
+    let parse = |input: Option<&String>| input.map(|input| input.parse().expect("Invalid number!"));
+    let preferences = MatchPreferences {
+        columns: parse(columns),
+        win_size: parse(win_size),
+    };
+
+    let (socket_tx, mut socket_rx) = connect_to_server().await;
+    let command_tx = spawn_command_writer(socket_tx);
+
+    // The server rates the identity the credential proves
+    command_tx.send(identify()).await.unwrap();
+    command_tx
+        .send(Command::FindMatch(preferences))
+        .await
+        .unwrap();
+    println!("Looking for a match..");
+
+    let found = read_greeting(socket_rx.as_mut()).await;
+    let payload_type = found.first().unwrap();
+    if *payload_type == 12 {
+        print_closed(&found);
+        return;
+    }
+    if *payload_type != 18 {
+        panic!("invalid match found payload type: {:?}", payload_type);
+    }
+    Span::current().record("game_id", read_u64(&found, 1));
+    println!(
+        "Matched in game {}, your rating {} against {}.",
+        read_u64(&found, 1),
+        read_u64(&found, 9),
+        read_u64(&found, 17)
+    );
+    if found[41] == 1 {
+        println!("You go first.");
+    }
+
+    play(socket_rx, command_tx).await;
+}
+
+/// Reads the seat the server hands over, or `None` if the server closed the session instead
+async fn take_seat(socket_rx: &mut dyn RecvHalf) -> Option<(u64, Color)> {
+    let joined = read_greeting(socket_rx).await;
+    let payload_type = joined.first().unwrap();
+    if *payload_type == 12 {
+        print_closed(&joined);
+        return None;
+    }
     if *payload_type != 0 {
         panic!("invalid joined payload type: {:?}", payload_type);
     }
-    let player_id = joined_buffer.get(1..9).unwrap();
-    let player_id = u64::from_be_bytes(player_id.try_into().unwrap());
-    let color = joined_buffer.get(9).unwrap();
+    let player_id = read_u64(&joined, 1);
+    let color = joined.get(9).unwrap();
     let color = Color::deserialize(color);
+    let token = joined.get(10..26).unwrap();
+
+    Span::current().record("player_id", player_id);
+    tracing::info!(?color, "seated");
 
     println!("Your player id is{:?}#{:?}", player_id, color);
+    println!(
+        "Rejoin with your seat using resume token {}",
+        encode_hex(token)
+    );
 
-    tokio::spawn(async move {
-        loop {
-            let size = u8::MAX as usize;
-            let mut snapshot_buffer = vec![0; size];
-            socket_rx.read(&mut snapshot_buffer).await.unwrap().unwrap();
+    Some((player_id, color))
+}
+
+/// Takes the seat the server hands over, then plays until the server goes away
+async fn play(mut socket_rx: Box<dyn RecvHalf>, command_tx: mpsc::Sender<Command>) {
+    if take_seat(socket_rx.as_mut()).await.is_none() {
+        return;
+    }
+
+    spawn_view_reader(socket_rx, command_tx.clone());
 
-            render_snapshot(snapshot_buffer.as_slice());
+    loop {
+        let command = get_user_command();
+        if command.is_none() {
+            continue;
         }
-    });
+
+        command_tx.send(command.unwrap()).await.unwrap();
+    }
+}
+
+/// Joins a game and plays it without anyone at the keyboard. The server doesn't say whose turn it
+/// is, so the bot moves after every coin that isn't its own, and whenever a clock names it, and
+/// the server turns down any move made out of turn.
+pub async fn join_bot(bot: Option<&String>) {
+    let bot = bot.expect("Provide a bot, either `random`, `greedy` or a command to run");
+    let mut bot = PlayingBot::from_arg(bot);
+
+    let (socket_tx, mut socket_rx) = connect_to_server().await;
+    let command_tx = spawn_command_writer(socket_tx);
+    command_tx.send(identify()).await.unwrap();
+    if let Some(color) = preferred_color() {
+        command_tx.send(color).await.unwrap();
+    }
+    command_tx.send(Command::Join).await.unwrap();
+
+    let seat = take_seat(socket_rx.as_mut()).await;
+    if seat.is_none() {
+        return;
+    }
+    let (player_id, color) = seat.unwrap();
+
+    let mut board: Option<Board> = None;
+    let mut voted = false;
 
     loop {
-        let input = get_user_column_input();
+        let view = View::read(socket_rx.as_mut()).await;
+        if view.is_none() {
+            println!("Disconnected from server.");
+            return;
+        }
+        let view = view.unwrap();
+
+        let mut move_now = false;
+        match view.first().unwrap() {
+            1 => {
+                board = Some(Board::from_snapshot(&view));
+                move_now = true;
+            }
+            11 => {
+                let snapshot = View::expand_snapshot(&view).unwrap();
+                board = Some(Board::from_snapshot(&snapshot));
+                move_now = true;
+            }
+            3 => {
+                println!("Player {} left the game.", read_u64(&view, 1));
+            }
+            8 => {
+                let applied = match board.as_mut() {
+                    Some(board) => board.apply_coin_placed(&view),
+                    None => false,
+                };
 
-        socket_tx
-            .write_all(&Command::PlayCoin(input).serialize())
-            .await
-            .unwrap();
+                if applied {
+                    move_now = read_u64(&view, 26) != player_id;
+                } else {
+                    tracing::debug!("missed a view, requesting a resync");
+                    board = None;
+                    command_tx.send(Command::Resync).await.unwrap();
+                }
+            }
+            12 => {
+                print_closed(&view);
+                return;
+            }
+            13 => {
+                move_now = read_u64(&view, 1) == player_id;
+            }
+            19 => {
+                print_server_message(&view);
+            }
+            payload_type => {
+                tracing::debug!(payload_type, "ignoring view");
+            }
+        }
+
+        if board.is_none() {
+            continue;
+        }
+        let board = board.as_ref().unwrap();
+
+        // Keep playing, every game over is followed by a vote for the next
+        if let Some(winner_id) = board.game.winner_id {
+            if !voted {
+                println!("Player {} won, voting for a rematch.", winner_id);
+                command_tx.send(Command::Rematch).await.unwrap();
+                voted = true;
+            }
+            continue;
+        }
+        voted = false;
+
+        if move_now {
+            let column = tokio::task::block_in_place(|| bot.choose_column(&board.coins, &color));
+            if let Err(error) = column {
+                // Leaving the seat to the server's leave policy, as if the player had disconnected
+                println!("The bot can't play on, {}. Leaving the game.", error);
+                return;
+            }
+            let column = column.unwrap();
+            tracing::debug!(column, "bot chose a column");
+            println!("Dropping a coin in column {}.", column + 1);
+            command_tx.send(Command::PlayCoin(column)).await.unwrap();
+        }
     }
 }
+
+pub async fn spectate_server() {
+    let (socket_tx, socket_rx) = connect_to_server().await;
+    let command_tx = spawn_command_writer(socket_tx);
+
+    command_tx.send(Command::Spectate).await.unwrap();
+
+    println!("Spectating, watch only.");
+
+    spawn_view_reader(socket_rx, command_tx).await.unwrap();
+}
@@ -1,15 +1,37 @@
-use join::join_server;
+use admin::run_admin;
+use join::{find_match, join_bot, join_server, session_span, spectate_server};
 use local::run_local;
 use start::start_server;
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
 
+mod admin;
+mod bot;
 mod join;
 mod local;
 mod start;
+#[cfg(test)]
+mod test;
 mod utils;
 
+/// Logs to stderr, filtered by `RUST_LOG`. `CONNECT4000_LOG_FORMAT` picks how, `pretty` for people
+/// to read or `json` for one object per line, spans and all.
+fn init_tracing() {
+    let format = std::env::var("CONNECT4000_LOG_FORMAT").unwrap_or("pretty".to_string());
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr);
+
+    match format.as_str() {
+        "pretty" => subscriber.pretty().init(),
+        "json" => subscriber.json().with_span_list(true).init(),
+        _ => panic!("Invalid log format! Either `pretty` or `json`"),
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    init_tracing();
 
     let args: Vec<String> = std::env::args().collect();
 
@@ -23,14 +45,28 @@ async fn main() {
     if target == "local" {
         run_local();
     } else if action.is_none() {
-        panic!("Provide a server type. Either `serve` or `join`");
+        panic!("Provide a server type. Either `serve`, `join`, `match`, `spectate` or `admin`");
     }
 
     let action = action.unwrap();
 
     if action == "serve" {
-        start_server().await;
+        start_server(args.get(3)).await;
     } else if action == "join" {
-        join_server().await;
+        if args.get(3).is_some_and(|arg| arg == "--bot") {
+            join_bot(args.get(4)).instrument(session_span("join")).await;
+        } else {
+            join_server(args.get(3))
+                .instrument(session_span("join"))
+                .await;
+        }
+    } else if action == "match" {
+        find_match(args.get(3), args.get(4))
+            .instrument(session_span("match"))
+            .await;
+    } else if action == "admin" {
+        run_admin(&args[3..]);
+    } else if action == "spectate" {
+        spectate_server().instrument(session_span("spectate")).await;
     }
 }
@@ -1,23 +1,64 @@
 use std::path::Path;
 
-use connect4000_server::ServerConfig;
-use tokio::sync::oneshot;
+use connect4000_server::{format_certificate_hash, ServerConfig};
+use tokio::signal::unix::{signal, Signal, SignalKind};
 
-pub async fn start_server() {
-    let (started_tx, started_rx) = oneshot::channel();
+/// Resolves on the first SIGINT or SIGTERM
+async fn shutdown_signal(mut terminate: Signal) {
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
 
-    let config = ServerConfig {
-        port: 4001,
-        cert_path: Path::new("../tls/cert.pem"),
-        key_path: Path::new("../tls/key.pem"),
+pub async fn start_server(config_path: Option<&String>) {
+    let config = match config_path {
+        Some(path) => ServerConfig::load(Path::new(path)),
+        None => Ok(ServerConfig::default()),
     };
+    if let Err(error) = &config {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+    let config = config.unwrap();
+
+    // Listening before the server starts, so it's never left running without a way to stop it
+    let terminate = signal(SignalKind::terminate());
+    if let Err(error) = &terminate {
+        eprintln!("Can't listen for SIGTERM: {}", error);
+        std::process::exit(1);
+    }
+    let terminate = terminate.unwrap();
+
+    println!("\n\n Starting server with config: {:?}", config);
+    let server = connect4000_server::start_server(config).await;
+    if let Err(error) = &server {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+    let server = server.unwrap();
+
+    let certificate_hash = format_certificate_hash(&server.certificate_hash());
 
-    let server_thread = tokio::spawn(async move {
-        println!("\n\n Starting server with config: {:?}", config);
-        connect4000_server::start_server(config, started_tx).await;
-    });
+    println!("Listening on {}", server.local_addr());
+    println!("Certificate hash {}", certificate_hash);
+    println!(
+        "Join with `CONNECT4000_CERT_HASH={} connect4000 server join`",
+        certificate_hash
+    );
+    if let Some(websocket_addr) = server.websocket_addr() {
+        println!("WebSocket listening on {}", websocket_addr);
+        println!(
+            "Join with `CONNECT4000_SERVER=ws://localhost:{} connect4000 server join`",
+            websocket_addr.port()
+        );
+    }
+    if let Some(metrics_addr) = server.metrics_addr() {
+        println!("Metrics on http://{}/metrics", metrics_addr);
+    }
 
-    started_rx.await.unwrap();
+    shutdown_signal(terminate).await;
 
-    server_thread.await.unwrap();
+    println!("Shutting down..");
+    server.shutdown().await;
 }
@@ -0,0 +1,34 @@
+use connect4000_core::{Coin, Color};
+
+use crate::bot::{BotError, ExternalBot};
+
+fn coins() -> Vec<Vec<Coin>> {
+    let coin = |color: Color| Coin { color, group: 0 };
+    vec![vec![coin(Color::Orange), coin(Color::Blue)], vec![]]
+}
+
+#[test]
+fn test_external_bot_answers_with_a_column() {
+    // Only answers once it's been sent the board it expects
+    let mut bot =
+        ExternalBot::spawn(r#"while read line; do [ "$line" = "2 12 ." ] && echo 1; done"#);
+
+    assert_eq!(bot.choose_column(&coins(), &Color::Blue).unwrap(), 1);
+    assert_eq!(bot.choose_column(&coins(), &Color::Blue).unwrap(), 1);
+}
+
+#[test]
+fn test_external_bot_errors_instead_of_panicking() {
+    let mut bot = ExternalBot::spawn("read line; echo left");
+    assert!(matches!(
+        bot.choose_column(&coins(), &Color::Blue),
+        Err(BotError::InvalidColumn(line)) if line == "left"
+    ));
+
+    // Whether the board or the answer goes missing first is down to timing
+    let mut bot = ExternalBot::spawn("exit 0");
+    assert!(matches!(
+        bot.choose_column(&coins(), &Color::Blue),
+        Err(BotError::Exited | BotError::Io(_))
+    ));
+}
@@ -1,3 +1,18 @@
 pub fn clear_screen() {
     print!("{}[2J", 27 as char);
 }
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..input.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(input.get(index..index + 2)?, 16).ok())
+        .collect()
+}
@@ -167,6 +167,8 @@ pub type Coins = Vec<CoinColumn>;
 #[derive(Default, Debug)]
 pub struct Game {
     pub winner_id: Option<u64>,
+    /// How many connected coins win the game, defaults to the number of columns
+    pub win_size: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -181,6 +183,7 @@ pub enum GameError {
     CoinNotFound,
     ColumnsAlreadySet,
     CantPlayCoinInEndedGame,
+    NotPlayersTurn,
 }
 
 type ColumnSet<'a> = HashSet<&'a CoinColumn>;
@@ -349,7 +352,8 @@ impl Game {
 
         let group_len = GroupStore::add(groups, lowest, &(column_index, coin_index))?;
 
-        if group_len >= coins.len() as u64 {
+        let win_size = self.win_size.unwrap_or(coins.len() as u64);
+        if group_len >= win_size {
             self.winner_id = Some(player_id);
         }
 
@@ -108,3 +108,24 @@ fn test_check_wins_diagonal() {
         };
     }
 }
+
+#[test]
+fn test_check_wins_with_win_size() {
+    let mut game = Game {
+        win_size: Some(3),
+        ..Default::default()
+    };
+    let player = Player::purple(1);
+
+    let mut coins = game.set_columns(8).unwrap();
+    let mut groups = HashMap::new();
+
+    for row_index in 0..3 {
+        let mut coin = player.coin(groups.len() as u64);
+        let winner_id = game
+            .play_coin(1, 5, &mut coin, &mut groups, &mut coins)
+            .unwrap();
+
+        assert_eq!(winner_id.is_some(), row_index == 2);
+    }
+}
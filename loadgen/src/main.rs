@@ -0,0 +1,368 @@
+use connect4000_server::transport::{RecvHalf, SendHalf};
+use connect4000_server::{
+    CertificateHash, ClientConfig, Command, Endpoint, SnapshotEncoding, View,
+};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tracing_subscriber::EnvFilter;
+use wtransport::endpoint::endpoint_side::Client;
+use wtransport::tls::Sha256Digest;
+use wtransport::VarInt;
+
+#[cfg(test)]
+mod test;
+
+/// What to throw at the server, from the command line and the same environment the cli reads
+struct LoadConfig {
+    url: String,
+    certificate_hash: Option<CertificateHash>,
+    sessions: u64,
+    /// Coins each session tries to drop every second
+    move_rate: f64,
+    duration: Duration,
+}
+
+impl LoadConfig {
+    /// `connect4000-loadgen [sessions] [moves_per_sec] [duration_secs]`
+    fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        let arg = |index: usize, default: &str| -> String {
+            args.get(index).cloned().unwrap_or(default.to_string())
+        };
+
+        let sessions = arg(1, "100").parse().expect("Invalid session count!");
+        let move_rate: f64 = arg(2, "1").parse().expect("Invalid move rate!");
+        let duration = arg(3, "30").parse().expect("Invalid duration!");
+        if move_rate <= 0.0 {
+            panic!("The move rate must be above zero!");
+        }
+
+        let certificate_hash = std::env::var("CONNECT4000_CERT_HASH").ok().map(|hash| {
+            decode_hex(&hash.replace(':', ""))
+                .and_then(|hash| hash.try_into().ok())
+                .expect("Invalid certificate hash!")
+        });
+
+        LoadConfig {
+            url: std::env::var("CONNECT4000_SERVER")
+                .unwrap_or("https://localhost:4001".to_string()),
+            certificate_hash,
+            sessions,
+            move_rate,
+            duration: Duration::from_secs(duration),
+        }
+    }
+}
+
+fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..input.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(input.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn read_u64(view: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(view[offset..offset + 8].try_into().unwrap())
+}
+
+/// What a single session saw over the run
+#[derive(Debug, Default)]
+struct SessionReport {
+    /// From sending `Join` to the `Joined` view coming back
+    join_latency: Option<Duration>,
+    /// From sending a `PlayCoin` to the `CoinPlaced` view for it coming back
+    move_latencies: Vec<Duration>,
+    moves_sent: u64,
+    /// `CoinPlaced` views received, for any player's coin
+    coins_seen: u64,
+    error: Option<&'static str>,
+}
+
+impl SessionReport {
+    fn failed(error: &'static str) -> Self {
+        SessionReport {
+            error: Some(error),
+            ..SessionReport::default()
+        }
+    }
+}
+
+/// Reads views off the stream as they arrive, stamped with when they did. Reading in a task of its
+/// own keeps a half read view from being dropped when the session is busy sending a move.
+fn spawn_view_reader(
+    mut socket_rx: Box<dyn RecvHalf>,
+) -> mpsc::UnboundedReceiver<(Instant, Vec<u8>)> {
+    let (view_tx, view_rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        while let Some(view) = View::read(socket_rx.as_mut()).await {
+            if view_tx.send((Instant::now(), view)).is_err() {
+                break;
+            }
+        }
+    });
+
+    view_rx
+}
+
+/// Joins the game as a new player, then drops coins in random columns until the deadline. Every
+/// session votes for a rematch once a game is won, so play carries on for the whole run.
+async fn run_session(
+    endpoint: Arc<Endpoint<Client>>,
+    config: Arc<LoadConfig>,
+    deadline: Instant,
+) -> SessionReport {
+    let connection = endpoint.connect(&config.url).await;
+    if connection.is_err() {
+        return SessionReport::failed("connect");
+    }
+    let connection = connection.unwrap();
+
+    let streams = match connection.open_bi().await {
+        Ok(opening) => opening.await.ok(),
+        Err(_) => None,
+    };
+    if streams.is_none() {
+        return SessionReport::failed("open stream");
+    }
+    let (socket_tx, socket_rx) = streams.unwrap();
+    let mut socket_tx: Box<dyn SendHalf> = Box::new(socket_tx);
+
+    let report = play(socket_tx.as_mut(), Box::new(socket_rx), &config, deadline).await;
+
+    connection.close(VarInt::from_u32(0), b"done");
+    report
+}
+
+async fn play(
+    socket_tx: &mut dyn SendHalf,
+    socket_rx: Box<dyn RecvHalf>,
+    config: &LoadConfig,
+    deadline: Instant,
+) -> SessionReport {
+    let mut report = SessionReport::default();
+
+    let encoding = Command::SnapshotEncoding(SnapshotEncoding::DeflatedRuns);
+    let join_sent = Instant::now();
+    let mut handshake = encoding.serialize();
+    handshake.extend(Command::Join.serialize());
+    if socket_tx.write_all(&handshake).await.is_err() {
+        return SessionReport::failed("write");
+    }
+
+    let mut view_rx = spawn_view_reader(socket_rx);
+
+    let joined = view_rx.recv().await;
+    let player_id = match joined {
+        Some((received, view)) if view[0] == 0 => {
+            report.join_latency = Some(received - join_sent);
+            read_u64(&view, 1)
+        }
+        Some((_, view)) if view[0] == 12 => return SessionReport::failed("closed by server"),
+        _ => return SessionReport::failed("stream closed"),
+    };
+
+    let mut columns: u64 = 0;
+    let mut voted = false;
+    let mut pending: Option<Instant> = None;
+
+    let mut moves = tokio::time::interval(Duration::from_secs_f64(1.0 / config.move_rate));
+    moves.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        let mut command = None;
+
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => break,
+            _ = moves.tick() => {
+                // Out of turn coins are turned down by the server, and never come back placed
+                if columns > 0 {
+                    let column = rand::thread_rng().gen_range(0..columns);
+                    command = Some(Command::PlayCoin(column));
+                    pending = Some(Instant::now());
+                    report.moves_sent += 1;
+                }
+            }
+            view = view_rx.recv() => {
+                if view.is_none() {
+                    report.error = Some("stream closed");
+                    break;
+                }
+                let (received, view) = view.unwrap();
+
+                let winner_id = match view[0] {
+                    1 | 11 => {
+                        columns = read_u64(&view, 9);
+                        voted = false;
+                        read_u64(&view, 1)
+                    }
+                    8 => {
+                        report.coins_seen += 1;
+                        if read_u64(&view, 26) == player_id {
+                            if let Some(sent) = pending.take() {
+                                report.move_latencies.push(received - sent);
+                            }
+                        }
+                        read_u64(&view, 34)
+                    }
+                    12 => {
+                        report.error = Some("closed by server");
+                        break;
+                    }
+                    _ => 0,
+                };
+
+                if winner_id != 0 && !voted {
+                    command = Some(Command::Rematch);
+                    voted = true;
+                }
+            }
+        }
+
+        if let Some(command) = command {
+            if socket_tx.write_all(&command.serialize()).await.is_err() {
+                report.error = Some("write");
+                break;
+            }
+        }
+    }
+
+    report
+}
+
+/// The value below which the given percent of the sorted samples fall
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    sorted[(sorted.len() - 1) * percent / 100]
+}
+
+fn print_latencies(name: &str, mut samples: Vec<Duration>) {
+    samples.sort();
+
+    println!(
+        "{:<16}p50 {:>9.2?}  p90 {:>9.2?}  p99 {:>9.2?}  max {:>9.2?}  ({} samples)",
+        name,
+        percentile(&samples, 50),
+        percentile(&samples, 90),
+        percentile(&samples, 99),
+        percentile(&samples, 100),
+        samples.len()
+    );
+}
+
+fn print_report(config: &LoadConfig, reports: Vec<SessionReport>, elapsed: Duration) {
+    let mut join_latencies = Vec::new();
+    let mut move_latencies = Vec::new();
+    let (mut moves_sent, mut coins_seen) = (0, 0);
+    let mut errors: BTreeMap<&'static str, u64> = BTreeMap::new();
+
+    for report in reports {
+        join_latencies.extend(report.join_latency);
+        move_latencies.extend(report.move_latencies);
+        moves_sent += report.moves_sent;
+        coins_seen += report.coins_seen;
+        if let Some(error) = report.error {
+            *errors.entry(error).or_default() += 1;
+        }
+    }
+
+    let coins_placed = move_latencies.len();
+    let secs = elapsed.as_secs_f64();
+
+    println!();
+    println!(
+        "{:<16}{} joined of {}, over {:.1}s",
+        "Sessions",
+        join_latencies.len(),
+        config.sessions,
+        secs
+    );
+    print_latencies("Join latency", join_latencies);
+    print_latencies("Move latency", move_latencies);
+    println!(
+        "{:<16}{} sent, {} placed, {:.1} placed/s",
+        "Moves",
+        moves_sent,
+        coins_placed,
+        coins_placed as f64 / secs
+    );
+    println!(
+        "{:<16}{} coins seen, {:.1} coins/s across every session",
+        "Broadcasts",
+        coins_seen,
+        coins_seen as f64 / secs
+    );
+
+    let errors: Vec<String> = errors
+        .iter()
+        .map(|(error, count)| format!("{} {}", error, count))
+        .collect();
+    println!(
+        "{:<16}{}",
+        "Errors",
+        if errors.is_empty() {
+            "none".to_string()
+        } else {
+            errors.join(", ")
+        }
+    );
+}
+
+/// Runs every session against the server until the deadline, returning what each one saw
+async fn run_load(config: Arc<LoadConfig>, deadline: Instant) -> Vec<SessionReport> {
+    let client_config = match config.certificate_hash {
+        Some(hash) => ClientConfig::builder()
+            .with_bind_default()
+            .with_server_certificate_hashes([Sha256Digest::new(hash)])
+            .build(),
+        None => ClientConfig::builder()
+            .with_bind_default()
+            .with_native_certs()
+            .build(),
+    };
+    // Every session shares the one endpoint, and its socket
+    let endpoint = Arc::new(Endpoint::client(client_config).unwrap());
+
+    let mut sessions = JoinSet::new();
+    for _ in 0..config.sessions {
+        sessions.spawn(run_session(endpoint.clone(), config.clone(), deadline));
+    }
+
+    let mut reports = Vec::new();
+    while let Some(report) = sessions.join_next().await {
+        reports.push(report.unwrap());
+    }
+
+    reports
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let config = Arc::new(LoadConfig::from_args());
+
+    println!(
+        "Running {} sessions against {} for {:?}, {} moves/s each..",
+        config.sessions, config.url, config.duration, config.move_rate
+    );
+
+    let started = Instant::now();
+    let reports = run_load(config.clone(), started + config.duration).await;
+
+    print_report(&config, reports, started.elapsed());
+}
@@ -0,0 +1,42 @@
+use connect4000_server::{start_server, ServerConfig};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::{run_load, LoadConfig};
+
+#[tokio::test]
+async fn test_short_run_against_a_server() {
+    let server = start_server(ServerConfig {
+        bind_address: SocketAddr::from(([127, 0, 0, 1], 0)),
+        websocket_bind_address: None,
+        metrics_bind_address: None,
+        ..ServerConfig::default()
+    })
+    .await
+    .unwrap();
+
+    let config = Arc::new(LoadConfig {
+        url: format!("https://localhost:{}", server.local_addr().port()),
+        certificate_hash: Some(server.certificate_hash()),
+        sessions: 4,
+        move_rate: 20.0,
+        duration: Duration::from_secs(2),
+    });
+    let reports = run_load(config.clone(), Instant::now() + config.duration).await;
+    server.shutdown().await;
+
+    assert_eq!(reports.len(), 4);
+    for report in &reports {
+        assert_eq!(report.error, None);
+        assert!(report.join_latency.is_some());
+    }
+    // Coins go in, and are broadcast to every session
+    let placed: usize = reports
+        .iter()
+        .map(|report| report.move_latencies.len())
+        .sum();
+    assert!(placed > 0);
+    assert!(reports.iter().all(|report| report.coins_seen > 0));
+}
@@ -0,0 +1,71 @@
+#![feature(test)]
+extern crate test;
+
+use connect4000_core::{Coin, Coins, Color};
+use connect4000_server::snapshot::{
+    deflate, deserialize_coins_runs, inflate, serialize_coins, serialize_coins_runs,
+};
+use test::Bencher;
+
+/// A wide board with ragged columns, where most columns are still empty
+fn setup(column_count: usize) -> Coins {
+    (0..column_count)
+        .map(|column_index| {
+            let height = if column_index % 7 == 0 {
+                column_index % 5
+            } else {
+                0
+            };
+
+            (0..height)
+                .map(|row_index| Coin {
+                    color: if (column_index + row_index) % 2 == 0 {
+                        Color::Orange
+                    } else {
+                        Color::Blue
+                    },
+                    group: 0,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[bench]
+fn bench_dense_1_million_columns(b: &mut Bencher) {
+    let coins = setup(1_000_000);
+
+    b.iter(|| serialize_coins(&coins));
+}
+
+#[bench]
+fn bench_runs_1_million_columns(b: &mut Bencher) {
+    let coins = setup(1_000_000);
+
+    b.iter(|| serialize_coins_runs(&coins));
+}
+
+#[bench]
+fn bench_deflated_runs_1_million_columns(b: &mut Bencher) {
+    let coins = setup(1_000_000);
+
+    b.iter(|| deflate(&serialize_coins_runs(&coins)));
+}
+
+#[bench]
+fn bench_expand_runs_1_million_columns(b: &mut Bencher) {
+    let coins = setup(1_000_000);
+    let (_, col_count, row_count) = serialize_coins(&coins);
+    let runs = serialize_coins_runs(&coins);
+
+    b.iter(|| deserialize_coins_runs(&runs, col_count, row_count));
+}
+
+#[bench]
+fn bench_expand_deflated_runs_1_million_columns(b: &mut Bencher) {
+    let coins = setup(1_000_000);
+    let (_, col_count, row_count) = serialize_coins(&coins);
+    let deflated = deflate(&serialize_coins_runs(&coins));
+
+    b.iter(|| deserialize_coins_runs(&inflate(&deflated).unwrap(), col_count, row_count));
+}
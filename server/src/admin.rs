@@ -0,0 +1,225 @@
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinSet;
+use tracing::{Instrument, Span};
+
+use crate::{Actions, CloseReason, ServerMessageViewData, SessionContext, View};
+
+const MAX_LINE_LEN: u64 = 4096;
+// In bytes
+pub(crate) const MAX_SERVER_MESSAGE_LEN: usize = 1024;
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AdminAction {
+    Players,
+    Kick(u64),
+    Reset,
+    End,
+    Columns(u64),
+    Dump,
+}
+
+#[derive(Debug)]
+pub(crate) enum AdminReply {
+    Lines(Vec<String>),
+    // The connection the player was on, if any, to close
+    Kicked(Option<u64>),
+    Ended,
+    Error(String),
+}
+
+// Compares tokens without bailing out at the first differing byte
+fn tokens_match(token: &[u8], expected: &[u8]) -> bool {
+    token.len() == expected.len()
+        && token
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn read_line(reader: &mut BufReader<UnixStream>) -> Option<String> {
+    let mut line = String::new();
+    let read = reader.take(MAX_LINE_LEN).read_line(&mut line).await.ok()?;
+    if read == 0 || !line.ends_with('\n') {
+        return None;
+    }
+
+    Some(line.trim_end().to_string())
+}
+
+async fn request(context: &SessionContext, action: AdminAction) -> AdminReply {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if context
+        .tx
+        .send(Actions::Admin(action, reply_tx))
+        .await
+        .is_err()
+    {
+        return AdminReply::Error("game not running".to_string());
+    }
+
+    reply_rx
+        .await
+        .unwrap_or(AdminReply::Error("game not running".to_string()))
+}
+
+async fn run_command(context: &SessionContext, line: &str) -> Result<Vec<String>, String> {
+    let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+    let number = || -> Result<u64, String> {
+        argument
+            .parse()
+            .map_err(|_| format!("{} needs a number", command))
+    };
+
+    let action = match command {
+        "players" => AdminAction::Players,
+        "kick" => AdminAction::Kick(number()?),
+        "reset" => AdminAction::Reset,
+        "end" => AdminAction::End,
+        "columns" => AdminAction::Columns(number()?),
+        "dump" => AdminAction::Dump,
+        "say" => {
+            if argument.is_empty() || argument.len() > MAX_SERVER_MESSAGE_LEN {
+                return Err(format!(
+                    "say needs a message of 1 to {} bytes",
+                    MAX_SERVER_MESSAGE_LEN
+                ));
+            }
+
+            // View - ServerMessage
+            let message = View::serialize(View::ServerMessage(ServerMessageViewData {
+                message: argument.to_string(),
+            }));
+            tracing::info!(message = argument, "admin broadcast a message");
+            context
+                .broadcast_tx
+                .send((message, Span::current()))
+                .await
+                .map_err(|_| "game not running".to_string())?;
+            return Ok(Vec::new());
+        }
+        _ => return Err(format!("unknown command {:?}", command)),
+    };
+
+    match request(context, action).await {
+        AdminReply::Lines(lines) => Ok(lines),
+        // View - Closed, for the player's connection alone, or for every connection
+        AdminReply::Kicked(connection_id) => {
+            let outbox = match connection_id {
+                Some(connection_id) => context
+                    .broadcast_channels
+                    .read()
+                    .await
+                    .get(&connection_id)
+                    .cloned(),
+                None => None,
+            };
+            if let Some(outbox) = outbox {
+                outbox.kick(CloseReason::Kicked);
+            }
+            Ok(Vec::new())
+        }
+        AdminReply::Ended => {
+            let outboxes: Vec<_> = context
+                .broadcast_channels
+                .read()
+                .await
+                .values()
+                .cloned()
+                .collect();
+            for outbox in outboxes {
+                outbox.kick(CloseReason::GameEnded);
+            }
+            Ok(Vec::new())
+        }
+        AdminReply::Error(error) => Err(error),
+    }
+}
+
+async fn handle(stream: UnixStream, context: &SessionContext, token: &str) {
+    let mut reader = BufReader::new(stream);
+
+    let auth = tokio::time::timeout(AUTH_TIMEOUT, read_line(&mut reader)).await;
+    let authenticated = match auth {
+        Ok(Some(line)) => line
+            .strip_prefix("auth ")
+            .is_some_and(|given| tokens_match(given.as_bytes(), token.as_bytes())),
+        _ => false,
+    };
+    if !authenticated {
+        tracing::info!("admin authentication failed");
+        let _ = reader.get_mut().write_all(b"error: not authorized\n").await;
+        return;
+    }
+    if reader.get_mut().write_all(b"ok\n").await.is_err() {
+        return;
+    }
+
+    while let Some(line) = read_line(&mut reader).await {
+        if line.is_empty() {
+            continue;
+        }
+
+        let span = tracing::info_span!("admin_command", command = line.as_str());
+        let result = run_command(context, &line).instrument(span).await;
+
+        let mut answer = String::new();
+        match result {
+            Ok(lines) => {
+                for line in lines {
+                    answer.push_str(&line);
+                    answer.push('\n');
+                }
+                answer.push_str("ok\n");
+            }
+            Err(error) => answer.push_str(&format!("error: {}\n", error)),
+        }
+        if reader.get_mut().write_all(answer.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+// Replaces a socket left behind by a server that didn't stop cleanly
+pub(crate) fn bind_admin(path: &Path) -> std::io::Result<UnixListener> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+// Connections are aborted along with the task, so none of them hold on to the game
+pub(crate) async fn serve_admin(listener: UnixListener, context: SessionContext, token: String) {
+    let mut connections = JoinSet::new();
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                tracing::info!(%error, "admin accept failed");
+                continue;
+            }
+        };
+
+        // Forget connections that have already closed
+        while connections.try_join_next().is_some() {}
+
+        let context = context.clone();
+        let token = token.clone();
+        connections.spawn(
+            async move { handle(stream, &context, &token).await }
+                .instrument(tracing::info_span!("admin")),
+        );
+    }
+}
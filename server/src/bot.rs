@@ -0,0 +1,200 @@
+use connect4000_core::{Coins, Color};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{Actions, SnapshotEncoding, View};
+
+/// Picks the moves of a computer player
+pub trait Bot: Send {
+    /// Chooses the column to drop the bot's next coin in
+    fn choose_column(&mut self, coins: &Coins, color: &Color) -> u64;
+}
+
+/// Drops its coins in any column
+#[derive(Debug, Default)]
+pub struct RandomBot;
+
+impl Bot for RandomBot {
+    fn choose_column(&mut self, coins: &Coins, _color: &Color) -> u64 {
+        rand::thread_rng().gen_range(0..coins.len().max(1) as u64)
+    }
+}
+
+/// Drops its coins where they grow its biggest group, cutting off the biggest group of any other
+/// colour when that's a tie. Columns still tied after that, like beside a group or on top of it,
+/// are chosen between at random.
+#[derive(Debug, Default)]
+pub struct GreedyBot;
+
+impl Bot for GreedyBot {
+    fn choose_column(&mut self, coins: &Coins, color: &Color) -> u64 {
+        let mut best: Vec<u64> = Vec::new();
+        let mut best_score = (0, 0);
+
+        for column in 0..coins.len() as u64 {
+            let grown = group_size(coins, column, color);
+            let blocked = other_colors(coins, column, color)
+                .iter()
+                .map(|other| group_size(coins, column, other))
+                .max()
+                .unwrap_or(0);
+
+            let score = (grown, blocked);
+            if score > best_score {
+                best_score = score;
+                best.clear();
+            }
+            if score == best_score {
+                best.push(column);
+            }
+        }
+
+        best.choose(&mut rand::thread_rng()).copied().unwrap_or(0)
+    }
+}
+
+/// The computer players built into the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotStrategy {
+    Random,
+    Greedy,
+}
+
+impl BotStrategy {
+    pub fn parse(input: &str) -> Option<Self> {
+        match input {
+            "random" => Some(BotStrategy::Random),
+            "greedy" => Some(BotStrategy::Greedy),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn serialize(&self) -> u8 {
+        match self {
+            BotStrategy::Random => 1,
+            BotStrategy::Greedy => 2,
+        }
+    }
+
+    pub(crate) fn deserialize(input: u8) -> Option<Self> {
+        match input {
+            1 => Some(BotStrategy::Random),
+            2 => Some(BotStrategy::Greedy),
+            _ => None,
+        }
+    }
+
+    pub fn bot(&self) -> Box<dyn Bot> {
+        match self {
+            BotStrategy::Random => Box::new(RandomBot),
+            BotStrategy::Greedy => Box::new(GreedyBot),
+        }
+    }
+}
+
+const NEIGHBOURS: [(i64, i64); 8] = [
+    (-1, 0),
+    (1, 0),
+    (0, 1),
+    (0, -1),
+    (-1, 1),
+    (1, 1),
+    (-1, -1),
+    (1, -1),
+];
+
+fn color_at(coins: &Coins, column: i64, row: i64) -> Option<&Color> {
+    if column < 0 || row < 0 {
+        return None;
+    }
+
+    let coin = coins.get(column as usize)?.get(row as usize)?;
+    Some(&coin.color)
+}
+
+/// Colours other than the bot's touching the space a coin dropped in the column would land on
+fn other_colors(coins: &Coins, column: u64, color: &Color) -> Vec<Color> {
+    let row = coins[column as usize].len() as i64;
+
+    let mut colors = Vec::new();
+    for (x, y) in NEIGHBOURS {
+        if let Some(other) = color_at(coins, column as i64 + x, row + y) {
+            if other != color && !colors.contains(other) {
+                colors.push(other.clone());
+            }
+        }
+    }
+
+    colors
+}
+
+/// Size of the group a coin of the colour dropped in the column would end up in, counting coins
+/// touching on any side or corner like the game does
+fn group_size(coins: &Coins, column: u64, color: &Color) -> u64 {
+    let start = (column as i64, coins[column as usize].len() as i64);
+
+    let mut seen = HashSet::from([start]);
+    let mut pending = vec![start];
+
+    while let Some((column, row)) = pending.pop() {
+        for (x, y) in NEIGHBOURS {
+            let next = (column + x, row + y);
+            if seen.contains(&next) || color_at(coins, next.0, next.1) != Some(color) {
+                continue;
+            }
+
+            seen.insert(next);
+            pending.push(next);
+        }
+    }
+
+    seen.len() as u64
+}
+
+/// Plays a bot's coins whenever the game actor hands it the turn, through the same actions a
+/// player's session sends. The bot stops once the actor drops its turn sender.
+pub(crate) fn spawn_bot(
+    tx: mpsc::Sender<Actions>,
+    player_id: u64,
+    color: Color,
+    mut bot: Box<dyn Bot>,
+    move_delay: Duration,
+) -> mpsc::UnboundedSender<()> {
+    let (turn_tx, mut turn_rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        while turn_rx.recv().await.is_some() {
+            tokio::time::sleep(move_delay).await;
+
+            // The game may have stopped while the bot was thinking
+            let (view_tx, view_rx) = oneshot::channel();
+            if tx
+                .send(Actions::Snapshot(SnapshotEncoding::Dense, view_tx))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            let view = match view_rx.await {
+                Ok(view) => view,
+                Err(_) => return,
+            };
+
+            let coins = View::snapshot_coins(&view);
+            if coins.is_none() {
+                tracing::error!(player_id, "bot snapshot unreadable");
+                return;
+            }
+            let column = bot.choose_column(&coins.unwrap(), &color);
+
+            if tx.send(Actions::PlayCoin(column, player_id)).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    turn_tx
+}
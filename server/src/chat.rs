@@ -0,0 +1,114 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// Longest chat message a client can send, in bytes. A longer one closes the stream.
+pub const MAX_CHAT_LEN: u64 = 280;
+/// How far back each player's messages count towards the rate limit
+const CHAT_RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Decides what becomes of a chat message before it's relayed, e.g. to keep out profanity
+pub trait ChatFilter: fmt::Debug + Send + Sync {
+    /// The message to relay in place of the one the player sent, or `None` to drop it
+    fn filter(&self, player_id: u64, message: &str) -> Option<String>;
+}
+
+/// Masks every blocked word with `*`, whatever its case
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    words: HashSet<String>,
+}
+
+impl Blocklist {
+    pub fn new(words: Vec<String>) -> Self {
+        let words = words
+            .into_iter()
+            .map(|word| word.to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+
+        Blocklist { words }
+    }
+
+    fn push_word(&self, filtered: &mut String, word: &mut String) {
+        if self.words.contains(&word.to_lowercase()) {
+            filtered.extend(word.chars().map(|_| '*'));
+        } else {
+            filtered.push_str(word);
+        }
+        word.clear();
+    }
+}
+
+impl ChatFilter for Blocklist {
+    fn filter(&self, _player_id: u64, message: &str) -> Option<String> {
+        let mut filtered = String::with_capacity(message.len());
+        let mut word = String::new();
+
+        // Only whole words are masked, so blocking a word doesn't mangle longer ones containing it
+        for c in message.chars() {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            self.push_word(&mut filtered, &mut word);
+            filtered.push(c);
+        }
+        self.push_word(&mut filtered, &mut word);
+
+        Some(filtered)
+    }
+}
+
+/// Counts each player's recent chat messages, turning away any over the limit
+#[derive(Debug)]
+pub(crate) struct ChatLimiter {
+    /// Messages each player can send within the window, unlimited without one
+    limit: Option<u64>,
+    sent: HashMap<u64, VecDeque<Instant>>,
+}
+
+impl ChatLimiter {
+    pub(crate) fn new(limit: Option<u64>) -> Self {
+        ChatLimiter {
+            limit,
+            sent: HashMap::new(),
+        }
+    }
+
+    /// Counts a message from the player, unless they've already sent as many as they're allowed
+    pub(crate) fn allow(&mut self, player_id: u64, now: Instant) -> bool {
+        if self.limit.is_none() {
+            return true;
+        }
+        let limit = self.limit.unwrap();
+
+        // Forget messages that have dropped out of the window, and players with none left in it
+        for sent in self.sent.values_mut() {
+            while sent
+                .front()
+                .is_some_and(|sent_at| now.duration_since(*sent_at) >= CHAT_RATE_WINDOW)
+            {
+                sent.pop_front();
+            }
+        }
+        self.sent.retain(|_, sent| !sent.is_empty());
+
+        let sent = self.sent.entry(player_id).or_default();
+        if sent.len() as u64 >= limit {
+            return false;
+        }
+        sent.push_back(now);
+
+        true
+    }
+}
+
+/// Milliseconds since the unix epoch, as chat messages are stamped with
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::{ClockViewData, View};
+
+/// What happens to a player who runs out of time on their turn
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPenalty {
+    /// The turn passes to the next player
+    #[default]
+    SkipTurn,
+    /// A coin is dropped in a random column for them
+    RandomMove,
+    /// The player loses their seat, and the last player left seated wins
+    Forfeit,
+}
+
+impl TimeoutPenalty {
+    pub(crate) fn parse(input: &str) -> Option<Self> {
+        match input {
+            "skip-turn" => Some(TimeoutPenalty::SkipTurn),
+            "random-move" => Some(TimeoutPenalty::RandomMove),
+            "forfeit" => Some(TimeoutPenalty::Forfeit),
+            _ => None,
+        }
+    }
+}
+
+/// A game's time controls, a limit per move, a chess style clock per player, or both
+#[derive(Debug)]
+pub(crate) struct TurnClock {
+    move_timeout: Option<Duration>,
+    clock: Option<Duration>,
+    increment: Duration,
+    remaining: HashMap<u64, Duration>,
+    /// The player on turn and when their turn started
+    turn: Option<(u64, Instant)>,
+}
+
+impl TurnClock {
+    pub(crate) fn new(
+        move_timeout: Option<Duration>,
+        clock: Option<Duration>,
+        increment: Duration,
+    ) -> Self {
+        TurnClock {
+            move_timeout,
+            clock,
+            increment,
+            remaining: HashMap::new(),
+            turn: None,
+        }
+    }
+
+    pub(crate) fn is_timed(&self) -> bool {
+        self.move_timeout.is_some() || self.clock.is_some()
+    }
+
+    /// Runs the clock of whoever is on turn, stopping anyone else's. Returns true if the turn changed.
+    pub(crate) fn sync(&mut self, on_turn: Option<u64>, now: Instant) -> bool {
+        if self.turn.map(|(player_id, _)| player_id) == on_turn {
+            return false;
+        }
+
+        self.stop(now, Duration::ZERO);
+        self.turn = on_turn.map(|player_id| (player_id, now));
+
+        true
+    }
+
+    /// Stops the clock of the player on turn after they've moved, crediting the increment
+    pub(crate) fn moved(&mut self, now: Instant) {
+        self.stop(now, self.increment);
+    }
+
+    /// Stops the clock of the player on turn, charging them for the time they took
+    pub(crate) fn stop(&mut self, now: Instant, credit: Duration) {
+        let turn = self.turn.take();
+        if turn.is_none() || self.clock.is_none() {
+            return;
+        }
+        let (player_id, started) = turn.unwrap();
+
+        let remaining = self
+            .remaining
+            .entry(player_id)
+            .or_insert(self.clock.unwrap());
+        *remaining = remaining.saturating_sub(now - started) + credit;
+    }
+
+    /// Winds every player's clock back to the start, for a new game
+    pub(crate) fn reset(&mut self) {
+        self.remaining.clear();
+        self.turn = None;
+    }
+
+    /// Whether the player has used up all the time on their clock, leaving no time for any turn
+    pub(crate) fn exhausted(&self, player_id: u64) -> bool {
+        self.clock_remaining(player_id) == Some(Duration::ZERO)
+    }
+
+    fn clock_remaining(&self, player_id: u64) -> Option<Duration> {
+        let clock = self.clock?;
+        Some(*self.remaining.get(&player_id).unwrap_or(&clock))
+    }
+
+    /// The player on turn and when their time runs out, if their turn is timed
+    pub(crate) fn deadline(&self) -> Option<(u64, Instant)> {
+        let (player_id, started) = self.turn?;
+
+        let allowed = match (self.move_timeout, self.clock_remaining(player_id)) {
+            (Some(move_timeout), Some(clock)) => move_timeout.min(clock),
+            (move_timeout, clock) => move_timeout.or(clock)?,
+        };
+
+        Some((player_id, started + allowed))
+    }
+
+    /// A `Clock` view of the time left for the player on turn, and on every seated player's clock
+    pub(crate) fn view(&self, seated: &[u64], now: Instant) -> Vec<u8> {
+        let turn_remaining = match self.deadline() {
+            Some((_, deadline)) => deadline.saturating_duration_since(now),
+            None => Duration::ZERO,
+        };
+
+        // Only the player on turn has a clock that's running
+        let clocks = match self.clock {
+            Some(_) => seated
+                .iter()
+                .map(|player_id| {
+                    let mut remaining = self.clock_remaining(*player_id).unwrap();
+                    if let Some((on_turn, started)) = self.turn {
+                        if on_turn == *player_id {
+                            remaining = remaining.saturating_sub(now - started);
+                        }
+                    }
+                    (*player_id, remaining)
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        View::serialize(View::Clock(ClockViewData {
+            on_turn: self.turn.map(|(player_id, _)| player_id),
+            turn_remaining,
+            clocks,
+        }))
+    }
+}
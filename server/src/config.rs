@@ -0,0 +1,633 @@
+use connect4000_core::Color;
+use serde::Deserialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    Blocklist, BotStrategy, ChatFilter, FsyncPolicy, OverflowPolicy, RateLimit, TimeoutPenalty,
+};
+
+/// What happens to a player's seat in the turn order once their connection goes away
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LeavePolicy {
+    /// The player is removed from the turn order and play continues with the others
+    #[default]
+    SkipTurn,
+    /// The player is removed from the turn order, and the last player left seated wins
+    Forfeit,
+    /// The player keeps their seat and the game waits for them on their turn
+    HoldSeat,
+}
+
+impl LeavePolicy {
+    fn parse(input: &str) -> Option<Self> {
+        match input {
+            "skip-turn" => Some(LeavePolicy::SkipTurn),
+            "forfeit" => Some(LeavePolicy::Forfeit),
+            "hold-seat" => Some(LeavePolicy::HoldSeat),
+            _ => None,
+        }
+    }
+}
+
+/// A colour by its lowercase name, as configs and clients spell them
+pub fn parse_color(input: &str) -> Option<Color> {
+    match input {
+        "orange" => Some(Color::Orange),
+        "blue" => Some(Color::Blue),
+        "red" => Some(Color::Red),
+        "yellow" => Some(Color::Yellow),
+        "purple" => Some(Color::Purple),
+        _ => None,
+    }
+}
+
+/// Where the server's TLS identity comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdentityConfig {
+    /// A certificate and key loaded from PEM files, as made by `scripts/generate-tls.sh`
+    PemFiles {
+        cert_path: PathBuf,
+        key_path: PathBuf,
+    },
+    /// A short lived certificate generated in memory on start, trusted by clients pinning its hash
+    SelfSigned { subject_alt_names: Vec<String> },
+}
+
+impl Default for IdentityConfig {
+    fn default() -> Self {
+        IdentityConfig::SelfSigned {
+            subject_alt_names: vec![
+                "localhost".to_string(),
+                "127.0.0.1".to_string(),
+                "::1".to_string(),
+            ],
+        }
+    }
+}
+
+/// Everything needed to run a server, built in code or loaded from a TOML file.
+///
+/// Limits left as `None` are unlimited.
+#[derive(Clone)]
+pub struct ServerConfig {
+    pub bind_address: SocketAddr,
+    /// Tcp address WebSocket clients connect to, WebSocket is turned off without one
+    pub websocket_bind_address: Option<SocketAddr>,
+    /// Address metrics, health and readiness are served on over plain HTTP, none to turn them off
+    pub metrics_bind_address: Option<SocketAddr>,
+    pub identity: IdentityConfig,
+    /// File the certificate hash is written to on start, as hex
+    pub cert_hash_path: Option<PathBuf>,
+    /// Number of columns on the board
+    pub columns: u64,
+    /// How many connected coins win, defaults to the number of columns
+    pub win_size: Option<u64>,
+    /// Players that can be seated in the turn order of a game
+    pub seats: Option<u64>,
+    /// Players that can be connected to the server at once, across every game
+    pub max_players: Option<u64>,
+    /// Colours handed out to players as they join, in order
+    pub colors: Vec<Color>,
+    pub keep_alive_interval: Duration,
+    /// Connections that stay silent for this long are closed
+    pub idle_timeout: Duration,
+    /// Sessions, players and spectators alike, that can be open at once
+    pub max_connections: Option<u64>,
+    /// Sessions that can be open at once from the same IP address
+    pub max_sessions_per_ip: Option<u64>,
+    /// Commands each connection can send
+    pub connection_rate_limit: Option<RateLimit>,
+    /// Commands every connection from the same IP address can send between them
+    pub ip_rate_limit: Option<RateLimit>,
+    /// Largest WebSocket frame a client can send, in bytes
+    pub max_frame_size: usize,
+    /// Capacity of the game action and broadcast channels
+    pub channel_size: usize,
+    /// Views that can wait to be written to each client before the overflow policy kicks in
+    pub outbound_queue_size: usize,
+    pub overflow_policy: OverflowPolicy,
+    pub leave_policy: LeavePolicy,
+    pub resume_grace_period: Duration,
+    /// Time a player has to make each move
+    pub move_timeout: Option<Duration>,
+    /// Time each player has for all of their moves in a game, chess style
+    pub clock: Option<Duration>,
+    /// Time added to a player's clock after each move they make
+    pub clock_increment: Duration,
+    pub timeout_penalty: TimeoutPenalty,
+    /// Directory each game's journal is kept in, games only live in memory without one
+    pub journal_dir: Option<PathBuf>,
+    pub journal_fsync: FsyncPolicy,
+    /// File matchmaking ratings are kept in, ratings only live in memory without one
+    pub ratings_path: Option<PathBuf>,
+    /// Widest board a player can ask to be matched on
+    pub max_match_columns: u64,
+    /// Computer player seated opposite a player left without an opponent, none without one
+    pub bot: Option<BotStrategy>,
+    /// Time a bot waits before dropping each coin
+    pub bot_move_delay: Duration,
+    /// How long shutdown waits for sessions to close before aborting them
+    pub shutdown_timeout: Duration,
+    /// Unix socket operators control the server through, turned off without one
+    pub admin_socket_path: Option<PathBuf>,
+    /// Secret an admin connection has to send before anything else, needed with a socket
+    pub admin_token: Option<String>,
+    /// Chat messages each player can send a minute
+    pub chat_rate_limit: Option<u64>,
+    /// Looks over every chat message before it's relayed, a `Blocklist` when loaded from a file
+    pub chat_filter: Option<Arc<dyn ChatFilter>>,
+    /// File holding the secret player credentials are signed with, created on first start.
+    /// Credentials only last until the server stops without one
+    pub credential_secret_path: Option<PathBuf>,
+    /// Only players who identify themselves can join, spectators needn't
+    pub require_credentials: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], 4001)),
+            websocket_bind_address: Some(SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], 4002))),
+            metrics_bind_address: Some(SocketAddr::from(([127, 0, 0, 1], 4003))),
+            identity: IdentityConfig::default(),
+            cert_hash_path: None,
+            columns: 4,
+            win_size: None,
+            seats: None,
+            max_players: None,
+            colors: vec![
+                Color::Orange,
+                Color::Blue,
+                Color::Red,
+                Color::Yellow,
+                Color::Purple,
+            ],
+            keep_alive_interval: Duration::from_secs(3),
+            idle_timeout: Duration::from_secs(30),
+            max_connections: None,
+            max_sessions_per_ip: None,
+            connection_rate_limit: None,
+            ip_rate_limit: None,
+            max_frame_size: 16 * 1024,
+            channel_size: u8::MAX as usize,
+            outbound_queue_size: 64,
+            overflow_policy: OverflowPolicy::default(),
+            leave_policy: LeavePolicy::default(),
+            resume_grace_period: Duration::from_secs(30),
+            move_timeout: None,
+            clock: None,
+            clock_increment: Duration::ZERO,
+            timeout_penalty: TimeoutPenalty::default(),
+            journal_dir: None,
+            journal_fsync: FsyncPolicy::default(),
+            ratings_path: None,
+            max_match_columns: 64,
+            bot: None,
+            bot_move_delay: Duration::from_secs(1),
+            shutdown_timeout: Duration::from_secs(5),
+            admin_socket_path: None,
+            admin_token: None,
+            chat_rate_limit: Some(10),
+            chat_filter: None,
+            credential_secret_path: None,
+            require_credentials: false,
+        }
+    }
+}
+
+// Written out so the admin token never ends up in logs
+impl fmt::Debug for ServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ServerConfig")
+            .field("bind_address", &self.bind_address)
+            .field("websocket_bind_address", &self.websocket_bind_address)
+            .field("metrics_bind_address", &self.metrics_bind_address)
+            .field("identity", &self.identity)
+            .field("cert_hash_path", &self.cert_hash_path)
+            .field("columns", &self.columns)
+            .field("win_size", &self.win_size)
+            .field("seats", &self.seats)
+            .field("max_players", &self.max_players)
+            .field("colors", &self.colors)
+            .field("keep_alive_interval", &self.keep_alive_interval)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("max_connections", &self.max_connections)
+            .field("max_sessions_per_ip", &self.max_sessions_per_ip)
+            .field("connection_rate_limit", &self.connection_rate_limit)
+            .field("ip_rate_limit", &self.ip_rate_limit)
+            .field("max_frame_size", &self.max_frame_size)
+            .field("channel_size", &self.channel_size)
+            .field("outbound_queue_size", &self.outbound_queue_size)
+            .field("overflow_policy", &self.overflow_policy)
+            .field("leave_policy", &self.leave_policy)
+            .field("resume_grace_period", &self.resume_grace_period)
+            .field("move_timeout", &self.move_timeout)
+            .field("clock", &self.clock)
+            .field("clock_increment", &self.clock_increment)
+            .field("timeout_penalty", &self.timeout_penalty)
+            .field("journal_dir", &self.journal_dir)
+            .field("journal_fsync", &self.journal_fsync)
+            .field("ratings_path", &self.ratings_path)
+            .field("max_match_columns", &self.max_match_columns)
+            .field("bot", &self.bot)
+            .field("bot_move_delay", &self.bot_move_delay)
+            .field("shutdown_timeout", &self.shutdown_timeout)
+            .field("admin_socket_path", &self.admin_socket_path)
+            .field(
+                "admin_token",
+                &self.admin_token.as_ref().map(|_| "<redacted>"),
+            )
+            .field("chat_rate_limit", &self.chat_rate_limit)
+            .field("chat_filter", &self.chat_filter)
+            .field("credential_secret_path", &self.credential_secret_path)
+            .field("require_credentials", &self.require_credentials)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(std::io::Error),
+    Parse(toml::de::Error),
+    InvalidBindAddress(String),
+    UnknownColor(String),
+    UnknownLeavePolicy(String),
+    UnknownFsyncPolicy(String),
+    UnknownOverflowPolicy(String),
+    UnknownTimeoutPenalty(String),
+    UnknownBotStrategy(String),
+    IncompleteIdentity,
+    ConflictingIdentity,
+    NoSubjectAltNames,
+    NoColumns,
+    NoWinSize,
+    NoSeats,
+    NoPlayers,
+    NoColors,
+    DuplicateColor(Color),
+    NoConnections,
+    NoSessionsPerIp,
+    NoRateLimit,
+    NoFrameSize,
+    NoChannelSize,
+    NoOutboundQueueSize,
+    KeepAliveNotBelowIdleTimeout,
+    NoFsyncRecords,
+    NoMoveTimeout,
+    NoClock,
+    IncrementWithoutClock,
+    NoMatchColumns,
+    AdminWithoutToken,
+    NoChatRateLimit,
+    InvalidIdleTimeout,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(error) => write!(f, "failed to read config: {}", error),
+            ConfigError::Parse(error) => write!(f, "failed to parse config: {}", error),
+            ConfigError::InvalidBindAddress(input) => write!(f, "invalid bind address: {}", input),
+            ConfigError::UnknownColor(input) => write!(f, "unknown colour: {}", input),
+            ConfigError::UnknownLeavePolicy(input) => write!(f, "unknown leave policy: {}", input),
+            ConfigError::UnknownFsyncPolicy(input) => write!(f, "unknown fsync policy: {}", input),
+            ConfigError::UnknownOverflowPolicy(input) => {
+                write!(f, "unknown overflow policy: {}", input)
+            }
+            ConfigError::UnknownTimeoutPenalty(input) => {
+                write!(f, "unknown timeout penalty: {}", input)
+            }
+            ConfigError::UnknownBotStrategy(input) => write!(f, "unknown bot strategy: {}", input),
+            ConfigError::IncompleteIdentity => {
+                write!(f, "cert_path and key_path must be set together")
+            }
+            ConfigError::ConflictingIdentity => {
+                write!(f, "pem files and a self signed identity can't both be set")
+            }
+            ConfigError::NoSubjectAltNames => {
+                write!(f, "a self signed identity needs at least one name")
+            }
+            ConfigError::NoColumns => write!(f, "the board needs at least one column"),
+            ConfigError::NoWinSize => write!(f, "the win size must be at least one coin"),
+            ConfigError::NoSeats => write!(f, "a game needs at least one seat"),
+            ConfigError::NoPlayers => write!(f, "the server needs room for at least one player"),
+            ConfigError::NoColors => write!(f, "at least one colour must be allowed"),
+            ConfigError::DuplicateColor(color) => write!(f, "colour allowed twice: {:?}", color),
+            ConfigError::NoConnections => write!(f, "the server needs room for a connection"),
+            ConfigError::NoSessionsPerIp => {
+                write!(f, "each address needs room for at least one session")
+            }
+            ConfigError::NoRateLimit => {
+                write!(f, "rate limits must allow at least one command")
+            }
+            ConfigError::NoFrameSize => write!(f, "max frame size must be at least one byte"),
+            ConfigError::NoChannelSize => write!(f, "channel size must be at least one"),
+            ConfigError::NoOutboundQueueSize => {
+                write!(f, "outbound queue size must be at least one")
+            }
+            ConfigError::KeepAliveNotBelowIdleTimeout => {
+                write!(
+                    f,
+                    "keep alive interval must be shorter than the idle timeout"
+                )
+            }
+            ConfigError::NoFsyncRecords => write!(f, "fsync must happen every one or more records"),
+            ConfigError::NoMoveTimeout => write!(f, "move timeout must be longer than zero"),
+            ConfigError::NoClock => write!(f, "clock must be longer than zero"),
+            ConfigError::IncrementWithoutClock => {
+                write!(f, "a clock increment needs a clock to add to")
+            }
+            ConfigError::NoMatchColumns => {
+                write!(f, "matched games need at least one column")
+            }
+            ConfigError::AdminWithoutToken => {
+                write!(f, "the admin socket needs an admin token")
+            }
+            ConfigError::NoChatRateLimit => {
+                write!(f, "chat rate limit must allow at least one message")
+            }
+            ConfigError::InvalidIdleTimeout => write!(f, "idle timeout is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The TOML file layout, every field falls back to `ServerConfig::default`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    bind_address: Option<String>,
+    /// An address, or "off" to only serve WebTransport
+    websocket_bind_address: Option<String>,
+    /// An address, or "off" to not serve metrics
+    metrics_bind_address: Option<String>,
+    cert_path: Option<PathBuf>,
+    key_path: Option<PathBuf>,
+    self_signed_names: Option<Vec<String>>,
+    cert_hash_path: Option<PathBuf>,
+    columns: Option<u64>,
+    win_size: Option<u64>,
+    seats: Option<u64>,
+    max_players: Option<u64>,
+    colors: Option<Vec<String>>,
+    keep_alive_interval_secs: Option<u64>,
+    idle_timeout_secs: Option<u64>,
+    max_connections: Option<u64>,
+    max_sessions_per_ip: Option<u64>,
+    /// A table of `per_sec` and an optional `burst`
+    connection_rate_limit: Option<RateLimit>,
+    ip_rate_limit: Option<RateLimit>,
+    max_frame_size: Option<usize>,
+    channel_size: Option<usize>,
+    outbound_queue_size: Option<usize>,
+    overflow_policy: Option<String>,
+    leave_policy: Option<String>,
+    resume_grace_period_secs: Option<u64>,
+    move_timeout_secs: Option<u64>,
+    clock_secs: Option<u64>,
+    clock_increment_secs: Option<u64>,
+    timeout_penalty: Option<String>,
+    journal_dir: Option<PathBuf>,
+    journal_fsync: Option<String>,
+    ratings_path: Option<PathBuf>,
+    max_match_columns: Option<u64>,
+    /// A strategy, or "off" to leave empty seats empty
+    bot: Option<String>,
+    bot_move_delay_secs: Option<u64>,
+    shutdown_timeout_secs: Option<u64>,
+    admin_socket_path: Option<PathBuf>,
+    admin_token: Option<String>,
+    chat_rate_limit: Option<u64>,
+    /// Words masked out of chat messages
+    chat_blocklist: Option<Vec<String>>,
+    credential_secret_path: Option<PathBuf>,
+    require_credentials: Option<bool>,
+}
+
+impl ServerConfig {
+    /// Reads and validates a TOML config file
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let input = std::fs::read_to_string(path).map_err(ConfigError::Read)?;
+        Self::from_toml(&input)
+    }
+
+    /// Parses and validates a TOML config, missing keys take their default value
+    pub fn from_toml(input: &str) -> Result<Self, ConfigError> {
+        let file: ConfigFile = toml::from_str(input).map_err(ConfigError::Parse)?;
+        let mut config = ServerConfig::default();
+
+        if let Some(bind_address) = file.bind_address {
+            config.bind_address = bind_address
+                .parse()
+                .map_err(|_| ConfigError::InvalidBindAddress(bind_address))?;
+        }
+        if let Some(websocket_bind_address) = file.websocket_bind_address {
+            config.websocket_bind_address = match websocket_bind_address.as_str() {
+                "off" => None,
+                _ => Some(
+                    websocket_bind_address
+                        .parse()
+                        .map_err(|_| ConfigError::InvalidBindAddress(websocket_bind_address))?,
+                ),
+            };
+        }
+        if let Some(metrics_bind_address) = file.metrics_bind_address {
+            config.metrics_bind_address = match metrics_bind_address.as_str() {
+                "off" => None,
+                _ => Some(
+                    metrics_bind_address
+                        .parse()
+                        .map_err(|_| ConfigError::InvalidBindAddress(metrics_bind_address))?,
+                ),
+            };
+        }
+        if let Some(colors) = file.colors {
+            config.colors = colors
+                .into_iter()
+                .map(|color| parse_color(&color).ok_or(ConfigError::UnknownColor(color)))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(leave_policy) = file.leave_policy {
+            config.leave_policy = LeavePolicy::parse(&leave_policy)
+                .ok_or(ConfigError::UnknownLeavePolicy(leave_policy))?;
+        }
+
+        match (file.cert_path, file.key_path, file.self_signed_names) {
+            (Some(cert_path), Some(key_path), None) => {
+                config.identity = IdentityConfig::PemFiles {
+                    cert_path,
+                    key_path,
+                };
+            }
+            (None, None, Some(subject_alt_names)) => {
+                config.identity = IdentityConfig::SelfSigned { subject_alt_names };
+            }
+            (None, None, None) => {}
+            (Some(_), Some(_), Some(_)) => return Err(ConfigError::ConflictingIdentity),
+            _ => return Err(ConfigError::IncompleteIdentity),
+        }
+
+        if let Some(overflow_policy) = file.overflow_policy {
+            config.overflow_policy = OverflowPolicy::parse(&overflow_policy)
+                .ok_or(ConfigError::UnknownOverflowPolicy(overflow_policy))?;
+        }
+        if let Some(timeout_penalty) = file.timeout_penalty {
+            config.timeout_penalty = TimeoutPenalty::parse(&timeout_penalty)
+                .ok_or(ConfigError::UnknownTimeoutPenalty(timeout_penalty))?;
+        }
+        if let Some(bot) = file.bot {
+            config.bot = match bot.as_str() {
+                "off" => None,
+                _ => Some(BotStrategy::parse(&bot).ok_or(ConfigError::UnknownBotStrategy(bot))?),
+            };
+        }
+        if let Some(journal_fsync) = file.journal_fsync {
+            config.journal_fsync = FsyncPolicy::parse(&journal_fsync)
+                .ok_or(ConfigError::UnknownFsyncPolicy(journal_fsync))?;
+        }
+
+        config.cert_hash_path = file.cert_hash_path.or(config.cert_hash_path);
+        config.journal_dir = file.journal_dir.or(config.journal_dir);
+        config.ratings_path = file.ratings_path.or(config.ratings_path);
+        config.admin_socket_path = file.admin_socket_path.or(config.admin_socket_path);
+        config.admin_token = file.admin_token.or(config.admin_token);
+        config.chat_rate_limit = file.chat_rate_limit.or(config.chat_rate_limit);
+        config.credential_secret_path = file
+            .credential_secret_path
+            .or(config.credential_secret_path);
+        config.require_credentials = file
+            .require_credentials
+            .unwrap_or(config.require_credentials);
+        if let Some(chat_blocklist) = file.chat_blocklist {
+            config.chat_filter = Some(Arc::new(Blocklist::new(chat_blocklist)));
+        }
+        config.max_match_columns = file.max_match_columns.unwrap_or(config.max_match_columns);
+        config.columns = file.columns.unwrap_or(config.columns);
+        config.win_size = file.win_size.or(config.win_size);
+        config.seats = file.seats.or(config.seats);
+        config.max_players = file.max_players.or(config.max_players);
+        config.max_connections = file.max_connections.or(config.max_connections);
+        config.max_sessions_per_ip = file.max_sessions_per_ip.or(config.max_sessions_per_ip);
+        config.connection_rate_limit = file.connection_rate_limit.or(config.connection_rate_limit);
+        config.ip_rate_limit = file.ip_rate_limit.or(config.ip_rate_limit);
+        config.max_frame_size = file.max_frame_size.unwrap_or(config.max_frame_size);
+        config.channel_size = file.channel_size.unwrap_or(config.channel_size);
+        config.outbound_queue_size = file
+            .outbound_queue_size
+            .unwrap_or(config.outbound_queue_size);
+
+        if let Some(secs) = file.keep_alive_interval_secs {
+            config.keep_alive_interval = Duration::from_secs(secs);
+        }
+        if let Some(secs) = file.idle_timeout_secs {
+            config.idle_timeout = Duration::from_secs(secs);
+        }
+        if let Some(secs) = file.resume_grace_period_secs {
+            config.resume_grace_period = Duration::from_secs(secs);
+        }
+        if let Some(secs) = file.move_timeout_secs {
+            config.move_timeout = Some(Duration::from_secs(secs));
+        }
+        if let Some(secs) = file.clock_secs {
+            config.clock = Some(Duration::from_secs(secs));
+        }
+        if let Some(secs) = file.clock_increment_secs {
+            config.clock_increment = Duration::from_secs(secs);
+        }
+        if let Some(secs) = file.bot_move_delay_secs {
+            config.bot_move_delay = Duration::from_secs(secs);
+        }
+        if let Some(secs) = file.shutdown_timeout_secs {
+            config.shutdown_timeout = Duration::from_secs(secs);
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Checks the config describes a game that can be played, `start_server` refuses anything else
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let IdentityConfig::SelfSigned { subject_alt_names } = &self.identity {
+            if subject_alt_names.is_empty() {
+                return Err(ConfigError::NoSubjectAltNames);
+            }
+        }
+        if self.columns == 0 {
+            return Err(ConfigError::NoColumns);
+        }
+        if self.win_size == Some(0) {
+            return Err(ConfigError::NoWinSize);
+        }
+        if self.seats == Some(0) {
+            return Err(ConfigError::NoSeats);
+        }
+        if self.max_players == Some(0) {
+            return Err(ConfigError::NoPlayers);
+        }
+        if self.max_connections == Some(0) {
+            return Err(ConfigError::NoConnections);
+        }
+        if self.max_sessions_per_ip == Some(0) {
+            return Err(ConfigError::NoSessionsPerIp);
+        }
+        for limit in [self.connection_rate_limit, self.ip_rate_limit]
+            .iter()
+            .flatten()
+        {
+            if limit.per_sec == 0 || limit.burst == Some(0) {
+                return Err(ConfigError::NoRateLimit);
+            }
+        }
+        if self.max_frame_size == 0 {
+            return Err(ConfigError::NoFrameSize);
+        }
+        if self.channel_size == 0 {
+            return Err(ConfigError::NoChannelSize);
+        }
+        if self.outbound_queue_size == 0 {
+            return Err(ConfigError::NoOutboundQueueSize);
+        }
+        if self.colors.is_empty() {
+            return Err(ConfigError::NoColors);
+        }
+        for (index, color) in self.colors.iter().enumerate() {
+            if self.colors[..index].contains(color) {
+                return Err(ConfigError::DuplicateColor(color.clone()));
+            }
+        }
+        if self.keep_alive_interval >= self.idle_timeout {
+            return Err(ConfigError::KeepAliveNotBelowIdleTimeout);
+        }
+        if self.journal_fsync == FsyncPolicy::EveryRecords(0) {
+            return Err(ConfigError::NoFsyncRecords);
+        }
+        if self.move_timeout == Some(Duration::ZERO) {
+            return Err(ConfigError::NoMoveTimeout);
+        }
+        if self.clock == Some(Duration::ZERO) {
+            return Err(ConfigError::NoClock);
+        }
+        if self.clock.is_none() && !self.clock_increment.is_zero() {
+            return Err(ConfigError::IncrementWithoutClock);
+        }
+        if self.max_match_columns == 0 {
+            return Err(ConfigError::NoMatchColumns);
+        }
+        if self.admin_socket_path.is_some()
+            && self.admin_token.as_ref().is_none_or(String::is_empty)
+        {
+            return Err(ConfigError::AdminWithoutToken);
+        }
+        if self.chat_rate_limit == Some(0) {
+            return Err(ConfigError::NoChatRateLimit);
+        }
+
+        Ok(())
+    }
+}
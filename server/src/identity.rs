@@ -0,0 +1,101 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use crate::PlayerIdentity;
+
+/// Longest display name a client can pick, in bytes. A longer one closes the stream.
+pub const MAX_NAME_LEN: u64 = 32;
+
+/// Proof of a player's identity, issued and signed by the server for the client to keep. The
+/// identity followed by its HMAC-SHA256 under the server's secret.
+pub type Credential = [u8; 48];
+
+/// Who a player says they are, and the identity they've proven with a credential, if any
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Profile {
+    pub(crate) name: String,
+    pub(crate) identity: Option<PlayerIdentity>,
+}
+
+impl Profile {
+    /// A name no other player in the game goes by, whatever its case. Players without one, or
+    /// who pick one already taken, are told apart by their id.
+    pub(crate) fn unique_name(&self, player_id: u64, profiles: &HashMap<u64, Profile>) -> String {
+        if self.name.is_empty() {
+            return format!("Player {}", player_id);
+        }
+
+        let taken = profiles
+            .iter()
+            .any(|(id, profile)| *id != player_id && profile.name.eq_ignore_ascii_case(&self.name));
+        if taken {
+            format!("{}#{}", self.name, player_id)
+        } else {
+            self.name.clone()
+        }
+    }
+}
+
+/// Issues and checks credentials with the server's secret
+#[derive(Debug)]
+pub(crate) struct Credentials {
+    secret: [u8; 32],
+}
+
+impl Credentials {
+    /// Reads the secret from a file, creating it on first start so credentials outlive restarts.
+    /// Without a file, credentials are only good until the server stops.
+    pub(crate) fn load(path: Option<&Path>) -> std::io::Result<Self> {
+        if path.is_none() {
+            return Ok(Credentials {
+                secret: rand::random(),
+            });
+        }
+        let path = path.unwrap();
+
+        if !path.exists() {
+            let secret: [u8; 32] = rand::random();
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)?;
+            file.write_all(&secret)?;
+            file.sync_all()?;
+        }
+
+        let secret = std::fs::read(path)?;
+        let secret = secret.try_into().map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "credential secret must be 32 bytes",
+            )
+        })?;
+
+        Ok(Credentials { secret })
+    }
+
+    fn mac(&self, identity: &PlayerIdentity) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(identity);
+        mac
+    }
+
+    pub(crate) fn issue(&self, identity: PlayerIdentity) -> Credential {
+        let mut credential = [0; 48];
+        credential[..16].copy_from_slice(&identity);
+        credential[16..].copy_from_slice(&self.mac(&identity).finalize().into_bytes());
+        credential
+    }
+
+    /// The identity a credential proves, `None` if this server didn't sign it
+    pub(crate) fn verify(&self, credential: &Credential) -> Option<PlayerIdentity> {
+        let identity: PlayerIdentity = credential[..16].try_into().unwrap();
+        self.mac(&identity).verify_slice(&credential[16..]).ok()?;
+        Some(identity)
+    }
+}
@@ -0,0 +1,386 @@
+use connect4000_core::Color;
+use flate2::Crc;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::{BotStrategy, PlayerIdentity, ResumeToken, MAX_NAME_LEN};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    #[default]
+    Always,
+    EveryRecords(u64),
+    Never,
+}
+
+impl FsyncPolicy {
+    pub(crate) fn parse(input: &str) -> Option<Self> {
+        match input {
+            "always" => Some(FsyncPolicy::Always),
+            "never" => Some(FsyncPolicy::Never),
+            _ => {
+                let records = input.strip_prefix("every-")?.parse().ok()?;
+                Some(FsyncPolicy::EveryRecords(records))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    // Always the first record
+    Create {
+        columns: u64,
+        win_size: Option<u64>,
+    },
+    Join {
+        player_id: u64,
+        color: Color,
+        token: ResumeToken,
+    },
+    PlayCoin {
+        player_id: u64,
+        column: u64,
+    },
+    Leave {
+        player_id: u64,
+        forfeit: bool,
+    },
+    Skip {
+        player_id: u64,
+    },
+    Forfeit {
+        player_id: u64,
+    },
+    Rematch,
+    Reset,
+    Resize {
+        columns: u64,
+    },
+    Color {
+        player_id: u64,
+        color: Color,
+    },
+    Profile {
+        player_id: u64,
+        identity: Option<PlayerIdentity>,
+        name: String,
+    },
+    // Bots are spawned again on replay rather than waiting to be resumed
+    Bot {
+        player_id: u64,
+        color: Color,
+        strategy: BotStrategy,
+    },
+}
+
+impl Record {
+    fn serialize(&self) -> Vec<u8> {
+        match self {
+            Record::Create { columns, win_size } => {
+                let mut buffer = vec![0];
+                buffer.extend_from_slice(&columns.to_be_bytes());
+                buffer.extend_from_slice(&win_size.unwrap_or(0).to_be_bytes());
+                buffer
+            }
+            Record::Join {
+                player_id,
+                color,
+                token,
+            } => {
+                let mut buffer = vec![1];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer.push(color.serialize());
+                buffer.extend_from_slice(token);
+                buffer
+            }
+            Record::PlayCoin { player_id, column } => {
+                let mut buffer = vec![2];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer.extend_from_slice(&column.to_be_bytes());
+                buffer
+            }
+            Record::Leave { player_id, forfeit } => {
+                let mut buffer = vec![3];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer.push(*forfeit as u8);
+                buffer
+            }
+            Record::Skip { player_id } => {
+                let mut buffer = vec![4];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer
+            }
+            Record::Forfeit { player_id } => {
+                let mut buffer = vec![5];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer
+            }
+            Record::Rematch => vec![6],
+            Record::Reset => vec![7],
+            Record::Resize { columns } => {
+                let mut buffer = vec![8];
+                buffer.extend_from_slice(&columns.to_be_bytes());
+                buffer
+            }
+            Record::Color { player_id, color } => {
+                let mut buffer = vec![10];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer.push(color.serialize());
+                buffer
+            }
+            Record::Profile {
+                player_id,
+                identity,
+                name,
+            } => {
+                let mut buffer = vec![9];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer.push(identity.is_some() as u8);
+                buffer.extend_from_slice(&identity.unwrap_or_default());
+                buffer.extend_from_slice(name.as_bytes());
+                buffer
+            }
+            Record::Bot {
+                player_id,
+                color,
+                strategy,
+            } => {
+                let mut buffer = vec![11];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer.push(color.serialize());
+                buffer.push(strategy.serialize());
+                buffer
+            }
+        }
+    }
+
+    fn deserialize(payload: &[u8]) -> Option<Self> {
+        let read_u64 = |offset: usize| -> Option<u64> {
+            Some(u64::from_be_bytes(
+                payload.get(offset..offset + 8)?.try_into().unwrap(),
+            ))
+        };
+
+        let record = match payload.first()? {
+            0 if payload.len() == 17 => Record::Create {
+                columns: read_u64(1)?,
+                win_size: Some(read_u64(9)?).filter(|win_size| *win_size != 0),
+            },
+            1 if payload.len() == 26 => Record::Join {
+                player_id: read_u64(1)?,
+                color: match payload[9] {
+                    color @ 1..=5 => Color::deserialize(&color),
+                    _ => return None,
+                },
+                token: payload[10..26].try_into().unwrap(),
+            },
+            2 if payload.len() == 17 => Record::PlayCoin {
+                player_id: read_u64(1)?,
+                column: read_u64(9)?,
+            },
+            3 if payload.len() == 10 => Record::Leave {
+                player_id: read_u64(1)?,
+                forfeit: payload[9] != 0,
+            },
+            4 if payload.len() == 9 => Record::Skip {
+                player_id: read_u64(1)?,
+            },
+            5 if payload.len() == 9 => Record::Forfeit {
+                player_id: read_u64(1)?,
+            },
+            6 if payload.len() == 1 => Record::Rematch,
+            7 if payload.len() == 1 => Record::Reset,
+            8 if payload.len() == 9 => Record::Resize {
+                columns: read_u64(1)?,
+            },
+            9 if payload.len() >= 26 => Record::Profile {
+                player_id: read_u64(1)?,
+                identity: match payload[9] {
+                    0 => None,
+                    _ => Some(payload[10..26].try_into().unwrap()),
+                },
+                name: String::from_utf8(payload[26..].to_vec()).ok()?,
+            },
+            10 if payload.len() == 10 => Record::Color {
+                player_id: read_u64(1)?,
+                color: match payload[9] {
+                    color @ 1..=5 => Color::deserialize(&color),
+                    _ => return None,
+                },
+            },
+            11 if payload.len() == 11 => Record::Bot {
+                player_id: read_u64(1)?,
+                color: match payload[9] {
+                    color @ 1..=5 => Color::deserialize(&color),
+                    _ => return None,
+                },
+                strategy: BotStrategy::deserialize(payload[10])?,
+            },
+            _ => return None,
+        };
+
+        Some(record)
+    }
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io(std::io::Error),
+    Corrupt { offset: u64 },
+    Unreplayable { record: u64 },
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JournalError::Io(error) => write!(f, "journal io failed: {}", error),
+            JournalError::Corrupt { offset } => {
+                write!(f, "journal is corrupt at byte {}", offset)
+            }
+            JournalError::Unreplayable { record } => {
+                write!(f, "journal record {} can't be replayed", record)
+            }
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+// Longest payload any record has, a profile with the longest name told apart by the largest id
+const MAX_RECORD_LEN: usize = 26 + MAX_NAME_LEN as usize + "#18446744073709551615".len();
+
+fn checksum(payload: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(payload);
+    crc.sum()
+}
+
+// Records are framed as [length u32][payload][crc32 u32], returns the length of file they cover
+fn read_records(data: &[u8]) -> Result<(Vec<Record>, usize), JournalError> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let length = data.get(offset..offset + 4);
+        if length.is_none() {
+            // Torn tail, not even the length made it to disk
+            break;
+        }
+        let length = u32::from_be_bytes(length.unwrap().try_into().unwrap()) as usize;
+        if length > MAX_RECORD_LEN {
+            // No record is this long, so it's a damaged length rather than a torn write
+            return Err(JournalError::Corrupt {
+                offset: offset as u64,
+            });
+        }
+
+        let end = offset + 8 + length;
+        if end > data.len() {
+            // Torn tail, the rest of the record never made it to disk
+            break;
+        }
+        let frame = &data[offset + 4..end];
+
+        let (payload, crc) = frame.split_at(frame.len() - 4);
+        let record = if checksum(payload) == u32::from_be_bytes(crc.try_into().unwrap()) {
+            Record::deserialize(payload)
+        } else {
+            None
+        };
+
+        match record {
+            Some(record) => records.push(record),
+            None if end == data.len() => break,
+            None => {
+                return Err(JournalError::Corrupt {
+                    offset: offset as u64,
+                })
+            }
+        }
+
+        offset = end;
+    }
+
+    Ok((records, offset))
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    policy: FsyncPolicy,
+    unsynced: u64,
+}
+
+impl Journal {
+    pub fn open(path: &Path, policy: FsyncPolicy) -> Result<(Self, Vec<Record>), JournalError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(JournalError::Io)?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).map_err(JournalError::Io)?;
+
+        let (records, valid_len) = read_records(&data)?;
+        // A torn record at the tail is cut off, appends carry on from the last whole record
+        if valid_len < data.len() {
+            tracing::warn!(
+                path = %path.display(),
+                bytes = data.len() - valid_len,
+                "truncating torn journal tail"
+            );
+            file.set_len(valid_len as u64).map_err(JournalError::Io)?;
+            file.sync_data().map_err(JournalError::Io)?;
+        }
+        file.seek(SeekFrom::End(0)).map_err(JournalError::Io)?;
+
+        let journal = Journal {
+            file,
+            policy,
+            unsynced: 0,
+        };
+
+        Ok((journal, records))
+    }
+
+    pub fn append(&mut self, record: &Record) -> std::io::Result<()> {
+        let payload = record.serialize();
+        // Refused now rather than read back as corruption on the next start
+        if payload.len() > MAX_RECORD_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "record longer than the journal reads back",
+            ));
+        }
+
+        let mut frame = Vec::with_capacity(payload.len() + 8);
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame.extend_from_slice(&checksum(&payload).to_be_bytes());
+        self.file.write_all(&frame)?;
+
+        self.unsynced += 1;
+        match self.policy {
+            FsyncPolicy::Always => self.flush(),
+            FsyncPolicy::EveryRecords(records) if self.unsynced >= records => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if self.unsynced == 0 {
+            return Ok(());
+        }
+
+        self.file.sync_data()?;
+        self.unsynced = 0;
+
+        Ok(())
+    }
+}
@@ -1,25 +1,83 @@
-use connect4000_core::{debug_print_game, Coin, Coins, Color, Game, GameError, Groups, Player};
-use std::collections::HashMap;
-use std::path::Path;
+use admin::{bind_admin, serve_admin, AdminAction, AdminReply};
+use bot::spawn_bot;
+use chat::{unix_millis, ChatLimiter};
+use clock::TurnClock;
+use connect4000_core::{debug_print_game, Coins, Color, Game, GameError, Groups, Player};
+use identity::{Credentials, Profile};
+use journal::{Journal, JournalError, Record};
+use limits::{Limits, SessionLimiter};
+use matchmaking::{
+    spawn_matchmaker, stop_matchmaker, GameEvent, Match, MatchmakerActions, Ratings, Ticket,
+};
+use metrics::{serve_metrics, Metrics};
+use outbox::{Outbox, OutboxConfig};
+use rand::Rng;
+use snapshot::{
+    deflate, deserialize_coins, deserialize_coins_runs, inflate, serialize_coins,
+    serialize_coins_runs,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{mpsc, oneshot, RwLock};
-use wtransport::Identity;
-use wtransport::{SendStream, ServerConfig as WTransportServerConfig};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, watch, RwLock};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+use tokio_tungstenite::accept_hdr_async_with_config;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tracing::{Instrument, Span};
+use transport::{RecvHalf, SendHalf};
+use wtransport::endpoint::IncomingSession;
+use wtransport::ServerConfig as WTransportServerConfig;
+use wtransport::{Identity, VarInt};
 
+pub use bot::BotStrategy;
+pub use chat::{Blocklist, ChatFilter, MAX_CHAT_LEN};
+pub use clock::TimeoutPenalty;
+pub use config::{parse_color, ConfigError, IdentityConfig, LeavePolicy, ServerConfig};
+pub use identity::{Credential, MAX_NAME_LEN};
+pub use journal::FsyncPolicy;
+pub use limits::RateLimit;
+pub use matchmaking::{MatchPreferences, PlayerIdentity};
+pub use outbox::{OutboundMetrics, OverflowPolicy};
+pub use snapshot::SnapshotEncoding;
 pub use wtransport::{ClientConfig, Endpoint};
 
+mod admin;
+pub mod bot;
+mod chat;
+mod clock;
+mod config;
+mod identity;
+pub mod journal;
+mod limits;
+mod matchmaking;
+mod metrics;
+mod outbox;
+pub mod snapshot;
+pub mod transport;
+
 fn handle_play_coin(
     game: &mut Game,
     coins: &mut Coins,
     groups: &mut Groups,
+    turns: &mut Turns,
     player: &Player,
     input: u64,
-) -> Result<(), GameError> {
+) -> Result<u64, GameError> {
     if input as usize > coins.len() {
         return Err(GameError::ColumnOutOfBounds);
     }
 
+    if turns.current() != Some(player.id) {
+        return Err(GameError::NotPlayersTurn);
+    }
+
     game.play_coin(
         player.id,
         input,
@@ -28,15 +86,159 @@ fn handle_play_coin(
         coins,
     )?;
 
+    turns.advance();
+
     debug_print_game(game, coins, 3);
 
-    Ok(())
+    let row = coins.get(input as usize).unwrap().len() as u64 - 1;
+
+    Ok(row)
+}
+
+/// Order in which seated players take their turns
+#[derive(Debug, Default)]
+struct Turns {
+    order: Vec<u64>,
+    index: usize,
+    /// Turns taken so far, telling one turn of a player's from their next
+    taken: u64,
+}
+
+impl Turns {
+    fn current(&self) -> Option<u64> {
+        self.order.get(self.index).copied()
+    }
+
+    fn seat(&mut self, player_id: u64) {
+        self.order.push(player_id);
+    }
+
+    fn advance(&mut self) {
+        if !self.order.is_empty() {
+            self.index = (self.index + 1) % self.order.len();
+        }
+        self.taken += 1;
+    }
+
+    /// Removes a player from the turn order, passing their turn on to the next player if it was theirs
+    fn unseat(&mut self, player_id: u64) {
+        let position = self.order.iter().position(|id| *id == player_id);
+        if position.is_none() {
+            return;
+        }
+        let position = position.unwrap();
+
+        self.order.remove(position);
+
+        if position < self.index {
+            self.index -= 1;
+        }
+        if self.index >= self.order.len() {
+            self.index = 0;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    fn seated(&self) -> &[u64] {
+        &self.order
+    }
+
+    /// Seats players afresh in the given order, the first of them on turn
+    fn reset(&mut self, order: Vec<u64>) {
+        self.order = order;
+        self.index = 0;
+        self.taken += 1;
+    }
+}
+
+/// Hands the game to the last player left seated, returning true if that decided the game
+fn last_seated_wins(game: &mut Game, turns: &Turns) -> bool {
+    if game.winner_id.is_some() || turns.len() != 1 {
+        return false;
+    }
+
+    game.winner_id = turns.current();
+    true
 }
 
-fn create_game() -> (Game, Coins, Groups) {
-    let mut game = Game::default();
+/// The player whose clock is running, once at least two players are seated in an undecided game
+fn on_turn(game: &Game, turns: &Turns) -> Option<u64> {
+    if game.winner_id.is_some() || turns.len() < 2 {
+        return None;
+    }
+
+    turns.current()
+}
+
+/// Games won by each player, across every rematch
+#[derive(Debug, Default)]
+struct Score {
+    /// Rematches started since the first game
+    round: u64,
+    wins: HashMap<u64, u64>,
+    /// Whether the current game's win has already been counted
+    counted: bool,
+}
+
+impl Score {
+    /// Counts the current game's win once it has a winner, returning true the first time
+    fn count(&mut self, game: &Game) -> bool {
+        if self.counted || game.winner_id.is_none() {
+            return false;
+        }
+
+        *self.wins.entry(game.winner_id.unwrap()).or_default() += 1;
+        self.counted = true;
+        true
+    }
+
+    fn view(&self) -> Vec<u8> {
+        let mut wins: Vec<(u64, u64)> = self.wins.iter().map(|(id, wins)| (*id, *wins)).collect();
+        wins.sort();
+
+        View::serialize(View::Score(ScoreViewData {
+            round: self.round,
+            wins,
+        }))
+    }
+}
+
+/// Starts a fresh board the same size as the last, or the size an admin picked, with every
+/// remaining player seated again and the first move passed along to the next player each round
+fn start_rematch(
+    game: &mut Game,
+    coins: &mut Coins,
+    groups: &mut Groups,
+    players: &HashMap<u64, Player>,
+    turns: &mut Turns,
+    score: &mut Score,
+    columns: Option<u64>,
+) {
+    let columns = columns.unwrap_or(coins.len() as u64);
+    (*game, *coins, *groups) = create_game(columns, game.win_size);
+
+    score.round += 1;
+    score.counted = false;
+
+    let mut order: Vec<u64> = players.keys().copied().collect();
+    order.sort();
+    if !order.is_empty() {
+        let first = (score.round as usize) % order.len();
+        order.rotate_left(first);
+    }
+    turns.reset(order);
+}
+
+fn create_game(columns: u64, win_size: Option<u64>) -> (Game, Coins, Groups) {
+    let mut game = Game {
+        win_size,
+        ..Default::default()
+    };
 
-    let coins = game.set_columns(4).unwrap();
+    let coins = game.set_columns(columns).unwrap();
     let groups: Groups = HashMap::new();
 
     (game, coins, groups)
@@ -47,9 +249,30 @@ fn vec_to_u64(vec: Vec<u8>) -> u64 {
     u64::from_be_bytes(bytes)
 }
 
+/// Opaque token handed to a player on join, used to resume their seat from a new connection
+pub type ResumeToken = [u8; 16];
+
 #[derive(Debug)]
 pub enum Command {
     PlayCoin(u64),
+    Join,
+    Resume(ResumeToken),
+    Spectate,
+    Resync,
+    SnapshotEncoding(SnapshotEncoding),
+    /// A vote to play again once the game has been won
+    Rematch,
+    /// Wait to be paired with a player of a similar rating, in a game of their own. Only players
+    /// who identified with a credential are rated.
+    FindMatch(MatchPreferences),
+    /// A message to every player and spectator, at most `MAX_CHAT_LEN` bytes
+    Chat(String),
+    /// The name to go by, and a credential proving who the player is, ahead of the handshake.
+    /// Without a credential the server issues one.
+    Identify(String, Option<Credential>),
+    /// The colour to play as. Ahead of the handshake it's the colour asked for on joining, after it
+    /// a change of colour, only until the first coin is dropped.
+    ChangeColor(Color),
     Closed,
 }
 
@@ -66,6 +289,41 @@ impl Command {
                 let rest = binary.get(1..9).unwrap().to_vec();
                 Command::PlayCoin(vec_to_u64(rest))
             }
+            4 => Command::Join,
+            5 => {
+                let token = binary.get(1..17).unwrap().try_into().unwrap();
+                Command::Resume(token)
+            }
+            6 => Command::Spectate,
+            9 => Command::Resync,
+            10 => {
+                let encoding = binary.get(1).unwrap();
+                Command::SnapshotEncoding(
+                    SnapshotEncoding::deserialize(encoding).unwrap_or_default(),
+                )
+            }
+            14 => Command::Rematch,
+            17 => {
+                let columns = vec_to_u64(binary.get(1..9).unwrap().to_vec());
+                let win_size = vec_to_u64(binary.get(9..17).unwrap().to_vec());
+                Command::FindMatch(MatchPreferences {
+                    columns: Some(columns).filter(|columns| *columns != 0),
+                    win_size: Some(win_size).filter(|win_size| *win_size != 0),
+                })
+            }
+            20 => {
+                let message = binary.get(9..).unwrap();
+                Command::Chat(String::from_utf8_lossy(message).into_owned())
+            }
+            22 => {
+                let credential = match binary.get(1).unwrap() {
+                    0 => None,
+                    _ => Some(binary.get(2..50).unwrap().try_into().unwrap()),
+                };
+                let name = binary.get(58..).unwrap().to_vec();
+                Command::Identify(String::from_utf8(name).unwrap(), credential)
+            }
+            25 => Command::ChangeColor(Color::deserialize(binary.get(1).unwrap())),
             fallthrough => {
                 panic!("invalid command: {}", fallthrough);
             }
@@ -81,6 +339,108 @@ impl Command {
                 buffer.append(&mut column);
                 buffer
             }
+            Command::Join => vec![4],
+            Command::Resume(token) => {
+                let mut buffer = vec![5];
+                buffer.extend_from_slice(token);
+                buffer
+            }
+            Command::Spectate => vec![6],
+            Command::Resync => vec![9],
+            Command::SnapshotEncoding(encoding) => vec![10, encoding.serialize()],
+            Command::Rematch => vec![14],
+            Command::FindMatch(preferences) => {
+                let mut buffer = vec![17];
+                buffer.extend_from_slice(&preferences.columns.unwrap_or(0).to_be_bytes());
+                buffer.extend_from_slice(&preferences.win_size.unwrap_or(0).to_be_bytes());
+                buffer
+            }
+            Command::Chat(message) => {
+                let mut buffer = vec![20];
+                buffer.extend_from_slice(&(message.len() as u64).to_be_bytes());
+                buffer.extend_from_slice(message.as_bytes());
+                buffer
+            }
+            Command::Identify(name, credential) => {
+                let mut buffer = vec![22, credential.is_some() as u8];
+                buffer.extend_from_slice(&credential.unwrap_or([0; 48]));
+                buffer.extend_from_slice(&(name.len() as u64).to_be_bytes());
+                buffer.extend_from_slice(name.as_bytes());
+                buffer
+            }
+            Command::ChangeColor(color) => vec![25, color.serialize()],
+        }
+    }
+
+    /// Number of bytes following the type byte of a command, if the type is a known command. Chat
+    /// messages and names follow on from their length.
+    fn body_len(op: u8) -> Option<usize> {
+        match op {
+            2 | 20 => Some(8),
+            4 | 6 | 9 | 14 => Some(0),
+            5 => Some(16),
+            10 | 25 => Some(1),
+            17 => Some(16),
+            22 => Some(57),
+            _ => None,
+        }
+    }
+}
+
+/// Why the server is closing a client's stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// Every seat in the game is taken
+    GameFull,
+    /// The server has as many players as it allows
+    ServerFull,
+    /// The server is shutting down
+    ServerShutdown,
+    /// An admin removed the player from the game
+    Kicked,
+    /// An admin ended the game for everyone
+    GameEnded,
+    /// The connection, or its address, sent commands faster than its rate limit
+    RateLimited,
+    /// The address already has as many sessions open as it's allowed
+    TooManySessions,
+    /// A WebSocket frame was larger than the server accepts
+    FrameTooLarge,
+    /// The credential wasn't signed by the server, or the server wants one that wasn't given
+    NotAuthenticated,
+    /// The player's seat was taken over by a newer connection
+    Replaced,
+}
+
+impl CloseReason {
+    pub fn serialize(&self) -> u8 {
+        match self {
+            CloseReason::GameFull => 1,
+            CloseReason::ServerFull => 2,
+            CloseReason::ServerShutdown => 3,
+            CloseReason::Kicked => 4,
+            CloseReason::GameEnded => 5,
+            CloseReason::RateLimited => 6,
+            CloseReason::TooManySessions => 7,
+            CloseReason::FrameTooLarge => 8,
+            CloseReason::NotAuthenticated => 9,
+            CloseReason::Replaced => 10,
+        }
+    }
+
+    pub fn deserialize(input: &u8) -> Option<Self> {
+        match input {
+            1 => Some(CloseReason::GameFull),
+            2 => Some(CloseReason::ServerFull),
+            3 => Some(CloseReason::ServerShutdown),
+            4 => Some(CloseReason::Kicked),
+            5 => Some(CloseReason::GameEnded),
+            6 => Some(CloseReason::RateLimited),
+            7 => Some(CloseReason::TooManySessions),
+            8 => Some(CloseReason::FrameTooLarge),
+            9 => Some(CloseReason::NotAuthenticated),
+            10 => Some(CloseReason::Replaced),
+            _ => None,
         }
     }
 }
@@ -89,6 +449,17 @@ impl Command {
 pub struct JoinedViewData {
     player_id: u64,
     color: u8,
+    token: ResumeToken,
+}
+
+#[derive(Debug)]
+pub struct PlayerLeftViewData {
+    player_id: u64,
+}
+
+#[derive(Debug)]
+pub struct SpectatorsViewData {
+    count: u64,
 }
 
 #[derive(Debug)]
@@ -97,22 +468,223 @@ pub struct SnapshotViewData<'a> {
     winner_id: Option<u64>,
     col_count: u64,
     row_count: u64,
+    sequence: u64,
+}
+
+#[derive(Debug)]
+pub struct EncodedSnapshotViewData {
+    body: Vec<u8>,
+    encoding: SnapshotEncoding,
+    winner_id: Option<u64>,
+    col_count: u64,
+    row_count: u64,
+    sequence: u64,
+}
+
+#[derive(Debug)]
+pub struct CoinPlacedViewData {
+    sequence: u64,
+    column: u64,
+    row: u64,
+    color: u8,
+    player_id: u64,
+    winner_id: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct ClosedViewData {
+    reason: CloseReason,
+}
+
+#[derive(Debug)]
+pub struct ClockViewData {
+    on_turn: Option<u64>,
+    turn_remaining: Duration,
+    clocks: Vec<(u64, Duration)>,
+}
+
+#[derive(Debug)]
+pub struct RematchVotesViewData {
+    votes: u64,
+    needed: u64,
+}
+
+#[derive(Debug)]
+pub struct ScoreViewData {
+    round: u64,
+    wins: Vec<(u64, u64)>,
+}
+
+#[derive(Debug)]
+pub struct ServerMessageViewData {
+    message: String,
+}
+
+#[derive(Debug)]
+pub struct CredentialViewData {
+    credential: Credential,
+}
+
+#[derive(Debug)]
+pub struct PlayerInfoViewData {
+    player_id: u64,
+    color: u8,
+    /// Whether the player proved who they are with a credential
+    verified: bool,
+    name: String,
+}
+
+#[derive(Debug)]
+pub struct ColorChangedViewData {
+    player_id: u64,
+    color: u8,
+}
+
+#[derive(Debug)]
+pub struct ChatViewData {
+    player_id: u64,
+    /// Milliseconds since the unix epoch the server relayed the message at
+    timestamp: u64,
+    message: String,
+}
+
+#[derive(Debug)]
+pub struct MatchFoundViewData {
+    game_id: u64,
+    rating: u64,
+    opponent_rating: u64,
+    columns: u64,
+    win_size: Option<u64>,
+    first: bool,
 }
 
 #[derive(Debug)]
 pub enum View<'a> {
     Snapshot(SnapshotViewData<'a>),
     Joined(JoinedViewData),
+    PlayerLeft(PlayerLeftViewData),
+    Spectators(SpectatorsViewData),
+    CoinPlaced(CoinPlacedViewData),
+    EncodedSnapshot(EncodedSnapshotViewData),
+    Closed(ClosedViewData),
+    Clock(ClockViewData),
+    RematchVotes(RematchVotesViewData),
+    Score(ScoreViewData),
+    MatchFound(MatchFoundViewData),
+    ServerMessage(ServerMessageViewData),
+    Chat(ChatViewData),
+    Credential(CredentialViewData),
+    PlayerInfo(PlayerInfoViewData),
+    ColorChanged(ColorChangedViewData),
 }
 
 impl<'a> View<'a> {
+    /// Reads the next whole view from a stream, returning its serialized bytes
+    pub async fn read<R: RecvHalf + ?Sized>(socket_rx: &mut R) -> Option<Vec<u8>> {
+        let mut buffer = vec![0; 1];
+        socket_rx.read_exact(&mut buffer).await.ok()?;
+
+        let header_len = match buffer[0] {
+            0 => 25,
+            1 => 32,
+            3 | 7 => 8,
+            8 => 41,
+            11 => 41,
+            12 => 1,
+            13 => 24,
+            15 | 16 => 16,
+            18 => 41,
+            19 => 8,
+            21 => 24,
+            23 => 48,
+            24 => 18,
+            26 => 9,
+            _ => return None,
+        };
+        buffer.resize(1 + header_len, 0);
+        socket_rx.read_exact(&mut buffer[1..]).await.ok()?;
+
+        // Snapshots carry their coins after the header, either as a dense grid or an encoded body
+        let body_len = match buffer[0] {
+            1 => {
+                let col_count = u64::from_be_bytes(buffer[9..17].try_into().unwrap());
+                let row_count = u64::from_be_bytes(buffer[17..25].try_into().unwrap());
+                (col_count * row_count) as usize
+            }
+            11 => u64::from_be_bytes(buffer[34..42].try_into().unwrap()) as usize,
+            // Clocks carry a player id and their time left for every seated player
+            13 => u64::from_be_bytes(buffer[17..25].try_into().unwrap()) as usize * 16,
+            // Scores carry a player id and their wins for every player who has won a game
+            16 => u64::from_be_bytes(buffer[9..17].try_into().unwrap()) as usize * 16,
+            // Server messages carry their text
+            19 => u64::from_be_bytes(buffer[1..9].try_into().unwrap()) as usize,
+            21 => u64::from_be_bytes(buffer[17..25].try_into().unwrap()) as usize,
+            24 => u64::from_be_bytes(buffer[11..19].try_into().unwrap()) as usize,
+            _ => 0,
+        };
+
+        if body_len > 0 {
+            buffer.resize(1 + header_len + body_len, 0);
+            socket_rx
+                .read_exact(&mut buffer[1 + header_len..])
+                .await
+                .ok()?;
+        }
+
+        Some(buffer)
+    }
+
+    /// Turns an `EncodedSnapshot` view back into the dense `Snapshot` view it stands for
+    pub fn expand_snapshot(view: &[u8]) -> Option<Vec<u8>> {
+        let col_count = u64::from_be_bytes(view.get(9..17)?.try_into().unwrap());
+        let row_count = u64::from_be_bytes(view.get(17..25)?.try_into().unwrap());
+        let encoding = SnapshotEncoding::deserialize(view.get(33)?)?;
+        let body = view.get(42..)?;
+
+        let grid = match encoding {
+            SnapshotEncoding::Dense => body.to_vec(),
+            SnapshotEncoding::Runs => deserialize_coins_runs(body, col_count, row_count)?,
+            SnapshotEncoding::DeflatedRuns => {
+                deserialize_coins_runs(&inflate(body)?, col_count, row_count)?
+            }
+        };
+
+        // The encoded header starts with the same fields as the dense one
+        let mut buffer = vec![1];
+        buffer.extend_from_slice(view.get(1..33)?);
+        buffer.extend_from_slice(&grid);
+        Some(buffer)
+    }
+
+    /// Reads the coins out of a `Snapshot` view, or an `EncodedSnapshot` one
+    pub fn snapshot_coins(view: &[u8]) -> Option<Coins> {
+        let expanded;
+        let view = match view.first()? {
+            1 => view,
+            11 => {
+                expanded = View::expand_snapshot(view)?;
+                &expanded
+            }
+            _ => return None,
+        };
+
+        let col_count = u64::from_be_bytes(view.get(9..17)?.try_into().unwrap());
+        let row_count = u64::from_be_bytes(view.get(17..25)?.try_into().unwrap());
+        deserialize_coins(view.get(33..)?, col_count, row_count)
+    }
+
     fn serialize(view: View) -> Vec<u8> {
         match view {
-            View::Joined(JoinedViewData { player_id, color }) => {
+            View::Joined(JoinedViewData {
+                player_id,
+                color,
+                token,
+            }) => {
                 let mut buffer = vec![0];
                 let mut vec = player_id.to_be_bytes().to_vec();
                 vec.push(color);
                 buffer.append(&mut vec);
+                buffer.extend_from_slice(&token);
                 buffer
             }
             View::Snapshot(SnapshotViewData {
@@ -120,246 +692,2522 @@ impl<'a> View<'a> {
                 winner_id,
                 col_count,
                 row_count,
+                sequence,
             }) => {
                 let mut buffer = vec![1];
                 let winner_id: u64 = winner_id.unwrap_or(0);
                 buffer.extend_from_slice(&winner_id.to_be_bytes());
                 buffer.extend_from_slice(&col_count.to_be_bytes());
                 buffer.extend_from_slice(&row_count.to_be_bytes());
+                buffer.extend_from_slice(&sequence.to_be_bytes());
                 buffer.append(snapshot);
                 buffer
             }
+            View::PlayerLeft(PlayerLeftViewData { player_id }) => {
+                let mut buffer = vec![3];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer
+            }
+            View::Spectators(SpectatorsViewData { count }) => {
+                let mut buffer = vec![7];
+                buffer.extend_from_slice(&count.to_be_bytes());
+                buffer
+            }
+            View::CoinPlaced(CoinPlacedViewData {
+                sequence,
+                column,
+                row,
+                color,
+                player_id,
+                winner_id,
+            }) => {
+                let mut buffer = vec![8];
+                buffer.extend_from_slice(&sequence.to_be_bytes());
+                buffer.extend_from_slice(&column.to_be_bytes());
+                buffer.extend_from_slice(&row.to_be_bytes());
+                buffer.push(color);
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer.extend_from_slice(&winner_id.unwrap_or(0).to_be_bytes());
+                buffer
+            }
+            View::EncodedSnapshot(EncodedSnapshotViewData {
+                body,
+                encoding,
+                winner_id,
+                col_count,
+                row_count,
+                sequence,
+            }) => {
+                let mut buffer = vec![11];
+                buffer.extend_from_slice(&winner_id.unwrap_or(0).to_be_bytes());
+                buffer.extend_from_slice(&col_count.to_be_bytes());
+                buffer.extend_from_slice(&row_count.to_be_bytes());
+                buffer.extend_from_slice(&sequence.to_be_bytes());
+                buffer.push(encoding.serialize());
+                buffer.extend_from_slice(&(body.len() as u64).to_be_bytes());
+                buffer.extend_from_slice(&body);
+                buffer
+            }
+            View::Closed(ClosedViewData { reason }) => vec![12, reason.serialize()],
+            View::Clock(ClockViewData {
+                on_turn,
+                turn_remaining,
+                clocks,
+            }) => {
+                let mut buffer = vec![13];
+                buffer.extend_from_slice(&on_turn.unwrap_or(0).to_be_bytes());
+                buffer.extend_from_slice(&(turn_remaining.as_millis() as u64).to_be_bytes());
+                buffer.extend_from_slice(&(clocks.len() as u64).to_be_bytes());
+                for (player_id, remaining) in clocks {
+                    buffer.extend_from_slice(&player_id.to_be_bytes());
+                    buffer.extend_from_slice(&(remaining.as_millis() as u64).to_be_bytes());
+                }
+                buffer
+            }
+            View::RematchVotes(RematchVotesViewData { votes, needed }) => {
+                let mut buffer = vec![15];
+                buffer.extend_from_slice(&votes.to_be_bytes());
+                buffer.extend_from_slice(&needed.to_be_bytes());
+                buffer
+            }
+            View::Score(ScoreViewData { round, wins }) => {
+                let mut buffer = vec![16];
+                buffer.extend_from_slice(&round.to_be_bytes());
+                buffer.extend_from_slice(&(wins.len() as u64).to_be_bytes());
+                for (player_id, wins) in wins {
+                    buffer.extend_from_slice(&player_id.to_be_bytes());
+                    buffer.extend_from_slice(&wins.to_be_bytes());
+                }
+                buffer
+            }
+            View::MatchFound(MatchFoundViewData {
+                game_id,
+                rating,
+                opponent_rating,
+                columns,
+                win_size,
+                first,
+            }) => {
+                let mut buffer = vec![18];
+                buffer.extend_from_slice(&game_id.to_be_bytes());
+                buffer.extend_from_slice(&rating.to_be_bytes());
+                buffer.extend_from_slice(&opponent_rating.to_be_bytes());
+                buffer.extend_from_slice(&columns.to_be_bytes());
+                buffer.extend_from_slice(&win_size.unwrap_or(0).to_be_bytes());
+                buffer.push(first as u8);
+                buffer
+            }
+            View::ServerMessage(ServerMessageViewData { message }) => {
+                let mut buffer = vec![19];
+                buffer.extend_from_slice(&(message.len() as u64).to_be_bytes());
+                buffer.extend_from_slice(message.as_bytes());
+                buffer
+            }
+            View::Chat(ChatViewData {
+                player_id,
+                timestamp,
+                message,
+            }) => {
+                let mut buffer = vec![21];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer.extend_from_slice(&timestamp.to_be_bytes());
+                buffer.extend_from_slice(&(message.len() as u64).to_be_bytes());
+                buffer.extend_from_slice(message.as_bytes());
+                buffer
+            }
+            View::Credential(CredentialViewData { credential }) => {
+                let mut buffer = vec![23];
+                buffer.extend_from_slice(&credential);
+                buffer
+            }
+            View::PlayerInfo(PlayerInfoViewData {
+                player_id,
+                color,
+                verified,
+                name,
+            }) => {
+                let mut buffer = vec![24];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer.push(color);
+                buffer.push(verified as u8);
+                buffer.extend_from_slice(&(name.len() as u64).to_be_bytes());
+                buffer.extend_from_slice(name.as_bytes());
+                buffer
+            }
+            View::ColorChanged(ColorChangedViewData { player_id, color }) => {
+                let mut buffer = vec![26];
+                buffer.extend_from_slice(&player_id.to_be_bytes());
+                buffer.push(color);
+                buffer
+            }
         }
     }
 }
 
-#[derive(Debug)]
-enum Actions {
-    Snapshot(oneshot::Sender<(Vec<u8>, Option<u64>, u64, u64)>),
-    PlayCoin(u64, u64),
-    Join(oneshot::Sender<(Vec<u8>, Color)>),
+/// A player's place in the game, as handed to their connection
+#[derive(Debug, Clone)]
+struct Seat {
+    player_id: u64,
+    color: Color,
+    token: ResumeToken,
 }
 
-fn serialize_coin_column(column: &[Coin]) -> Vec<u8> {
-    let mut result = Vec::new();
-
-    for coin in column.iter() {
-        result.push(coin.color.serialize());
-    }
-
-    result
+/// Tracks which connection, if any, is currently attached to a player
+#[derive(Debug)]
+struct Session {
+    token: ResumeToken,
+    connection_id: Option<u64>,
+    disconnects: u64,
 }
 
-fn serialize_coins(coins: &Coins) -> (Vec<u8>, u64, u64) {
-    let mut data = Vec::new();
-
-    let number_of_columns: u64 = coins.len() as u64;
-    let number_of_rows: u64 = coins.iter().fold(0, |acc, column| {
-        if column.len() > acc {
-            column.len()
-        } else {
-            acc
-        }
-    }) as u64;
+/// A game's state, either fresh or rebuilt by replaying its journal
+#[derive(Debug)]
+struct GameState {
+    game_data: (Game, Coins, Groups),
+    sequence: u64,
+    players: HashMap<u64, Player>,
+    turns: Turns,
+    next_player_id: u64,
+    sessions: HashMap<u64, Session>,
+    tokens: HashMap<ResumeToken, u64>,
+    score: Score,
+    /// Board size an admin set for the next fresh board
+    next_columns: Option<u64>,
+    /// Names players go by, players without one haven't said
+    profiles: HashMap<u64, Profile>,
+    /// Seated bots, spawned once the game starts
+    bots: HashMap<u64, BotStrategy>,
+}
 
-    for column_index in 0..number_of_columns {
-        let v: Vec<Coin> = Vec::new();
-        let column = coins.get(column_index as usize).unwrap_or(&v);
-        let mut column_result = serialize_coin_column(column);
-        if column_result.len() > number_of_rows as usize {
-            panic!("invalid column serialize");
+impl GameState {
+    fn new(columns: u64, win_size: Option<u64>) -> Self {
+        GameState {
+            game_data: create_game(columns, win_size),
+            sequence: 0,
+            players: HashMap::new(),
+            turns: Turns::default(),
+            next_player_id: 1,
+            sessions: HashMap::new(),
+            tokens: HashMap::new(),
+            score: Score::default(),
+            next_columns: None,
+            profiles: HashMap::new(),
+            bots: HashMap::new(),
         }
-        column_result.resize(number_of_rows as usize, 0);
-        data.append(&mut column_result);
     }
 
-    (data, number_of_columns, number_of_rows)
-}
+    /// Seats a player ahead of them connecting, they take the seat by resuming it with its token
+    fn hold_seat(&mut self, colors: &[Color]) -> (u64, ResumeToken) {
+        let player_id = self.next_player_id;
+        self.next_player_id += 1;
 
-#[derive(Debug)]
-pub struct ServerConfig<'a> {
-    pub port: u16,
-    pub cert_path: &'a Path,
-    pub key_path: &'a Path,
-}
+        let color = free_color(colors, &self.players, player_id, None);
+        self.players
+            .insert(player_id, Player::from_color(player_id, color));
+        self.turns.seat(player_id);
 
-pub async fn start_server(config: ServerConfig<'_>, start_tx: oneshot::Sender<()>) {
-    let identity = Identity::load_pemfiles(config.cert_path, config.key_path)
-        .await
-        .unwrap();
+        let token: ResumeToken = rand::random();
+        self.sessions.insert(
+            player_id,
+            Session {
+                token,
+                connection_id: None,
+                disconnects: 0,
+            },
+        );
+        self.tokens.insert(token, player_id);
 
-    let config = WTransportServerConfig::builder()
-        .with_bind_default(config.port)
-        .with_identity(&identity)
-        .keep_alive_interval(Some(Duration::from_secs(3)))
-        .build();
-    let server = Endpoint::server(config).unwrap();
+        (player_id, token)
+    }
 
-    let size = u8::MAX as usize;
-    let (game_action_tx, mut game_action_rx) = mpsc::channel(size);
+    /// Rebuilds a game from its journal, starting from the board it was created with. Every player
+    /// comes back disconnected, free to resume their seat with the token they were given.
+    fn replay(records: Vec<Record>) -> Result<Self, JournalError> {
+        let mut records = records.into_iter();
 
-    start_tx.send(()).unwrap();
+        let mut state = match records.next() {
+            Some(Record::Create { columns, win_size }) => GameState::new(columns, win_size),
+            _ => return Err(JournalError::Unreplayable { record: 0 }),
+        };
+        let (game, coins, groups) = &mut state.game_data;
 
-    // Game action thread, receive events from other threads to read/write game state
-    tokio::spawn(async move {
-        let mut game_data = create_game();
-        let mut players: HashMap<u64, Player> = HashMap::new();
+        for (index, record) in records.enumerate() {
+            let unreplayable = JournalError::Unreplayable {
+                record: index as u64 + 1,
+            };
 
-        loop {
-            let action = game_action_rx.recv().await;
-            if action.is_none() {
-                panic!("action is none");
-            }
-            let action = action.unwrap();
-            match action {
-                Actions::PlayCoin(column, player_id) => {
-                    log::info!("player dropped coin - {} - {}", player_id, column);
-
-                    let player = players.get(&player_id).unwrap();
-
-                    let _ = handle_play_coin(
-                        &mut game_data.0,
-                        &mut game_data.1,
-                        &mut game_data.2,
-                        player,
-                        column,
+            match record {
+                Record::Create { .. } => return Err(unreplayable),
+                Record::Join {
+                    player_id,
+                    color,
+                    token,
+                } => {
+                    state
+                        .players
+                        .insert(player_id, Player::from_color(player_id, color));
+                    state.turns.seat(player_id);
+                    state.sessions.insert(
+                        player_id,
+                        Session {
+                            token,
+                            connection_id: None,
+                            disconnects: 1,
+                        },
                     );
+                    state.tokens.insert(token, player_id);
+                    state.next_player_id = state.next_player_id.max(player_id + 1);
                 }
-                Actions::Snapshot(view_tx) => {
-                    log::info!("snapshot requested");
-
-                    let (snapshot, col_count, row_count) = serialize_coins(&game_data.1);
-                    view_tx
-                        .send((snapshot, game_data.0.winner_id, col_count, row_count))
-                        .unwrap();
-                }
-                Actions::Join(view_tx) => {
-                    let player_id = (players.len() as u64) + 1;
+                Record::PlayCoin { player_id, column } => {
+                    let player = state.players.get(&player_id).ok_or(unreplayable)?;
 
-                    log::info!("player joined - {}", player_id);
+                    let mut coin = player.coin(groups.len() as u64);
+                    game.play_coin(player_id, column, &mut coin, groups, coins)
+                        .map_err(|_| JournalError::Unreplayable {
+                            record: index as u64 + 1,
+                        })?;
 
-                    let next_index = player_id % 2;
+                    state.turns.advance();
+                    state.sequence += 1;
+                }
+                Record::Leave { player_id, forfeit } => {
+                    state.turns.unseat(player_id);
+                    state.players.remove(&player_id);
+                    state.bots.remove(&player_id);
+                    if let Some(session) = state.sessions.remove(&player_id) {
+                        state.tokens.remove(&session.token);
+                    }
 
-                    let player = match next_index {
-                        0 => Player::orange(player_id),
-                        1 => Player::blue(player_id),
-                        _ => panic!("invalid player gen index"),
-                    };
-                    players.insert(player_id, player.clone());
+                    if forfeit && last_seated_wins(game, &state.turns) {
+                        state.sequence += 1;
+                    }
+                }
+                Record::Skip { player_id } => {
+                    if state.turns.current() != Some(player_id) {
+                        return Err(unreplayable);
+                    }
+                    state.turns.advance();
+                }
+                Record::Forfeit { player_id } => {
+                    state.turns.unseat(player_id);
 
-                    let player_id_bytes = player_id.to_be_bytes().to_vec();
+                    if last_seated_wins(game, &state.turns) {
+                        state.sequence += 1;
+                    }
+                }
+                Record::Rematch | Record::Reset => {
+                    if record == Record::Rematch && game.winner_id.is_none() {
+                        return Err(unreplayable);
+                    }
 
-                    view_tx.send((player_id_bytes, player.color)).unwrap();
+                    start_rematch(
+                        game,
+                        coins,
+                        groups,
+                        &state.players,
+                        &mut state.turns,
+                        &mut state.score,
+                        state.next_columns.take(),
+                    );
+                    state.sequence += 1;
+                }
+                Record::Resize { columns } => {
+                    state.next_columns = Some(columns);
+                }
+                Record::Color { player_id, color } => {
+                    let player = state.players.get_mut(&player_id).ok_or(unreplayable)?;
+                    player.color = color;
+                }
+                Record::Profile {
+                    player_id,
+                    identity,
+                    name,
+                } => {
+                    if !state.players.contains_key(&player_id) {
+                        return Err(unreplayable);
+                    }
+                    state.profiles.insert(player_id, Profile { name, identity });
+                }
+                Record::Bot {
+                    player_id,
+                    color,
+                    strategy,
+                } => {
+                    state
+                        .players
+                        .insert(player_id, Player::from_color(player_id, color));
+                    state.turns.seat(player_id);
+                    state.bots.insert(player_id, strategy);
+                    state.next_player_id = state.next_player_id.max(player_id + 1);
                 }
             }
+
+            state.score.count(game);
         }
-    });
 
-    let broadcast_channels: Arc<RwLock<Vec<Arc<RwLock<SendStream>>>>> =
-        Arc::new(RwLock::new(Vec::new()));
+        Ok(state)
+    }
+}
 
-    //  Main loop, keep accepting new connections
-    loop {
-        let tx = game_action_tx.clone();
+/// Appends to a game's journal, if it has one. A failed write is logged, the game carries on
+fn record(journal: &mut Option<Journal>, record: Record) {
+    if let Some(journal) = journal {
+        if let Err(error) = journal.append(&record) {
+            tracing::error!(?record, %error, "journal append failed");
+        }
+    }
+}
 
-        let incoming_session = server.accept().await;
+/// The colour a player asked for if nobody else has it, or else the next one nobody has, counting on
+/// from where the palette puts the player. Once every colour is taken they're shared.
+fn free_color(
+    colors: &[Color],
+    players: &HashMap<u64, Player>,
+    player_id: u64,
+    preferred: Option<&Color>,
+) -> Color {
+    let start = preferred
+        .and_then(|preferred| colors.iter().position(|color| color == preferred))
+        .unwrap_or(player_id as usize % colors.len());
 
-        let my_broadcast_channels = broadcast_channels.clone();
-        // Start a thread for each new session
-        tokio::spawn(async move {
-            let command = incoming_session.await.unwrap();
-            let connection = command.accept().await.unwrap();
-            let (socket_tx, mut socket_rx) = connection.accept_bi().await.unwrap();
-            let sock_tx = Arc::new(RwLock::new(socket_tx));
-
-            // Register for broadcasting
-            my_broadcast_channels.write().await.push(sock_tx.clone());
-
-            // Game action - Join game
-            let (join_view_tx, join_view_rx) = oneshot::channel();
-            tx.send(Actions::Join(join_view_tx)).await.unwrap();
-            let join_view = join_view_rx.await.unwrap();
-            let color = join_view.1;
-            let player_id = vec_to_u64(join_view.0);
-
-            // View - Joined game
-            sock_tx
-                .write()
-                .await
-                .write_all(&View::serialize(View::Joined(JoinedViewData {
-                    player_id,
-                    color: color.serialize(),
-                })))
-                .await
-                .unwrap();
+    let taken = |color: &Color| {
+        players
+            .values()
+            .any(|player| player.id != player_id && player.color == *color)
+    };
+    let free = (0..colors.len())
+        .map(|offset| &colors[(start + offset) % colors.len()])
+        .find(|color| !taken(color));
 
-            // View - Snapshot
-            let (view_tx, view_rx) = oneshot::channel();
-            tx.send(Actions::Snapshot(view_tx)).await.unwrap();
-            let mut snapshot = view_rx.await.unwrap();
-            sock_tx
-                .write()
-                .await
-                .write_all(&View::serialize(View::Snapshot(SnapshotViewData {
-                    snapshot: &mut snapshot.0,
-                    winner_id: snapshot.1,
-                    col_count: snapshot.2,
-                    row_count: snapshot.3,
-                })))
-                .await
-                .unwrap();
+    free.unwrap_or(&colors[start]).clone()
+}
 
-            let my_broadcast_channels = my_broadcast_channels.clone();
-            // Command thread, ingests http3 streams, creating actions to pass to worker threads
-            // and views to reply with
-            tokio::spawn(async move {
-                loop {
-                    let size = u8::MAX as usize;
-                    let mut buffer = vec![0; size];
-                    log::debug!("Waiting to read from stream... {}", size);
-                    socket_rx.read(&mut buffer).await.unwrap();
+/// Gives a player the name they asked for, or one told apart from a name already taken, and
+/// returns the view letting everyone know
+fn set_profile(
+    journal: &mut Option<Journal>,
+    profiles: &mut HashMap<u64, Profile>,
+    player: &Player,
+    profile: Profile,
+) -> Vec<u8> {
+    let profile = Profile {
+        name: profile.unique_name(player.id, profiles),
+        ..profile
+    };
+    record(
+        journal,
+        Record::Profile {
+            player_id: player.id,
+            identity: profile.identity,
+            name: profile.name.clone(),
+        },
+    );
+
+    let view = player_info_view(player, &profile);
+    profiles.insert(player.id, profile);
+    view
+}
+
+fn player_info_view(player: &Player, profile: &Profile) -> Vec<u8> {
+    View::serialize(View::PlayerInfo(PlayerInfoViewData {
+        player_id: player.id,
+        color: player.color.serialize(),
+        verified: profile.identity.is_some(),
+        name: profile.name.clone(),
+    }))
+}
+
+#[derive(Debug)]
+enum Actions {
+    Snapshot(SnapshotEncoding, oneshot::Sender<Vec<u8>>),
+    PlayCoin(u64, u64),
+    /// Seats a new player, or hands an identified player back the seat they already have along
+    /// with the connection it's taken from
+    Join(
+        u64,
+        Option<Profile>,
+        Option<Color>,
+        oneshot::Sender<Result<(Seat, Option<u64>), CloseReason>>,
+    ),
+    Resume(
+        ResumeToken,
+        u64,
+        Option<Profile>,
+        oneshot::Sender<Option<(Seat, Option<u64>)>>,
+    ),
+    Disconnect(u64, u64, oneshot::Sender<Option<u64>>),
+    Leave(u64, u64),
+    Spectate(oneshot::Sender<u64>),
+    StopSpectating(oneshot::Sender<u64>),
+    Status(oneshot::Sender<Vec<Vec<u8>>>),
+    Rematch(u64),
+    Chat(u64, String),
+    ChangeColor(u64, Color),
+    /// The player on turn ran out of time, raised by the game actor itself
+    TurnExpired(u64),
+    /// Answered straight away, telling health checks the actor is still running
+    Ping(oneshot::Sender<()>),
+    Admin(AdminAction, oneshot::Sender<AdminReply>),
+    Shutdown(oneshot::Sender<()>),
+}
+
+impl Actions {
+    /// Covers the game actor handling the action, along with the player it was taken for
+    fn span(&self) -> Span {
+        match self {
+            Actions::Snapshot(encoding, _) => tracing::debug_span!("snapshot", ?encoding),
+            Actions::PlayCoin(column, player_id) => {
+                tracing::info_span!("play_coin", player_id, column)
+            }
+            Actions::Join(connection_id, _, _, _) => {
+                tracing::info_span!("join", connection_id, player_id = tracing::field::Empty)
+            }
+            Actions::Resume(_, connection_id, _, _) => {
+                tracing::info_span!("resume", connection_id)
+            }
+            Actions::Disconnect(player_id, connection_id, _) => {
+                tracing::info_span!("disconnect", player_id, connection_id)
+            }
+            Actions::Leave(player_id, disconnects) => {
+                tracing::info_span!("leave", player_id, disconnects)
+            }
+            Actions::Spectate(_) => tracing::info_span!("spectate"),
+            Actions::StopSpectating(_) => tracing::info_span!("stop_spectating"),
+            Actions::Status(_) => tracing::debug_span!("status"),
+            Actions::Rematch(player_id) => tracing::info_span!("rematch", player_id),
+            Actions::Chat(player_id, _) => tracing::info_span!("chat", player_id),
+            Actions::ChangeColor(player_id, color) => {
+                tracing::info_span!("change_color", player_id, ?color)
+            }
+            Actions::TurnExpired(player_id) => tracing::info_span!("turn_expired", player_id),
+            Actions::Ping(_) => tracing::trace_span!("ping"),
+            Actions::Admin(action, _) => tracing::info_span!("admin", ?action),
+            Actions::Shutdown(_) => tracing::info_span!("shutdown"),
+        }
+    }
+}
+
+fn snapshot_view(game: &Game, coins: &Coins, sequence: u64, encoding: SnapshotEncoding) -> Vec<u8> {
+    if encoding == SnapshotEncoding::Dense {
+        let (mut snapshot, col_count, row_count) = serialize_coins(coins);
+
+        return View::serialize(View::Snapshot(SnapshotViewData {
+            snapshot: &mut snapshot,
+            winner_id: game.winner_id,
+            col_count,
+            row_count,
+            sequence,
+        }));
+    }
+
+    let runs = serialize_coins_runs(coins);
+    let body = match encoding {
+        SnapshotEncoding::DeflatedRuns => deflate(&runs),
+        _ => runs,
+    };
+    let row_count = coins.iter().map(|column| column.len()).max().unwrap_or(0) as u64;
+
+    View::serialize(View::EncodedSnapshot(EncodedSnapshotViewData {
+        body,
+        encoding,
+        winner_id: game.winner_id,
+        col_count: coins.len() as u64,
+        row_count,
+        sequence,
+    }))
+}
+
+type BroadcastChannels = Arc<RwLock<HashMap<u64, Outbox>>>;
+
+/// A view on its way to every client, with the span it was sent from so the broadcast can be
+/// traced back to the action or session behind it
+type Broadcast = (Vec<u8>, Span);
+
+/// Queues a payload for every registered client, dropping any client whose writer has stopped
+async fn broadcast(broadcast_channels: &BroadcastChannels, payload: Vec<u8>) {
+    let payload: Arc<[u8]> = payload.into();
+    let mut closed = Vec::new();
+
+    let clients = broadcast_channels.read().await;
+    for (connection_id, outbox) in clients.iter() {
+        if !outbox.push(payload.clone()) {
+            closed.push(*connection_id);
+        }
+    }
+    tracing::debug!(clients = clients.len(), "view queued");
+    drop(clients);
+
+    if !closed.is_empty() {
+        let mut broadcast_channels = broadcast_channels.write().await;
+        for connection_id in closed {
+            tracing::debug!(connection_id, "dropping closed broadcast channel");
+            broadcast_channels.remove(&connection_id);
+        }
+    }
+}
+
+async fn request_snapshot(tx: &mpsc::Sender<Actions>, encoding: SnapshotEncoding) -> Vec<u8> {
+    let (view_tx, view_rx) = oneshot::channel();
+    tx.send(Actions::Snapshot(encoding, view_tx)).await.unwrap();
+    view_rx.await.unwrap()
+}
+
+/// Views following the snapshot for a new client, a `Clock` in a timed game and a `Score` once
+/// there's one to keep
+async fn request_status(tx: &mpsc::Sender<Actions>) -> Vec<Vec<u8>> {
+    let (views_tx, views_rx) = oneshot::channel();
+    tx.send(Actions::Status(views_tx)).await.unwrap();
+    views_rx.await.unwrap()
+}
+
+/// Resolves with the player on turn once their time runs out, or never for an untimed turn
+async fn turn_expiry(deadline: Option<(u64, Instant)>) -> u64 {
+    match deadline {
+        Some((player_id, deadline)) => {
+            tokio::time::sleep_until(deadline).await;
+            player_id
+        }
+        None => std::future::pending().await,
+    }
+}
+
+async fn request_join(
+    tx: &mpsc::Sender<Actions>,
+    connection_id: u64,
+    profile: Option<Profile>,
+    color: Option<Color>,
+) -> Result<(Seat, Option<u64>), CloseReason> {
+    let (join_view_tx, join_view_rx) = oneshot::channel();
+    tx.send(Actions::Join(connection_id, profile, color, join_view_tx))
+        .await
+        .unwrap();
+    join_view_rx.await.unwrap()
+}
+
+/// Registers or deregisters a spectator, returning the new spectator count as a view
+async fn request_spectators(
+    tx: &mpsc::Sender<Actions>,
+    action: fn(oneshot::Sender<u64>) -> Actions,
+) -> Vec<u8> {
+    let (spectators_tx, spectators_rx) = oneshot::channel();
+    tx.send(action(spectators_tx)).await.unwrap();
+    let count = spectators_rx.await.unwrap();
+
+    View::serialize(View::Spectators(SpectatorsViewData { count }))
+}
+
+async fn read_command<R: RecvHalf + ?Sized>(socket_rx: &mut R) -> Command {
+    tracing::trace!("waiting for a command");
+
+    let mut buffer = vec![0; 1];
+    if socket_rx.read_exact(&mut buffer).await.is_err() {
+        return Command::Closed;
+    }
+
+    // Without a known length there's no telling where the next command starts, so give up on the stream
+    let body_len = Command::body_len(buffer[0]);
+    if body_len.is_none() {
+        tracing::info!(payload_type = buffer[0], "invalid command");
+        return Command::Closed;
+    }
+    buffer.resize(1 + body_len.unwrap(), 0);
+    if socket_rx.read_exact(&mut buffer[1..]).await.is_err() {
+        return Command::Closed;
+    }
+
+    if buffer[0] == 20 {
+        let message_len = vec_to_u64(buffer[1..9].to_vec());
+        if message_len > MAX_CHAT_LEN {
+            tracing::info!(message_len, "chat message too long");
+            return Command::Closed;
+        }
+        buffer.resize(9 + message_len as usize, 0);
+        if socket_rx.read_exact(&mut buffer[9..]).await.is_err() {
+            return Command::Closed;
+        }
+    }
+    if buffer[0] == 22 {
+        let name_len = vec_to_u64(buffer[50..58].to_vec());
+        if name_len > MAX_NAME_LEN {
+            tracing::info!(name_len, "name too long");
+            return Command::Closed;
+        }
+        buffer.resize(58 + name_len as usize, 0);
+        if socket_rx.read_exact(&mut buffer[58..]).await.is_err() {
+            return Command::Closed;
+        }
+        // Replacing invalid bytes would make the name longer than it was checked to be
+        if std::str::from_utf8(&buffer[58..]).is_err() {
+            tracing::info!("name not utf-8");
+            return Command::Closed;
+        }
+    }
+    if buffer[0] == 25 && !(1..=5).contains(&buffer[1]) {
+        tracing::info!(color = buffer[1], "invalid color");
+        return Command::Closed;
+    }
+
+    let command = Command::deserialize(buffer);
+
+    tracing::debug!(?command, "command read");
+
+    command
+}
+
+#[derive(Debug)]
+pub enum ServerError {
+    Config(ConfigError),
+    Identity(String),
+    Journal(JournalError),
+    Ratings(std::io::Error),
+    Credentials(std::io::Error),
+    ExportCertificateHash(std::io::Error),
+    Bind(std::io::Error),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerError::Config(error) => write!(f, "invalid config: {}", error),
+            ServerError::Identity(error) => write!(f, "failed to load tls identity: {}", error),
+            ServerError::Journal(error) => write!(f, "failed to load game journal: {}", error),
+            ServerError::Ratings(error) => write!(f, "failed to load ratings: {}", error),
+            ServerError::Credentials(error) => {
+                write!(f, "failed to load credential secret: {}", error)
+            }
+            ServerError::ExportCertificateHash(error) => {
+                write!(f, "failed to export certificate hash: {}", error)
+            }
+            ServerError::Bind(error) => write!(f, "failed to bind server: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+/// Holds one of the server's connection slots until the session ends
+struct ConnectionSlot(Arc<AtomicU64>);
+
+impl ConnectionSlot {
+    fn acquire(connections: &Arc<AtomicU64>, limit: Option<u64>) -> Option<Self> {
+        let previous = connections.fetch_add(1, Ordering::SeqCst);
+        let slot = ConnectionSlot(connections.clone());
+
+        match limit {
+            Some(limit) if previous >= limit => None,
+            _ => Some(slot),
+        }
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Only a single game is hosted for now
+const GAME_ID: u64 = 1;
+
+/// Opens a game's journal and rebuilds the game from it, or starts a fresh game if it has no journal
+fn load_game(
+    config: &ServerConfig,
+    game_id: u64,
+) -> Result<(Option<Journal>, GameState), JournalError> {
+    let fresh_game = GameState::new(config.columns, config.win_size);
+    if config.journal_dir.is_none() {
+        return Ok((None, fresh_game));
+    }
+    let journal_dir = config.journal_dir.as_ref().unwrap();
+
+    std::fs::create_dir_all(journal_dir).map_err(JournalError::Io)?;
+    let path = journal_dir.join(format!("game-{}.journal", game_id));
+    let (mut journal, records) = Journal::open(&path, config.journal_fsync)?;
+
+    if records.is_empty() {
+        journal
+            .append(&Record::Create {
+                columns: config.columns,
+                win_size: config.win_size,
+            })
+            .map_err(JournalError::Io)?;
+        return Ok((Some(journal), fresh_game));
+    }
+
+    tracing::info!(
+        path = %path.display(),
+        records = records.len(),
+        "replaying journal"
+    );
+    let game_state = GameState::replay(records)?;
+
+    Ok((Some(journal), game_state))
+}
+
+/// SHA-256 hash of the server's certificate, which clients can pin instead of validating a chain
+pub type CertificateHash = [u8; 32];
+
+pub fn format_certificate_hash(hash: &CertificateHash) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+async fn load_identity(identity: &IdentityConfig) -> Result<Identity, ServerError> {
+    let identity = match identity {
+        IdentityConfig::PemFiles {
+            cert_path,
+            key_path,
+        } => Identity::load_pemfiles(cert_path, key_path)
+            .await
+            .map_err(|error| ServerError::Identity(error.to_string()))?,
+        IdentityConfig::SelfSigned { subject_alt_names } => {
+            Identity::self_signed(subject_alt_names)
+                .map_err(|error| ServerError::Identity(error.to_string()))?
+        }
+    };
+
+    Ok(identity)
+}
+
+/// A running server, which keeps serving until it's shut down
+#[derive(Debug)]
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    websocket_addr: Option<SocketAddr>,
+    metrics_addr: Option<SocketAddr>,
+    certificate_hash: CertificateHash,
+    metrics: Arc<Metrics>,
+    shutdown_tx: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
 
-                    log::debug!("Read from stream! Attempting to deserialize into command...");
+impl ServerHandle {
+    /// Address the server is listening on, with the real port if the configured port was 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Address WebSocket clients connect to, if WebSocket is turned on
+    pub fn websocket_addr(&self) -> Option<SocketAddr> {
+        self.websocket_addr
+    }
+
+    /// Address metrics, health and readiness are served on, if the metrics listener is turned on
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+
+    pub fn certificate_hash(&self) -> CertificateHash {
+        self.certificate_hash
+    }
+
+    /// Depth of the queues views wait in before being written to each client
+    pub fn outbound_metrics(&self) -> OutboundMetrics {
+        self.metrics.outbound.snapshot()
+    }
+
+    /// Stops accepting sessions, sends every client a `Closed` view, flushes the game journal and
+    /// waits for every session to finish
+    pub async fn shutdown(self) {
+        self.shutdown_tx.send_replace(true);
+        self.task.await.unwrap();
+    }
+}
+
+/// Everything a session needs to take part in a game, whatever transport it arrived over
+#[derive(Clone)]
+struct SessionContext {
+    game_id: u64,
+    tx: mpsc::Sender<Actions>,
+    broadcast_tx: mpsc::Sender<Broadcast>,
+    broadcast_channels: BroadcastChannels,
+    shutdown_rx: watch::Receiver<bool>,
+    resume_grace_period: Duration,
+    outbox: OutboxConfig,
+    /// Pairs players who'd rather be matched than join this game, if the server has one
+    matchmaker: Option<mpsc::Sender<MatchmakerActions>>,
+    limits: Arc<Limits>,
+    /// Issues and checks the credentials players identify with, if the server has a secret
+    credentials: Option<Arc<Credentials>>,
+    /// Turns away players who haven't identified themselves
+    require_credentials: bool,
+}
+
+/// The tasks running a game
+struct GameTasks {
+    game_actor: JoinHandle<()>,
+    broadcaster: JoinHandle<()>,
+}
+
+impl GameTasks {
+    /// Stops the game, flushing its journal, then the broadcaster once the last session has let go
+    /// of the context
+    async fn stop(self, context: SessionContext) {
+        let (stopped_tx, stopped_rx) = oneshot::channel();
+        context
+            .tx
+            .send(Actions::Shutdown(stopped_tx))
+            .await
+            .unwrap();
+        stopped_rx.await.unwrap();
+        self.game_actor.await.unwrap();
 
-                    let command = Command::deserialize(buffer.to_vec());
+        drop(context);
+        self.broadcaster.await.unwrap();
+    }
+}
 
-                    log::debug!("Deserialized into command! {:?}", command);
+/// Starts a game's actor and broadcaster, returning the context sessions reach the game through
+fn spawn_game(
+    config: &ServerConfig,
+    game_id: u64,
+    mut journal: Option<Journal>,
+    game_state: GameState,
+    shutdown_rx: watch::Receiver<bool>,
+    events: Option<mpsc::UnboundedSender<GameEvent>>,
+    metrics: Arc<Metrics>,
+) -> (SessionContext, GameTasks) {
+    let leave_policy = config.leave_policy;
+    let timeout_penalty = config.timeout_penalty;
+    let mut turn_clock = TurnClock::new(config.move_timeout, config.clock, config.clock_increment);
+    let resume_grace_period = config.resume_grace_period;
+    let (seats, max_players) = (config.seats, config.max_players);
+    let colors = config.colors.clone();
+    let (bot_strategy, bot_move_delay) = (config.bot, config.bot_move_delay);
+    let chat_filter = config.chat_filter.clone();
+    let mut chat_limiter = ChatLimiter::new(config.chat_rate_limit);
 
-                    match command {
-                        Command::PlayCoin(column) => {
-                            tx.send(Actions::PlayCoin(column, player_id)).await.unwrap();
+    let size = config.channel_size;
+    let (game_action_tx, mut game_action_rx) = mpsc::channel(size);
+    let (broadcast_tx, mut broadcast_rx) = mpsc::channel::<Broadcast>(size);
 
-                            let (view_tx, view_rx) = oneshot::channel();
-                            tx.send(Actions::Snapshot(view_tx)).await.unwrap();
-                            let mut snapshot = view_rx.await.unwrap();
+    // Players replayed from the journal, or with seats held for them, get the same grace period to
+    // resume as a dropped connection
+    for (player_id, session) in game_state.sessions.iter() {
+        let leave = Actions::Leave(*player_id, session.disconnects);
+        let tx = game_action_tx.clone();
+        let mut shutdown_rx = shutdown_rx.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(resume_grace_period) => {}
+                _ = shutdown_rx.changed() => return,
+            }
+            // A game everyone has already left may have stopped
+            let _ = tx.send(leave).await;
+        });
+    }
+
+    let broadcast_channels: BroadcastChannels = Arc::new(RwLock::new(HashMap::new()));
+    let game_span = tracing::info_span!("game", game_id);
+
+    // Broadcast thread, writes views to every client in the order they were sent
+    let channels = broadcast_channels.clone();
+    let broadcaster_metrics = metrics.clone();
+    let broadcaster = async move {
+        while let Some((payload, sent_from)) = broadcast_rx.recv().await {
+            let span = tracing::debug_span!(parent: &sent_from, "broadcast", view = payload[0]);
+            let started = Instant::now();
+            broadcast(&channels, payload).instrument(span).await;
+            broadcaster_metrics.broadcast(started.elapsed());
+        }
+    }
+    .instrument(game_span.clone());
+    let broadcaster = tokio::spawn(broadcaster);
 
-                            let serialized = View::serialize(View::Snapshot(SnapshotViewData {
-                                snapshot: &mut snapshot.0,
-                                winner_id: snapshot.1,
-                                col_count: snapshot.2,
-                                row_count: snapshot.3,
+    // Game action thread, receive events from other threads to read/write game state
+    let game_broadcast_tx = broadcast_tx.clone();
+    let bot_tx = game_action_tx.clone();
+    let game_metrics = metrics.clone();
+    metrics.game_started();
+    let game_actor = async move {
+        let metrics = game_metrics;
+        let broadcast_tx = game_broadcast_tx;
+        let GameState {
+            mut game_data,
+            mut sequence,
+            mut players,
+            mut turns,
+            mut next_player_id,
+            mut sessions,
+            mut tokens,
+            mut score,
+            mut next_columns,
+            mut profiles,
+            bots: replayed_bots,
+        } = game_state;
+        let mut spectators: u64 = 0;
+        let mut rematch_votes: HashSet<u64> = HashSet::new();
+        // Seated bots, each with the sender handing it its turn
+        let mut bots: HashMap<u64, mpsc::UnboundedSender<()>> = replayed_bots
+            .into_iter()
+            .map(|(player_id, strategy)| {
+                let color = players[&player_id].color.clone();
+                let bot = spawn_bot(
+                    bot_tx.clone(),
+                    player_id,
+                    color,
+                    strategy.bot(),
+                    bot_move_delay,
+                );
+                (player_id, bot)
+            })
+            .collect();
+        let mut bot_turn: Option<(u64, u64)> = None;
+
+        // A replayed bot already on turn is handed it straight away, nothing else may come along to
+        if let Some(player_id) = on_turn(&game_data.0, &turns).filter(|id| bots.contains_key(id)) {
+            bot_turn = Some((player_id, turns.taken));
+            let _ = bots[&player_id].send(());
+        }
+
+        turn_clock.sync(on_turn(&game_data.0, &turns), Instant::now());
+
+        loop {
+            let action = tokio::select! {
+                action = game_action_rx.recv() => action,
+                player_id = turn_expiry(turn_clock.deadline()) => Some(Actions::TurnExpired(player_id)),
+            };
+            if action.is_none() {
+                panic!("action is none");
+            }
+            let action = action.unwrap();
+
+            // A random move is played as if the player had dropped the coin themselves
+            let action = match action {
+                Actions::TurnExpired(player_id)
+                    if timeout_penalty == TimeoutPenalty::RandomMove =>
+                {
+                    turn_clock.stop(Instant::now(), Duration::ZERO);
+                    if turn_clock.exhausted(player_id) {
+                        Actions::TurnExpired(player_id)
+                    } else {
+                        let column = rand::thread_rng().gen_range(0..game_data.1.len() as u64);
+                        tracing::info!(player_id, column, "turn expired, playing a random move");
+                        Actions::PlayCoin(column, player_id)
+                    }
+                }
+                action => action,
+            };
+
+            // Everything the action leads to, broadcasts included, is traced under its span
+            let span = action.span();
+            let stopped = async {
+                match action {
+                    Actions::PlayCoin(column, player_id) => {
+                        tracing::info!("player dropped coin");
+
+                        let player = players.get(&player_id);
+                        if player.is_none() {
+                            tracing::info!("player coin rejected, no longer seated");
+                            return false;
+                        }
+                        let player = player.unwrap();
+
+                        let result = handle_play_coin(
+                            &mut game_data.0,
+                            &mut game_data.1,
+                            &mut game_data.2,
+                            &mut turns,
+                            player,
+                            column,
+                        );
+
+                        if let Err(error) = &result {
+                            tracing::info!(?error, "player coin rejected");
+                            metrics.move_rejected(error);
+                        }
+
+                        if let Ok(row) = result {
+                            record(&mut journal, Record::PlayCoin { player_id, column });
+                            metrics.move_placed();
+                            turn_clock.moved(Instant::now());
+                            sequence += 1;
+
+                            let coin_placed = View::serialize(View::CoinPlaced(CoinPlacedViewData {
+                                sequence,
+                                column,
+                                row,
+                                color: player.color.serialize(),
+                                player_id,
+                                winner_id: game_data.0.winner_id,
                             }));
+                            broadcast_tx.send((coin_placed, Span::current())).await.unwrap();
+                        }
+                    }
+                    Actions::Snapshot(encoding, view_tx) => {
+                        tracing::debug!("snapshot requested");
+
+                        view_tx
+                            .send(snapshot_view(
+                                &game_data.0,
+                                &game_data.1,
+                                sequence,
+                                encoding,
+                            ))
+                            .unwrap();
+                    }
+                    Actions::Join(connection_id, profile, color, view_tx) => {
+                        // A player who has proven who they are gets their own seat back, taking it over
+                        // from whichever connection held it
+                        let identity = profile.as_ref().and_then(|profile| profile.identity);
+                        let seated = profiles
+                            .iter()
+                            .find(|(_, seated)| identity.is_some() && seated.identity == identity)
+                            .map(|(player_id, _)| *player_id);
+                        if let Some(player_id) = seated {
+                            let session = sessions.get_mut(&player_id);
+                            let player = players.get(&player_id);
+                            if let (Some(session), Some(player)) = (session, player) {
+                                Span::current().record("player_id", player_id);
+                                tracing::info!("player took over their seat");
+
+                                let previous_connection_id =
+                                    session.connection_id.replace(connection_id);
+                                let seat = Seat {
+                                    player_id,
+                                    color: player.color.clone(),
+                                    token: session.token,
+                                };
+
+                                // View - PlayerInfo, the player may have picked another name
+                                let profile = profile.unwrap();
+                                if profiles[&player_id].name != profile.name {
+                                    let info =
+                                        set_profile(&mut journal, &mut profiles, player, profile);
+                                    broadcast_tx.send((info, Span::current())).await.unwrap();
+                                }
+
+                                view_tx.send(Ok((seat, previous_connection_id))).unwrap();
+                                return false;
+                            }
+                        }
+
+                        // A bot's seat is given up to the player, below
+                        let humans = turns.len() - bots.len();
+                        if seats.is_some_and(|seats| humans as u64 >= seats) {
+                            tracing::info!("player join rejected, game full");
+                            view_tx.send(Err(CloseReason::GameFull)).unwrap();
+                            return false;
+                        }
+                        if max_players.is_some_and(|max| (players.len() - bots.len()) as u64 >= max) {
+                            tracing::info!("player join rejected, server full");
+                            view_tx.send(Err(CloseReason::ServerFull)).unwrap();
+                            return false;
+                        }
+
+                        let player_id = next_player_id;
+                        next_player_id += 1;
+
+                        Span::current().record("player_id", player_id);
+                        tracing::info!("player joined");
+
+                        let color = free_color(&colors, &players, player_id, color.as_ref());
+                        let player = Player::from_color(player_id, color);
+                        players.insert(player_id, player.clone());
+                        turns.seat(player_id);
+
+                        let token: ResumeToken = rand::random();
+                        record(
+                            &mut journal,
+                            Record::Join {
+                                player_id,
+                                color: player.color.clone(),
+                                token,
+                            },
+                        );
+                        sessions.insert(
+                            player_id,
+                            Session {
+                                token,
+                                connection_id: Some(connection_id),
+                                disconnects: 0,
+                            },
+                        );
+                        tokens.insert(token, player_id);
+
+                        // View - PlayerInfo
+                        if let Some(profile) = profile {
+                            let info = set_profile(&mut journal, &mut profiles, &player, profile);
+                            broadcast_tx.send((info, Span::current())).await.unwrap();
+                        }
+
+                        let seat = Seat {
+                            player_id,
+                            color: player.color,
+                            token,
+                        };
+                        view_tx.send(Ok((seat, None))).unwrap();
+                    }
+                    Actions::Resume(token, connection_id, profile, view_tx) => {
+                        let resumed = tokens.get(&token).and_then(|player_id| {
+                            let session = sessions.get_mut(player_id)?;
+                            let player = players.get(player_id)?;
+
+                            tracing::info!(player_id, "player resumed");
+
+                            let previous_connection_id = session.connection_id.replace(connection_id);
+                            let seat = Seat {
+                                player_id: *player_id,
+                                color: player.color.clone(),
+                                token: session.token,
+                            };
+
+                            Some((seat, previous_connection_id, player))
+                        });
+                        if resumed.is_none() {
+                            view_tx.send(None).unwrap();
+                            return false;
+                        }
+                        let (seat, previous_connection_id, player) = resumed.unwrap();
+
+                        // View - PlayerInfo, for a player who hasn't said who they are yet, as when
+                        // taking a seat held for them by the matchmaker
+                        if let Some(profile) = profile {
+                            if !profiles.contains_key(&seat.player_id) {
+                                let info = set_profile(&mut journal, &mut profiles, player, profile);
+                                broadcast_tx.send((info, Span::current())).await.unwrap();
+                            }
+                        }
+
+                        view_tx.send(Some((seat, previous_connection_id))).unwrap();
+                    }
+                    Actions::Disconnect(player_id, connection_id, disconnect_tx) => {
+                        let session = sessions.get_mut(&player_id);
+
+                        // Only the connection currently attached to the session can disconnect it
+                        let disconnects = match session {
+                            Some(session) if session.connection_id == Some(connection_id) => {
+                                tracing::info!("player disconnected");
+
+                                session.connection_id = None;
+                                session.disconnects += 1;
+
+                                Some(session.disconnects)
+                            }
+                            _ => None,
+                        };
+
+                        disconnect_tx.send(disconnects).unwrap();
+                    }
+                    Actions::Leave(player_id, disconnects) => {
+                        let session = sessions.get(&player_id);
+
+                        // The player may have resumed, or resumed and dropped again, during the grace period
+                        let expired = match session {
+                            Some(session) => {
+                                session.connection_id.is_none() && session.disconnects == disconnects
+                            }
+                            None => false,
+                        };
+
+                        if expired {
+                            tracing::info!(?leave_policy, "player left");
+
+                            match leave_policy {
+                                LeavePolicy::HoldSeat => {}
+                                LeavePolicy::SkipTurn => {
+                                    turns.unseat(player_id);
+                                    players.remove(&player_id);
+                                }
+                                LeavePolicy::Forfeit => {
+                                    turns.unseat(player_id);
+                                    players.remove(&player_id);
+
+                                    if last_seated_wins(&mut game_data.0, &turns) {
+                                        sequence += 1;
+
+                                        let snapshot = snapshot_view(
+                                            &game_data.0,
+                                            &game_data.1,
+                                            sequence,
+                                            SnapshotEncoding::Dense,
+                                        );
+                                        broadcast_tx.send((snapshot, Span::current())).await.unwrap();
+                                    }
+                                }
+                            }
+
+                            // A held seat can still be resumed, anything else is gone for good
+                            if leave_policy != LeavePolicy::HoldSeat {
+                                record(
+                                    &mut journal,
+                                    Record::Leave {
+                                        player_id,
+                                        forfeit: leave_policy == LeavePolicy::Forfeit,
+                                    },
+                                );
+                                let session = sessions.remove(&player_id).unwrap();
+                                tokens.remove(&session.token);
+                            }
+
+                            if sessions.is_empty() {
+                                if let Some(events) = &events {
+                                    let _ = events.send(GameEvent::Abandoned);
+                                }
+                            }
+                        }
+                    }
+                    Actions::Spectate(spectators_tx) => {
+                        spectators += 1;
+                        tracing::info!(spectators, "spectator joined");
+                        spectators_tx.send(spectators).unwrap();
+                    }
+                    Actions::StopSpectating(spectators_tx) => {
+                        spectators -= 1;
+                        tracing::info!(spectators, "spectator left");
+                        spectators_tx.send(spectators).unwrap();
+                    }
+                    Actions::Status(views_tx) => {
+                        let mut views = Vec::new();
+                        if turn_clock.is_timed() {
+                            views.push(turn_clock.view(turns.seated(), Instant::now()));
+                        }
+                        if score.round > 0 || !score.wins.is_empty() {
+                            views.push(score.view());
+                        }
+
+                        let mut player_ids: Vec<&u64> = profiles.keys().collect();
+                        player_ids.sort();
+                        for player_id in player_ids {
+                            views.push(player_info_view(&players[player_id], &profiles[player_id]));
+                        }
+
+                        views_tx.send(views).unwrap();
+                    }
+                    Actions::Chat(player_id, message) => {
+                        if message.is_empty() || !players.contains_key(&player_id) {
+                            tracing::info!("chat rejected");
+                            return false;
+                        }
+                        if !chat_limiter.allow(player_id, Instant::now()) {
+                            tracing::info!("chat rate limited");
+                            return false;
+                        }
+
+                        let message = match &chat_filter {
+                            Some(chat_filter) => chat_filter.filter(player_id, &message),
+                            None => Some(message),
+                        };
+                        if message.is_none() {
+                            tracing::info!("chat dropped by filter");
+                            return false;
+                        }
+                        let message = message.unwrap();
+
+                        tracing::debug!(message, "chat");
+
+                        // View - Chat
+                        let chat = View::serialize(View::Chat(ChatViewData {
+                            player_id,
+                            timestamp: unix_millis(),
+                            message,
+                        }));
+                        broadcast_tx.send((chat, Span::current())).await.unwrap();
+                    }
+                    Actions::ChangeColor(player_id, color) => {
+                        // Coins already dropped keep their colour, so it's only up for change before then
+                        let started = game_data.1.iter().any(|column| !column.is_empty());
+                        let taken = players
+                            .values()
+                            .any(|player| player.id != player_id && player.color == color);
+                        if started || taken || !colors.contains(&color) {
+                            tracing::info!(started, taken, "color change rejected");
+                            return false;
+                        }
+                        let player = players.get_mut(&player_id);
+                        if player.is_none() {
+                            tracing::info!("color change rejected, no longer seated");
+                            return false;
+                        }
+                        let player = player.unwrap();
+
+                        tracing::info!("player changed color");
+                        player.color = color.clone();
+                        record(&mut journal, Record::Color { player_id, color });
+
+                        // View - ColorChanged
+                        let changed = View::serialize(View::ColorChanged(ColorChangedViewData {
+                            player_id,
+                            color: player.color.serialize(),
+                        }));
+                        broadcast_tx.send((changed, Span::current())).await.unwrap();
+                    }
+                    Actions::Rematch(player_id) => {
+                        if game_data.0.winner_id.is_none() || !players.contains_key(&player_id) {
+                            tracing::info!("rematch vote rejected");
+                            return false;
+                        }
+
+                        tracing::info!("rematch vote");
+                        rematch_votes.insert(player_id);
+
+                        // Every player still connected has to agree, a held seat doesn't get a say
+                        let voters: HashSet<u64> = players
+                            .keys()
+                            .filter(|id| sessions.get(*id).is_some_and(|s| s.connection_id.is_some()))
+                            .copied()
+                            .collect();
+                        rematch_votes.retain(|id| voters.contains(id));
+
+                        if rematch_votes.len() < voters.len() {
+                            // View - RematchVotes
+                            let votes = View::serialize(View::RematchVotes(RematchVotesViewData {
+                                votes: rematch_votes.len() as u64,
+                                needed: voters.len() as u64,
+                            }));
+                            broadcast_tx.send((votes, Span::current())).await.unwrap();
+                            return false;
+                        }
+
+                        record(&mut journal, Record::Rematch);
+                        rematch_votes.clear();
+
+                        let (game, coins, groups) = &mut game_data;
+                        start_rematch(
+                            game,
+                            coins,
+                            groups,
+                            &players,
+                            &mut turns,
+                            &mut score,
+                            next_columns.take(),
+                        );
+                        turn_clock.reset();
+                        sequence += 1;
 
-                            for broadcast in my_broadcast_channels.read().await.iter() {
-                                broadcast
-                                    .write()
-                                    .await
-                                    .write_all(&serialized)
-                                    .await
-                                    .unwrap();
+                        tracing::info!(round = score.round, seated = ?turns.seated(), "rematch started");
+
+                        // View - Snapshot of the empty board, then the Score with the new round
+                        let snapshot = snapshot_view(
+                            &game_data.0,
+                            &game_data.1,
+                            sequence,
+                            SnapshotEncoding::Dense,
+                        );
+                        broadcast_tx.send((snapshot, Span::current())).await.unwrap();
+                        broadcast_tx.send((score.view(), Span::current())).await.unwrap();
+                    }
+                    Actions::TurnExpired(player_id) => {
+                        turn_clock.stop(Instant::now(), Duration::ZERO);
+
+                        // A player out of time on their clock would time out the moment every later
+                        // turn starts, so they lose their seat whatever the penalty
+                        let penalty = match turn_clock.exhausted(player_id) {
+                            true => TimeoutPenalty::Forfeit,
+                            false => timeout_penalty,
+                        };
+                        tracing::info!(?penalty, "turn expired");
+
+                        match penalty {
+                            TimeoutPenalty::SkipTurn => {
+                                record(&mut journal, Record::Skip { player_id });
+                                turns.advance();
+                            }
+                            TimeoutPenalty::Forfeit => {
+                                record(&mut journal, Record::Forfeit { player_id });
+                                turns.unseat(player_id);
+
+                                if last_seated_wins(&mut game_data.0, &turns) {
+                                    sequence += 1;
+
+                                    let snapshot = snapshot_view(
+                                        &game_data.0,
+                                        &game_data.1,
+                                        sequence,
+                                        SnapshotEncoding::Dense,
+                                    );
+                                    broadcast_tx.send((snapshot, Span::current())).await.unwrap();
+                                }
+                            }
+                            // Played above, as a coin dropped by the player
+                            TimeoutPenalty::RandomMove => {}
+                        }
+                    }
+                    Actions::Ping(pong_tx) => {
+                        let _ = pong_tx.send(());
+                    }
+                    Actions::Admin(AdminAction::Players, reply_tx) => {
+                        let on_turn = on_turn(&game_data.0, &turns);
+                        let mut ids: Vec<&u64> = players.keys().collect();
+                        ids.sort();
+
+                        let mut lines: Vec<String> = ids
+                            .into_iter()
+                            .map(|player_id| {
+                                let status = match sessions.get(player_id) {
+                                    _ if bots.contains_key(player_id) => "bot",
+                                    Some(session) if session.connection_id.is_some() => "connected",
+                                    _ => "disconnected",
+                                };
+                                let mut line = format!(
+                                    "player {} {:?} {}",
+                                    player_id, players[player_id].color, status
+                                );
+                                if on_turn == Some(*player_id) {
+                                    line.push_str(" on-turn");
+                                }
+                                if let Some(profile) = profiles.get(player_id) {
+                                    line.push_str(&format!(" name {}", profile.name));
+                                }
+                                line
+                            })
+                            .collect();
+                        lines.push(format!("spectators {}", spectators));
+
+                        let _ = reply_tx.send(AdminReply::Lines(lines));
+                    }
+                    Actions::Admin(AdminAction::Kick(player_id), reply_tx) => {
+                        // Bots have no session, dropping their turn sender stops them
+                        let connection_id = if bots.remove(&player_id).is_some() {
+                            None
+                        } else {
+                            let session = sessions.remove(&player_id);
+                            if session.is_none() {
+                                let _ = reply_tx.send(AdminReply::Error(format!(
+                                    "no player {} to kick",
+                                    player_id
+                                )));
+                                return false;
+                            }
+                            let session = session.unwrap();
+                            tokens.remove(&session.token);
+                            session.connection_id
+                        };
+
+                        tracing::info!(player_id, "player kicked");
+
+                        turns.unseat(player_id);
+                        players.remove(&player_id);
+                        // Forgotten now, so a bot taking the seat doesn't clash with the name
+                        profiles.remove(&player_id);
+                        rematch_votes.remove(&player_id);
+                        record(
+                            &mut journal,
+                            Record::Leave {
+                                player_id,
+                                forfeit: false,
+                            },
+                        );
+
+                        let left =
+                            View::serialize(View::PlayerLeft(PlayerLeftViewData { player_id }));
+                        broadcast_tx.send((left, Span::current())).await.unwrap();
+
+                        let _ = reply_tx.send(AdminReply::Kicked(connection_id));
+                    }
+                    Actions::Admin(action @ (AdminAction::Reset | AdminAction::End), reply_tx) => {
+                        // Ending the game sends every player away, bots leave once nobody is left
+                        if action == AdminAction::End {
+                            let player_ids: Vec<u64> = sessions.keys().copied().collect();
+                            for player_id in player_ids {
+                                let session = sessions.remove(&player_id).unwrap();
+                                tokens.remove(&session.token);
+                                turns.unseat(player_id);
+                                players.remove(&player_id);
+                                record(
+                                    &mut journal,
+                                    Record::Leave {
+                                        player_id,
+                                        forfeit: false,
+                                    },
+                                );
                             }
                         }
-                        Command::Closed => {
-                            println!("The client has closed the socket....");
+
+                        record(&mut journal, Record::Reset);
+                        rematch_votes.clear();
+
+                        let (game, coins, groups) = &mut game_data;
+                        start_rematch(
+                            game,
+                            coins,
+                            groups,
+                            &players,
+                            &mut turns,
+                            &mut score,
+                            next_columns.take(),
+                        );
+                        turn_clock.reset();
+                        sequence += 1;
+
+                        tracing::info!(round = score.round, seated = ?turns.seated(), "game reset");
+
+                        // View - Snapshot of the empty board, then the Score with the new round
+                        let snapshot = snapshot_view(
+                            &game_data.0,
+                            &game_data.1,
+                            sequence,
+                            SnapshotEncoding::Dense,
+                        );
+                        broadcast_tx.send((snapshot, Span::current())).await.unwrap();
+                        broadcast_tx.send((score.view(), Span::current())).await.unwrap();
+
+                        let reply = match action {
+                            AdminAction::End => AdminReply::Ended,
+                            _ => AdminReply::Lines(Vec::new()),
+                        };
+                        let _ = reply_tx.send(reply);
+                    }
+                    Actions::Admin(AdminAction::Columns(columns), reply_tx) => {
+                        if columns == 0 {
+                            let _ = reply_tx.send(AdminReply::Error(
+                                "the board needs at least one column".to_string(),
+                            ));
+                            return false;
                         }
-                    };
+
+                        tracing::info!(columns, "next board resized");
+                        record(&mut journal, Record::Resize { columns });
+                        next_columns = Some(columns);
+
+                        let _ = reply_tx.send(AdminReply::Lines(Vec::new()));
+                    }
+                    Actions::Admin(AdminAction::Dump, reply_tx) => {
+                        let (game, coins, groups) = &game_data;
+                        let mut lines = vec![
+                            format!(
+                                "columns {} next {}",
+                                coins.len(),
+                                next_columns.unwrap_or(coins.len() as u64)
+                            ),
+                            format!("winner {:?}", game.winner_id),
+                        ];
+
+                        for (index, column) in coins.iter().enumerate() {
+                            if column.is_empty() {
+                                continue;
+                            }
+                            let column: Vec<String> = column
+                                .iter()
+                                .map(|coin| format!("{:?}/{}", coin.color, coin.group))
+                                .collect();
+                            lines.push(format!("column {} {}", index, column.join(" ")));
+                        }
+
+                        let mut group_ids: Vec<&u64> = groups.keys().collect();
+                        group_ids.sort();
+                        for group_id in group_ids {
+                            let positions: Vec<String> = groups[group_id]
+                                .iter()
+                                .map(|(column, row)| format!("{},{}", column, row))
+                                .collect();
+                            lines.push(format!("group {} {}", group_id, positions.join(" ")));
+                        }
+
+                        let _ = reply_tx.send(AdminReply::Lines(lines));
+                    }
+                    Actions::Shutdown(stopped_tx) => {
+                        tracing::info!(sequence, "game stopping");
+
+                        if let Some(journal) = &mut journal {
+                            if let Err(error) = journal.flush() {
+                                tracing::error!(%error, "journal flush failed");
+                            }
+                        }
+
+                        stopped_tx.send(()).unwrap();
+                        metrics.game_stopped();
+                        return true;
+                    }
                 }
-            })
-            .await
-            .unwrap()
+
+                // Bots - one takes the empty seat opposite a player on their own, and gives it up to the
+                // next player to join or once nobody is left to play against
+                if let Some(strategy) = bot_strategy {
+                    let humans = turns.len() - bots.len();
+
+                    if humans == 1
+                        && bots.is_empty()
+                        && seats.is_none_or(|seats| (turns.len() as u64) < seats)
+                    {
+                        let player_id = next_player_id;
+                        next_player_id += 1;
+
+                        tracing::info!(player_id, ?strategy, "bot joined");
+
+                        let color = free_color(&colors, &players, player_id, None);
+                        let player = Player::from_color(player_id, color.clone());
+                        players.insert(player_id, player.clone());
+                        turns.seat(player_id);
+                        record(
+                            &mut journal,
+                            Record::Bot {
+                                player_id,
+                                color: color.clone(),
+                                strategy,
+                            },
+                        );
+
+                        // View - PlayerInfo
+                        let profile = Profile {
+                            name: format!("{:?} bot", strategy),
+                            identity: None,
+                        };
+                        let info = set_profile(&mut journal, &mut profiles, &player, profile);
+                        broadcast_tx.send((info, Span::current())).await.unwrap();
+
+                        let bot = spawn_bot(
+                            bot_tx.clone(),
+                            player_id,
+                            color,
+                            strategy.bot(),
+                            bot_move_delay,
+                        );
+                        bots.insert(player_id, bot);
+                    } else if humans != 1 && !bots.is_empty() {
+                        for (player_id, _) in bots.drain() {
+                            tracing::info!(player_id, "bot left");
+
+                            turns.unseat(player_id);
+                            players.remove(&player_id);
+                            record(
+                                &mut journal,
+                                Record::Leave {
+                                    player_id,
+                                    forfeit: false,
+                                },
+                            );
+
+                            let left =
+                                View::serialize(View::PlayerLeft(PlayerLeftViewData { player_id }));
+                            broadcast_tx.send((left, Span::current())).await.unwrap();
+                        }
+                    }
+                }
+
+                // Names are forgotten along with the players who went by them
+                profiles.retain(|player_id, _| players.contains_key(player_id));
+
+                // Hand a bot its turn, once per turn
+                let bot_on_turn = on_turn(&game_data.0, &turns).filter(|id| bots.contains_key(id));
+                if let Some(player_id) = bot_on_turn {
+                    if bot_turn != Some((player_id, turns.taken)) {
+                        bot_turn = Some((player_id, turns.taken));
+                        let _ = bots[&player_id].send(());
+                    }
+                }
+
+                // View - Score, once a game has been won
+                if score.count(&game_data.0) {
+                    broadcast_tx.send((score.view(), Span::current())).await.unwrap();
+
+                    if let Some(events) = &events {
+                        let _ = events.send(GameEvent::Won(game_data.0.winner_id.unwrap()));
+                    }
+                }
+
+                // View - Clock, whenever the turn passes to another player in a timed game
+                if turn_clock.sync(on_turn(&game_data.0, &turns), Instant::now())
+                    && turn_clock.is_timed()
+                {
+                    let clock = turn_clock.view(turns.seated(), Instant::now());
+                    broadcast_tx.send((clock, Span::current())).await.unwrap();
+                }
+
+                false
+            }
+            .instrument(span)
+            .await;
+            if stopped {
+                break;
+            }
+        }
+    }
+    .instrument(game_span);
+    let game_actor = tokio::spawn(game_actor);
+
+    let context = SessionContext {
+        game_id,
+        tx: game_action_tx,
+        broadcast_tx,
+        broadcast_channels,
+        shutdown_rx,
+        resume_grace_period,
+        outbox: OutboxConfig {
+            size: config.outbound_queue_size,
+            policy: config.overflow_policy,
+            metrics: metrics.outbound.clone(),
+        },
+        matchmaker: None,
+        limits: Limits::new(config),
+        credentials: None,
+        require_credentials: config.require_credentials,
+    };
+
+    (
+        context,
+        GameTasks {
+            game_actor,
+            broadcaster,
+        },
+    )
+}
+
+/// How a player's wait for a match ended
+enum Queued {
+    Matched(Match),
+    /// The client went away, or the server doesn't do matchmaking
+    Left,
+    /// The client sent commands too quickly while waiting
+    RateLimited,
+    Shutdown,
+}
+
+/// Queues a player with the matchmaker, waiting until they're paired with an opponent
+async fn find_match(
+    context: &SessionContext,
+    connection_id: u64,
+    identity: Option<PlayerIdentity>,
+    preferences: MatchPreferences,
+    socket_rx: &mut dyn RecvHalf,
+    limiter: &mut SessionLimiter,
+) -> Queued {
+    if context.matchmaker.is_none() {
+        tracing::info!("matchmaking unavailable");
+        return Queued::Left;
+    }
+    let matchmaker = context.matchmaker.as_ref().unwrap();
+
+    let (reply, matched) = oneshot::channel();
+    let ticket = Ticket {
+        connection_id,
+        identity,
+        preferences,
+        reply,
+    };
+    matchmaker
+        .send(MatchmakerActions::FindMatch(ticket))
+        .await
+        .unwrap();
+
+    let mut shutdown = context.shutdown_rx.clone();
+    tokio::pin!(matched);
+    loop {
+        tokio::select! {
+            found = &mut matched => match found {
+                Ok(found) => return Queued::Matched(found),
+                // The matchmaker only gives up on players as it stops
+                Err(_) => return Queued::Shutdown,
+            },
+            command = read_command(socket_rx) => match command {
+                Command::Closed => {
+                    tracing::info!("client stopped waiting for a match");
+                    matchmaker
+                        .send(MatchmakerActions::Cancel(connection_id))
+                        .await
+                        .unwrap();
+                    return Queued::Left;
+                }
+                command if !limiter.allow(Instant::now()) => {
+                    tracing::info!(?command, "client rate limited while matching");
+                    matchmaker
+                        .send(MatchmakerActions::Cancel(connection_id))
+                        .await
+                        .unwrap();
+                    return Queued::RateLimited;
+                }
+                command => {
+                    tracing::info!(?command, "unexpected command while matching");
+                }
+            },
+            _ = shutdown.changed() => return Queued::Shutdown,
+        }
+    }
+}
+
+/// The profile a client identifies with, issuing it a credential if it hasn't one yet, or `None`
+/// if its credential wasn't signed by this server
+fn identify(
+    context: &SessionContext,
+    outbox: &Outbox,
+    name: String,
+    credential: Option<Credential>,
+) -> Option<Profile> {
+    // Control characters would let a name mess with whoever displays it
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let mut name = name.trim().to_string();
+    // The journal's longest record is sized for names held to the limit
+    while name.len() as u64 > MAX_NAME_LEN {
+        name.pop();
+    }
+
+    if context.credentials.is_none() {
+        return Some(Profile {
+            name,
+            identity: None,
         });
     }
+    let credentials = context.credentials.as_ref().unwrap();
+
+    let identity = match credential {
+        Some(credential) => credentials.verify(&credential)?,
+        None => {
+            let identity: PlayerIdentity = rand::random();
+            tracing::info!("credential issued");
+
+            // View - Credential, for the client to keep and identify with next time
+            let credential = credentials.issue(identity);
+            outbox
+                .push(View::serialize(View::Credential(CredentialViewData { credential })).into());
+            identity
+        }
+    };
+
+    Some(Profile {
+        name,
+        identity: Some(identity),
+    })
+}
+
+/// Runs a client's session from its handshake until it disconnects or the server shuts down
+async fn run_session(
+    context: SessionContext,
+    connection_id: u64,
+    peer: Option<IpAddr>,
+    socket_tx: Box<dyn SendHalf>,
+    mut socket_rx: Box<dyn RecvHalf>,
+) {
+    let (outbox, writer) = Outbox::spawn(socket_tx, &context.outbox);
+
+    // Held for as long as the session runs
+    let limiter = context.limits.acquire(peer);
+    if limiter.is_none() {
+        // View - Closed, the address has used up its sessions
+        tracing::info!("session rejected, too many sessions from the address");
+        outbox.close(CloseReason::TooManySessions);
+        return;
+    }
+    let mut limiter: SessionLimiter = limiter.unwrap();
+
+    // Snapshot encoding, identity and colour - all optional, in any order ahead of the handshake
+    let mut encoding = SnapshotEncoding::default();
+    let mut profile: Option<Profile> = None;
+    let mut color: Option<Color> = None;
+    let mut handshake = read_command(socket_rx.as_mut()).await;
+    loop {
+        // Rate limits - every command counts, the handshake included
+        if !matches!(handshake, Command::Closed) && !limiter.allow(Instant::now()) {
+            tracing::info!(command = ?handshake, "client rate limited, disconnecting");
+            outbox.close(CloseReason::RateLimited);
+            return;
+        }
+
+        match handshake {
+            Command::SnapshotEncoding(requested) => encoding = requested,
+            Command::ChangeColor(requested) => color = Some(requested),
+            Command::Identify(name, credential) => {
+                let identified = identify(&context, &outbox, name, credential);
+                if identified.is_none() {
+                    // View - Closed, the credential wasn't one the server signed
+                    tracing::info!("session rejected, invalid credential");
+                    outbox.close(CloseReason::NotAuthenticated);
+                    return;
+                }
+                profile = identified;
+            }
+            _ => break,
+        }
+        handshake = read_command(socket_rx.as_mut()).await;
+    }
+    let identity = profile.as_ref().and_then(|profile| profile.identity);
+
+    let player_handshake = matches!(
+        handshake,
+        Command::Join | Command::Resume(_) | Command::FindMatch(..)
+    );
+    if context.require_credentials && player_handshake && identity.is_none() {
+        // View - Closed, only identified players can take a seat
+        tracing::info!("session rejected, not identified");
+        outbox.close(CloseReason::NotAuthenticated);
+        return;
+    }
+
+    // Matchmaking - wait to be paired, then take the seat held in the new game as if resuming it
+    let mut context = context;
+    if let Command::FindMatch(preferences) = handshake {
+        // Only an identity proven by a credential is rated, anyone else plays unrated
+        match find_match(
+            &context,
+            connection_id,
+            identity,
+            preferences,
+            socket_rx.as_mut(),
+            &mut limiter,
+        )
+        .await
+        {
+            // View - MatchFound
+            Queued::Matched(found) => {
+                outbox.push(found.view.into());
+                handshake = Command::Resume(found.token);
+                context = found.context;
+            }
+            Queued::Left => return,
+            // View - Closed
+            Queued::RateLimited => {
+                outbox.close(CloseReason::RateLimited);
+                return;
+            }
+            // View - Closed
+            Queued::Shutdown => {
+                outbox.close(CloseReason::ServerShutdown);
+                return;
+            }
+        }
+    }
+
+    let SessionContext {
+        game_id,
+        tx,
+        broadcast_tx,
+        broadcast_channels,
+        shutdown_rx: mut shutdown,
+        resume_grace_period,
+        ..
+    } = context;
+    Span::current().record("game_id", game_id);
+
+    // Handshake - Join game as a new player, resume an existing player's seat, or spectate
+    let joined = match handshake {
+        Command::Join => Some(request_join(&tx, connection_id, profile, color).await),
+        Command::Resume(token) => {
+            let (resume_tx, resume_rx) = oneshot::channel();
+            tx.send(Actions::Resume(
+                token,
+                connection_id,
+                profile.clone(),
+                resume_tx,
+            ))
+            .await
+            .unwrap();
+
+            match resume_rx.await.unwrap() {
+                Some(resumed) => Some(Ok(resumed)),
+                None => {
+                    tracing::info!("resume token not recognised, joining as a new player");
+                    Some(request_join(&tx, connection_id, profile, color).await)
+                }
+            }
+        }
+        Command::Spectate => None,
+        command => {
+            tracing::info!(?command, "invalid handshake");
+            return;
+        }
+    };
+
+    let seat = match joined {
+        Some(Ok((seat, previous_connection_id))) => {
+            // Take over from a connection that hasn't noticed it has dropped yet, or from another
+            // client the same player left connected
+            if let Some(previous_connection_id) = previous_connection_id {
+                let previous = broadcast_channels
+                    .write()
+                    .await
+                    .remove(&previous_connection_id);
+                if let Some(previous) = previous {
+                    // View - Closed
+                    previous.kick(CloseReason::Replaced);
+                }
+            }
+
+            Span::current().record("player_id", seat.player_id);
+            Some(seat)
+        }
+        // View - Closed, there's no room for another player
+        Some(Err(reason)) => {
+            outbox.close(reason);
+            return;
+        }
+        None => None,
+    };
+
+    let greeting = match &seat {
+        // View - Joined game
+        Some(seat) => View::serialize(View::Joined(JoinedViewData {
+            player_id: seat.player_id,
+            color: seat.color.serialize(),
+            token: seat.token,
+        })),
+        // View - Spectators, everyone else hears about the new spectator below
+        None => request_spectators(&tx, Actions::Spectate).await,
+    };
+
+    // View - Snapshot
+    let snapshot = request_snapshot(&tx, encoding).await;
+
+    // View - Clock and Score, when the game has them
+    let status = request_status(&tx).await;
+
+    let mut written = outbox.push(greeting.clone().into()) && outbox.push(snapshot.into());
+    for view in status {
+        written = written && outbox.push(view.into());
+    }
+
+    if written {
+        if seat.is_none() {
+            broadcast_tx
+                .send((greeting, Span::current()))
+                .await
+                .unwrap();
+        }
+
+        // Register for broadcasting
+        broadcast_channels
+            .write()
+            .await
+            .insert(connection_id, outbox.clone());
+
+        // Command loop, ingests http3 streams, creating actions to pass to worker threads
+        // and views to reply with
+        loop {
+            let command = tokio::select! {
+                command = read_command(socket_rx.as_mut()) => command,
+                _ = outbox.overflowed() => match outbox.policy() {
+                    OverflowPolicy::Resync => {
+                        // View - Snapshot, covering every view dropped from the full queue
+                        let snapshot = request_snapshot(&tx, encoding).await;
+                        outbox.push(snapshot.into());
+                        continue;
+                    }
+                    OverflowPolicy::Disconnect => {
+                        tracing::info!("client too slow, disconnecting");
+                        outbox.count_disconnect();
+                        writer.abort();
+                        break;
+                    }
+                },
+                // View - Closed, already queued by the admin who kicked the connection
+                _ = outbox.kicked() => {
+                    tracing::info!("client kicked");
+                    break;
+                }
+                _ = shutdown.changed() => {
+                    // View - Closed, the server is going away but seats are kept for
+                    // players to resume once it's back
+                    outbox.close(CloseReason::ServerShutdown);
+                    drop(outbox);
+                    let _ = writer.await;
+                    return;
+                }
+            };
+
+            // Rate limits - enforced before the command gets anywhere near the game
+            if !matches!(command, Command::Closed) && !limiter.allow(Instant::now()) {
+                tracing::info!(?command, "client rate limited, disconnecting");
+                outbox.close(CloseReason::RateLimited);
+                break;
+            }
+
+            match (command, &seat) {
+                (Command::PlayCoin(column), Some(seat)) => {
+                    tx.send(Actions::PlayCoin(column, seat.player_id))
+                        .await
+                        .unwrap();
+                }
+                (Command::PlayCoin(_), None) => {
+                    tracing::info!("spectator coin rejected");
+                }
+                (Command::Rematch, Some(seat)) => {
+                    tx.send(Actions::Rematch(seat.player_id)).await.unwrap();
+                }
+                (Command::Chat(message), Some(seat)) => {
+                    tx.send(Actions::Chat(seat.player_id, message))
+                        .await
+                        .unwrap();
+                }
+                (Command::Chat(_), None) => {
+                    tracing::info!("spectator chat rejected");
+                }
+                (Command::ChangeColor(color), Some(seat)) => {
+                    tx.send(Actions::ChangeColor(seat.player_id, color))
+                        .await
+                        .unwrap();
+                }
+                (Command::Resync, _) => {
+                    // View - Snapshot, for a client that has missed a view
+                    let snapshot = request_snapshot(&tx, encoding).await;
+                    if !outbox.push(snapshot.into()) {
+                        break;
+                    }
+                }
+                (Command::SnapshotEncoding(requested), _) => {
+                    encoding = requested;
+                }
+                (Command::Identify(..), _) => {
+                    tracing::info!("identify rejected, only allowed ahead of the handshake");
+                }
+                (Command::Closed, _) if socket_rx.frame_too_large() => {
+                    // View - Closed, letting the client know why its stream was cut off
+                    tracing::info!("client sent a frame too large, disconnecting");
+                    outbox.close(CloseReason::FrameTooLarge);
+                    break;
+                }
+                (Command::Closed, _) => {
+                    tracing::info!("client closed the stream");
+                    break;
+                }
+                (command, _) => {
+                    tracing::info!(?command, "unexpected command");
+                }
+            };
+        }
+    }
+
+    // Deregister, the writer finishes off whatever is still queued, then let the remaining players know
+    broadcast_channels.write().await.remove(&connection_id);
+    drop(outbox);
+
+    if seat.is_none() {
+        let spectators = request_spectators(&tx, Actions::StopSpectating).await;
+        broadcast_tx
+            .send((spectators, Span::current()))
+            .await
+            .unwrap();
+        return;
+    }
+    let player_id = seat.unwrap().player_id;
+
+    let (disconnect_tx, disconnect_rx) = oneshot::channel();
+    tx.send(Actions::Disconnect(player_id, connection_id, disconnect_tx))
+        .await
+        .unwrap();
+    let disconnects = disconnect_rx.await.unwrap();
+    if disconnects.is_none() {
+        // Another connection has already resumed this player's seat
+        return;
+    }
+    let disconnects = disconnects.unwrap();
+
+    let left = View::serialize(View::PlayerLeft(PlayerLeftViewData { player_id }));
+    broadcast_tx.send((left, Span::current())).await.unwrap();
+
+    // Give the player a chance to resume before the leave policy is applied to their seat
+    tokio::select! {
+        _ = tokio::time::sleep(resume_grace_period) => {}
+        _ = shutdown.changed() => return,
+    }
+
+    tx.send(Actions::Leave(player_id, disconnects))
+        .await
+        .unwrap();
+}
+
+/// A connection waiting to be handed a session
+enum Incoming {
+    // Boxed, it's far bigger than a TCP stream
+    WebTransport(Box<IncomingSession>),
+    WebSocket(TcpStream, SocketAddr),
+}
+
+/// Accepts the next WebSocket connection, or waits forever when WebSocket is turned off
+async fn accept_websocket(
+    listener: &Option<TcpListener>,
+) -> std::io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Upgrades a tcp connection to a WebSocket and runs its session, refusing the upgrade when
+/// there's no connection slot left
+// The error response type in the handshake callback is set by tungstenite
+#[allow(clippy::result_large_err)]
+async fn run_websocket_session(
+    context: SessionContext,
+    connection_id: u64,
+    stream: TcpStream,
+    address: SocketAddr,
+    connections: Arc<AtomicU64>,
+    max_connections: Option<u64>,
+    max_frame_size: usize,
+) {
+    // Held for as long as this thread runs
+    let slot = ConnectionSlot::acquire(&connections, max_connections);
+
+    let websocket_config = WebSocketConfig {
+        max_frame_size: Some(max_frame_size),
+        max_message_size: Some(max_frame_size),
+        ..WebSocketConfig::default()
+    };
+    let callback = |_: &Request, response: Response| {
+        if slot.is_none() {
+            tracing::info!("session rejected, too many connections");
+            let mut rejected = ErrorResponse::new(None);
+            *rejected.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+            return Err(rejected);
+        }
+        Ok(response)
+    };
+    let socket = accept_hdr_async_with_config(stream, callback, Some(websocket_config)).await;
+    if let Err(error) = socket {
+        tracing::info!(%error, "websocket handshake failed");
+        return;
+    }
+    let (socket_tx, socket_rx) = transport::websocket(socket.unwrap());
+
+    run_session(
+        context,
+        connection_id,
+        Some(address.ip().to_canonical()),
+        Box::new(socket_tx),
+        Box::new(socket_rx),
+    )
+    .await;
+}
+
+/// Covers a client's session from the moment it connects, naming the game and player once it has
+/// a seat or is spectating
+fn session_span(connection_id: u64, transport: &'static str) -> Span {
+    tracing::info_span!(
+        "session",
+        connection_id,
+        transport,
+        game_id = tracing::field::Empty,
+        player_id = tracing::field::Empty,
+    )
+}
+
+pub async fn start_server(config: ServerConfig) -> Result<ServerHandle, ServerError> {
+    config.validate().map_err(ServerError::Config)?;
+
+    let max_connections = config.max_connections;
+    let max_frame_size = config.max_frame_size;
+    let shutdown_timeout = config.shutdown_timeout;
+
+    let (journal, game_state) = load_game(&config, GAME_ID).map_err(ServerError::Journal)?;
+    let ratings = Ratings::load(config.ratings_path.clone()).map_err(ServerError::Ratings)?;
+    let credentials = Credentials::load(config.credential_secret_path.as_deref())
+        .map_err(ServerError::Credentials)?;
+
+    let identity = load_identity(&config.identity).await?;
+
+    let certificate = identity.certificate_chain().as_slice().first();
+    let certificate_hash: CertificateHash = *certificate.unwrap().hash().as_ref();
+    tracing::info!(
+        certificate_hash = format_certificate_hash(&certificate_hash),
+        "certificate loaded"
+    );
+    if let Some(cert_hash_path) = &config.cert_hash_path {
+        std::fs::write(cert_hash_path, format_certificate_hash(&certificate_hash))
+            .map_err(ServerError::ExportCertificateHash)?;
+    }
+
+    let transport_config = WTransportServerConfig::builder()
+        .with_bind_address(config.bind_address)
+        .with_identity(&identity)
+        .keep_alive_interval(Some(config.keep_alive_interval))
+        .max_idle_timeout(Some(config.idle_timeout))
+        .map_err(|_| ServerError::Config(ConfigError::InvalidIdleTimeout))?
+        .build();
+    let server = Endpoint::server(transport_config).map_err(ServerError::Bind)?;
+    let local_addr = server.local_addr().map_err(ServerError::Bind)?;
+
+    let websocket = match config.websocket_bind_address {
+        Some(address) => Some(
+            TcpListener::bind(address)
+                .await
+                .map_err(ServerError::Bind)?,
+        ),
+        None => None,
+    };
+    let websocket_addr = match &websocket {
+        Some(listener) => Some(listener.local_addr().map_err(ServerError::Bind)?),
+        None => None,
+    };
+
+    let metrics_listener = match config.metrics_bind_address {
+        Some(address) => Some(
+            TcpListener::bind(address)
+                .await
+                .map_err(ServerError::Bind)?,
+        ),
+        None => None,
+    };
+    let metrics_addr = match &metrics_listener {
+        Some(listener) => Some(listener.local_addr().map_err(ServerError::Bind)?),
+        None => None,
+    };
+
+    let admin_listener = match &config.admin_socket_path {
+        Some(path) => Some(bind_admin(path).map_err(ServerError::Bind)?),
+        None => None,
+    };
+    let admin_socket_path = config.admin_socket_path.clone();
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    let metrics = Arc::new(Metrics::default());
+    let (mut context, game_tasks) = spawn_game(
+        &config,
+        GAME_ID,
+        journal,
+        game_state,
+        shutdown_rx.clone(),
+        None,
+        metrics.clone(),
+    );
+    let (matchmaker_tx, matchmaker) = spawn_matchmaker(
+        &config,
+        ratings,
+        GAME_ID + 1,
+        shutdown_rx.clone(),
+        metrics.clone(),
+    );
+    context.matchmaker = Some(matchmaker_tx.clone());
+    context.credentials = Some(Arc::new(credentials));
+
+    let metrics_server = metrics_listener.map(|listener| {
+        tokio::spawn(serve_metrics(
+            listener,
+            metrics.clone(),
+            context.tx.clone(),
+            shutdown_rx.clone(),
+        ))
+    });
+
+    // Validated to have a token along with the socket
+    let admin_server = admin_listener.map(|listener| {
+        let token = config.admin_token.clone().unwrap();
+        tokio::spawn(serve_admin(listener, context.clone(), token))
+    });
+
+    let connections = metrics.sessions.clone();
+    let task = tokio::spawn(async move {
+        let mut next_connection_id: u64 = 0;
+        let mut sessions = JoinSet::new();
+        let mut stop_accepting = shutdown_rx;
+
+        //  Main loop, keep accepting new connections on either transport until shutdown
+        loop {
+            let incoming = tokio::select! {
+                incoming_session = server.accept() => {
+                    Incoming::WebTransport(Box::new(incoming_session))
+                }
+                accepted = accept_websocket(&websocket) => match accepted {
+                    Ok((stream, address)) => Incoming::WebSocket(stream, address),
+                    Err(error) => {
+                        tracing::info!(%error, "websocket accept failed");
+                        continue;
+                    }
+                },
+                _ = stop_accepting.changed() => break,
+            };
+
+            // Forget sessions that have already finished
+            while sessions.try_join_next().is_some() {}
+
+            let connection_id = next_connection_id;
+            next_connection_id += 1;
+
+            let context = context.clone();
+            let connections = connections.clone();
+
+            let incoming_session = match incoming {
+                Incoming::WebTransport(incoming_session) => *incoming_session,
+                Incoming::WebSocket(stream, address) => {
+                    let span = session_span(connection_id, "websocket");
+                    span.in_scope(|| tracing::info!(%address, "websocket connection"));
+                    let session = run_websocket_session(
+                        context,
+                        connection_id,
+                        stream,
+                        address,
+                        connections,
+                        max_connections,
+                        max_frame_size,
+                    );
+                    sessions.spawn(session.instrument(span));
+                    continue;
+                }
+            };
+
+            // Start a thread for each new session
+            let session = async move {
+                let session_request = incoming_session.await;
+                if let Err(error) = session_request {
+                    tracing::info!(%error, "session request failed");
+                    return;
+                }
+                let session_request = session_request.unwrap();
+                let peer = session_request.remote_address().ip().to_canonical();
+
+                // Held for as long as this thread runs
+                let slot = ConnectionSlot::acquire(&connections, max_connections);
+                if slot.is_none() {
+                    tracing::info!("session rejected, too many connections");
+                    session_request.too_many_requests().await;
+                    return;
+                }
+
+                let connection = session_request.accept().await;
+                if let Err(error) = connection {
+                    tracing::info!(%error, "session accept failed");
+                    return;
+                }
+                // Dropping the connection closes it, so it's held until the session is over
+                let connection = connection.unwrap();
+                let stream = connection.accept_bi().await;
+                if let Err(error) = stream {
+                    tracing::info!(%error, "stream accept failed");
+                    return;
+                }
+                let (socket_tx, socket_rx) = stream.unwrap();
+
+                run_session(
+                    context,
+                    connection_id,
+                    Some(peer),
+                    Box::new(socket_tx),
+                    Box::new(socket_rx),
+                )
+                .await;
+            };
+            sessions.spawn(session.instrument(session_span(connection_id, "webtransport")));
+        }
+
+        // Shutdown - every session closes its own stream, then the games stop and flush their journal
+        tracing::info!(sessions = sessions.len(), "server shutting down");
+
+        let drain = async { while sessions.join_next().await.is_some() {} };
+        if tokio::time::timeout(shutdown_timeout, drain).await.is_err() {
+            tracing::info!("sessions still running after shutdown timeout, aborting");
+            sessions.shutdown().await;
+        }
+
+        // Admin connections let go of the hosted game before it stops
+        if let Some(admin_server) = admin_server {
+            admin_server.abort();
+            let _ = admin_server.await;
+        }
+        if let Some(path) = admin_socket_path {
+            let _ = std::fs::remove_file(path);
+        }
+
+        // Matched games stop first, then the hosted game
+        stop_matchmaker(&matchmaker_tx, matchmaker).await;
+        game_tasks.stop(context).await;
+
+        server.close(VarInt::from_u32(0), b"shutdown");
+        server.wait_idle().await;
+
+        if let Some(metrics_server) = metrics_server {
+            metrics_server.abort();
+        }
+
+        tracing::info!("server stopped");
+    });
+
+    Ok(ServerHandle {
+        local_addr,
+        websocket_addr,
+        metrics_addr,
+        certificate_hash,
+        metrics,
+        shutdown_tx,
+        task,
+    })
 }
 
-// #[cfg(test)]
-// mod test;
+#[cfg(test)]
+mod test;
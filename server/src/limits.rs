@@ -0,0 +1,181 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+
+use crate::ServerConfig;
+
+/// A steady rate of commands, with room for a burst of them at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub per_sec: u64,
+    /// Commands that can be sent back to back before the rate applies, defaults to the rate
+    #[serde(default)]
+    pub burst: Option<u64>,
+}
+
+/// Fills up at the limit's rate to hold at most its burst, every command takes a token
+#[derive(Debug)]
+struct TokenBucket {
+    per_sec: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        let burst = limit.burst.unwrap_or(limit.per_sec) as f64;
+
+        TokenBucket {
+            per_sec: limit.per_sec as f64,
+            burst,
+            tokens: burst,
+            refilled: now,
+        }
+    }
+
+    /// Whether the bucket has refilled, so forgetting it lets nothing through that it wouldn't
+    fn is_full(&self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens + elapsed * self.per_sec >= self.burst
+    }
+
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.burst);
+        self.refilled = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+
+        true
+    }
+}
+
+/// Sessions open from an address, and the commands they share between them
+#[derive(Debug)]
+struct Address {
+    sessions: u64,
+    bucket: Option<TokenBucket>,
+}
+
+impl Address {
+    /// An address without sessions is only kept until its bucket refills, so reconnecting doesn't
+    /// get a client a fresh one
+    fn is_idle(&self, now: Instant) -> bool {
+        self.sessions == 0
+            && self
+                .bucket
+                .as_ref()
+                .is_none_or(|bucket| bucket.is_full(now))
+    }
+}
+
+/// The server's limits on its clients, shared by every session
+#[derive(Debug)]
+pub(crate) struct Limits {
+    connection_rate_limit: Option<RateLimit>,
+    ip_rate_limit: Option<RateLimit>,
+    max_sessions_per_ip: Option<u64>,
+    addresses: Mutex<HashMap<IpAddr, Address>>,
+}
+
+impl Limits {
+    pub(crate) fn new(config: &ServerConfig) -> Arc<Self> {
+        Arc::new(Limits {
+            connection_rate_limit: config.connection_rate_limit,
+            ip_rate_limit: config.ip_rate_limit,
+            max_sessions_per_ip: config.max_sessions_per_ip,
+            addresses: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Takes a session slot for a client, `None` if its address already has as many sessions as it's
+    /// allowed. Clients without an address, connected in process, are only limited by connection.
+    pub(crate) fn acquire(self: &Arc<Self>, peer: Option<IpAddr>) -> Option<SessionLimiter> {
+        let now = Instant::now();
+        let mut limiter = SessionLimiter {
+            bucket: self
+                .connection_rate_limit
+                .map(|limit| TokenBucket::new(limit, now)),
+            peer: None,
+            limits: self.clone(),
+        };
+        if peer.is_none() {
+            return Some(limiter);
+        }
+        let peer = peer.unwrap();
+
+        let mut addresses = self.addresses.lock().unwrap();
+        addresses.retain(|_, address| !address.is_idle(now));
+        let address = addresses.entry(peer).or_insert_with(|| Address {
+            sessions: 0,
+            bucket: self.ip_rate_limit.map(|limit| TokenBucket::new(limit, now)),
+        });
+        if self
+            .max_sessions_per_ip
+            .is_some_and(|max_sessions| address.sessions >= max_sessions)
+        {
+            return None;
+        }
+        address.sessions += 1;
+        limiter.peer = Some(peer);
+
+        Some(limiter)
+    }
+}
+
+/// Holds a session's slot until it ends, counting its commands against its own and its address's
+/// rate limits
+#[derive(Debug)]
+pub(crate) struct SessionLimiter {
+    bucket: Option<TokenBucket>,
+    peer: Option<IpAddr>,
+    limits: Arc<Limits>,
+}
+
+impl SessionLimiter {
+    /// Counts a command, unless the connection or its address is sending them too quickly
+    pub(crate) fn allow(&mut self, now: Instant) -> bool {
+        if let Some(bucket) = &mut self.bucket {
+            if !bucket.take(now) {
+                return false;
+            }
+        }
+
+        if let Some(peer) = self.peer {
+            let mut addresses = self.limits.addresses.lock().unwrap();
+            let bucket = addresses
+                .get_mut(&peer)
+                .and_then(|address| address.bucket.as_mut());
+            if let Some(bucket) = bucket {
+                return bucket.take(now);
+            }
+        }
+
+        true
+    }
+}
+
+impl Drop for SessionLimiter {
+    fn drop(&mut self) {
+        if self.peer.is_none() {
+            return;
+        }
+        let peer = self.peer.unwrap();
+
+        // The address is forgotten along with its last session, once its bucket has refilled
+        let mut addresses = self.limits.addresses.lock().unwrap();
+        if let Some(address) = addresses.get_mut(&peer) {
+            address.sessions -= 1;
+            if address.is_idle(Instant::now()) {
+                addresses.remove(&peer);
+            }
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+use tracing::Instrument;
+
+use crate::config::ServerConfig;
+use crate::metrics::Metrics;
+use crate::{
+    spawn_game, GameState, LeavePolicy, MatchFoundViewData, ResumeToken, SessionContext, View,
+};
+
+/// Opaque id the server issues a player in their credential, ratings are kept against it
+pub type PlayerIdentity = [u8; 16];
+
+/// Rating every player starts from
+const INITIAL_RATING: f64 = 1500.0;
+/// Most a single game can move a rating by
+const K_FACTOR: f64 = 32.0;
+/// Rating difference players are paired across straight away
+const RATING_WINDOW: f64 = 100.0;
+/// How much further the rating window reaches for every second a player has waited
+const RATING_WINDOW_GROWTH: f64 = 50.0;
+/// How often waiting players are looked over again, as their rating windows widen
+const PAIRING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The board a player would like to be matched on, `None` for anything the server offers
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MatchPreferences {
+    pub columns: Option<u64>,
+    pub win_size: Option<u64>,
+}
+
+impl MatchPreferences {
+    fn agrees(&self, other: &Self) -> bool {
+        let agrees = |a: Option<u64>, b: Option<u64>| a.is_none() || b.is_none() || a == b;
+        agrees(self.columns, other.columns) && agrees(self.win_size, other.win_size)
+    }
+
+    /// The preferences both players share, anything neither cares about is left open
+    fn merge(&self, other: &Self) -> Self {
+        MatchPreferences {
+            columns: self.columns.or(other.columns),
+            win_size: self.win_size.or(other.win_size),
+        }
+    }
+}
+
+/// A player's Elo rating and how many rated games it's built on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Rating {
+    pub(crate) rating: f64,
+    pub(crate) games: u64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating {
+            rating: INITIAL_RATING,
+            games: 0,
+        }
+    }
+}
+
+/// New ratings for the winner and loser of a game
+pub(crate) fn elo(winner: f64, loser: f64) -> (f64, f64) {
+    let expected = 1.0 / (1.0 + 10f64.powf((loser - winner) / 400.0));
+    let change = K_FACTOR * (1.0 - expected);
+
+    (winner + change, loser - change)
+}
+
+fn encode_identity(identity: &PlayerIdentity) -> String {
+    identity
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn decode_identity(input: &str) -> Option<PlayerIdentity> {
+    if input.len() != 32 || !input.is_ascii() {
+        return None;
+    }
+
+    let mut identity = [0; 16];
+    for (index, byte) in identity.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&input[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(identity)
+}
+
+/// Every player's rating, kept in a file so they carry over restarts.
+///
+/// The file has a line per player of `<identity hex> <rating> <games>`.
+#[derive(Debug, Default)]
+pub(crate) struct Ratings {
+    path: Option<PathBuf>,
+    ratings: HashMap<PlayerIdentity, Rating>,
+}
+
+impl Ratings {
+    /// Reads the ratings file, a missing file starts everyone from scratch
+    pub(crate) fn load(path: Option<PathBuf>) -> std::io::Result<Self> {
+        let mut ratings = HashMap::new();
+        if path.is_none() {
+            return Ok(Ratings { path, ratings });
+        }
+
+        let input = match std::fs::read_to_string(path.as_ref().unwrap()) {
+            Ok(input) => input,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+
+        for (index, line) in input.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let rating = match fields[..] {
+                [identity, rating, games] => Some((
+                    decode_identity(identity),
+                    rating.parse().ok(),
+                    games.parse().ok(),
+                )),
+                _ => None,
+            };
+
+            match rating {
+                Some((Some(identity), Some(rating), Some(games))) => {
+                    ratings.insert(identity, Rating { rating, games });
+                }
+                _ => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("invalid rating on line {}", index + 1),
+                    ))
+                }
+            }
+        }
+
+        Ok(Ratings { path, ratings })
+    }
+
+    pub(crate) fn get(&self, identity: &PlayerIdentity) -> Rating {
+        self.ratings.get(identity).copied().unwrap_or_default()
+    }
+
+    /// Moves both ratings for a decided game, and writes them out
+    pub(crate) fn record_win(&mut self, winner: &PlayerIdentity, loser: &PlayerIdentity) {
+        let (winner_rating, loser_rating) = (self.get(winner), self.get(loser));
+        let (won, lost) = elo(winner_rating.rating, loser_rating.rating);
+
+        self.ratings.insert(
+            *winner,
+            Rating {
+                rating: won,
+                games: winner_rating.games + 1,
+            },
+        );
+        self.ratings.insert(
+            *loser,
+            Rating {
+                rating: lost,
+                games: loser_rating.games + 1,
+            },
+        );
+
+        if let Err(error) = self.save() {
+            tracing::error!(%error, "ratings save failed");
+        }
+    }
+
+    /// Writes every rating to a temporary file, then swaps it in so a crash never leaves half a file
+    fn save(&self) -> std::io::Result<()> {
+        if self.path.is_none() {
+            return Ok(());
+        }
+        let path = self.path.as_ref().unwrap();
+
+        let mut output = String::new();
+        for (identity, rating) in self.ratings.iter() {
+            output.push_str(&format!(
+                "{} {} {}\n",
+                encode_identity(identity),
+                rating.rating,
+                rating.games
+            ));
+        }
+
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, output)?;
+        std::fs::rename(&temporary, path)
+    }
+}
+
+/// Something that happened in a game that whoever started it wants to hear about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GameEvent {
+    /// A game, or a rematch of it, was won by this player
+    Won(u64),
+    /// Every player has left, nothing more can happen
+    Abandoned,
+}
+
+/// A player waiting to be matched
+pub(crate) struct Ticket {
+    pub(crate) connection_id: u64,
+    /// Proven by the player's credential, without one the player isn't rated
+    pub(crate) identity: Option<PlayerIdentity>,
+    pub(crate) preferences: MatchPreferences,
+    pub(crate) reply: oneshot::Sender<Match>,
+}
+
+/// A seat held for a matched player in their new game
+pub(crate) struct Match {
+    pub(crate) context: SessionContext,
+    pub(crate) token: ResumeToken,
+    /// A `MatchFound` view for the player
+    pub(crate) view: Vec<u8>,
+}
+
+pub(crate) enum MatchmakerActions {
+    FindMatch(Ticket),
+    /// The player's connection went away while they were waiting
+    Cancel(u64),
+    Shutdown(oneshot::Sender<()>),
+}
+
+pub(crate) struct Waiting {
+    pub(crate) ticket: Ticket,
+    pub(crate) rating: f64,
+    pub(crate) since: Instant,
+}
+
+impl Waiting {
+    /// How far from their own rating the player will accept an opponent, growing as they wait
+    fn window(&self, now: Instant) -> f64 {
+        RATING_WINDOW + RATING_WINDOW_GROWTH * (now - self.since).as_secs_f64()
+    }
+}
+
+/// The closest rated pair of waiting players who'd accept each other, if any
+pub(crate) fn pair(queue: &[Waiting], now: Instant) -> Option<(usize, usize)> {
+    let mut best: Option<(usize, usize, f64)> = None;
+
+    for (a, first) in queue.iter().enumerate() {
+        for (b, second) in queue.iter().enumerate().skip(a + 1) {
+            let gap = (first.rating - second.rating).abs();
+            let same_player =
+                first.ticket.identity.is_some() && first.ticket.identity == second.ticket.identity;
+            let acceptable = !same_player
+                && first.ticket.preferences.agrees(&second.ticket.preferences)
+                && gap <= first.window(now).min(second.window(now));
+
+            if acceptable && best.is_none_or(|(_, _, best_gap)| gap < best_gap) {
+                best = Some((a, b, gap));
+            }
+        }
+    }
+
+    best.map(|(a, b, _)| (a, b))
+}
+
+/// Starts a game for two matched players with both seats held for them, returning the task that
+/// rates the players as the game is won and stops it once they've both left
+fn start_match(
+    config: &ServerConfig,
+    game_id: u64,
+    players: [Waiting; 2],
+    ratings: &Arc<Mutex<Ratings>>,
+    shutdown_rx: &watch::Receiver<bool>,
+    stop_rx: &watch::Receiver<bool>,
+    metrics: &Arc<Metrics>,
+) -> impl Future<Output = ()> {
+    let preferences = players[0]
+        .ticket
+        .preferences
+        .merge(&players[1].ticket.preferences);
+
+    // Rated games are two player games between people, and leaving one loses it
+    let mut match_config = config.clone();
+    match_config.columns = preferences
+        .columns
+        .unwrap_or(config.columns)
+        .min(config.max_match_columns);
+    match_config.win_size = preferences.win_size.or(config.win_size);
+    match_config.seats = Some(2);
+    match_config.leave_policy = LeavePolicy::Forfeit;
+    match_config.bot = None;
+
+    let mut game_state = GameState::new(match_config.columns, match_config.win_size);
+    let seats: Vec<(u64, ResumeToken)> = players
+        .iter()
+        .map(|_| game_state.hold_seat(&match_config.colors))
+        .collect();
+
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+    let (context, tasks) = spawn_game(
+        &match_config,
+        game_id,
+        None,
+        game_state,
+        shutdown_rx.clone(),
+        Some(events_tx),
+        metrics.clone(),
+    );
+
+    tracing::info!(
+        game_id,
+        first_rating = players[0].rating.round(),
+        second_rating = players[1].rating.round(),
+        "match started"
+    );
+
+    // A match is only rated when both players are
+    let identities: HashMap<u64, PlayerIdentity> = seats
+        .iter()
+        .zip(players.iter())
+        .filter_map(|((player_id, _), waiting)| Some((*player_id, waiting.ticket.identity?)))
+        .collect();
+
+    let player_ratings = [players[0].rating, players[1].rating];
+    for (index, (waiting, (_, token))) in players.into_iter().zip(seats).enumerate() {
+        let view = View::serialize(View::MatchFound(MatchFoundViewData {
+            game_id,
+            rating: waiting.rating.round() as u64,
+            opponent_rating: player_ratings[1 - index].round() as u64,
+            columns: match_config.columns,
+            win_size: match_config.win_size,
+            first: index == 0,
+        }));
+
+        // A player who went away just as they were matched forfeits once their seat times out
+        let _ = waiting.ticket.reply.send(Match {
+            context: context.clone(),
+            token,
+            view,
+        });
+    }
+
+    let ratings = ratings.clone();
+    let mut stop_rx = stop_rx.clone();
+    async move {
+        loop {
+            let event = tokio::select! {
+                event = events_rx.recv() => event,
+                _ = stop_rx.changed() => None,
+            };
+
+            match event {
+                Some(GameEvent::Won(winner_id)) => {
+                    let winner = identities.get(&winner_id);
+                    let loser = identities
+                        .iter()
+                        .find(|(player_id, _)| **player_id != winner_id)
+                        .map(|(_, identity)| identity);
+
+                    if let (Some(winner), Some(loser)) = (winner, loser) {
+                        tracing::info!(game_id, winner_id, "match won");
+                        ratings.lock().unwrap().record_win(winner, loser);
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        tracing::info!(game_id, "match stopping");
+        tasks.stop(context).await;
+    }
+}
+
+/// Starts the matchmaker, which pairs waiting players into games of their own from `first_game_id`
+/// upwards
+pub(crate) fn spawn_matchmaker(
+    config: &ServerConfig,
+    ratings: Ratings,
+    first_game_id: u64,
+    shutdown_rx: watch::Receiver<bool>,
+    metrics: Arc<Metrics>,
+) -> (mpsc::Sender<MatchmakerActions>, JoinHandle<()>) {
+    let config = config.clone();
+    let (tx, mut rx) = mpsc::channel(config.channel_size);
+
+    let matchmaker = async move {
+        let ratings = Arc::new(Mutex::new(ratings));
+        let mut queue: Vec<Waiting> = Vec::new();
+        let mut next_game_id = first_game_id;
+        let mut matches = JoinSet::new();
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let mut pairing = tokio::time::interval(PAIRING_INTERVAL);
+
+        loop {
+            tokio::select! {
+                action = rx.recv() => match action {
+                    Some(MatchmakerActions::FindMatch(ticket)) => {
+                        let rating = match &ticket.identity {
+                            Some(identity) => ratings.lock().unwrap().get(identity).rating,
+                            None => INITIAL_RATING,
+                        };
+                        tracing::info!(
+                            connection_id = ticket.connection_id,
+                            rating = rating.round(),
+                            "finding match"
+                        );
+
+                        queue.push(Waiting {
+                            ticket,
+                            rating,
+                            since: Instant::now(),
+                        });
+                    }
+                    Some(MatchmakerActions::Cancel(connection_id)) => {
+                        queue.retain(|waiting| waiting.ticket.connection_id != connection_id);
+                    }
+                    Some(MatchmakerActions::Shutdown(stopped_tx)) => {
+                        // Every session has finished by now, so every game can be stopped
+                        stop_tx.send_replace(true);
+                        while matches.join_next().await.is_some() {}
+
+                        stopped_tx.send(()).unwrap();
+                        break;
+                    }
+                    None => break,
+                },
+                _ = pairing.tick() => {}
+            }
+
+            // Forget finished games, and players who stopped waiting without saying so
+            while matches.try_join_next().is_some() {}
+            queue.retain(|waiting| !waiting.ticket.reply.is_closed());
+
+            while let Some((a, b)) = pair(&queue, Instant::now()) {
+                let second = queue.remove(b);
+                let first = queue.remove(a);
+
+                // Whoever goes first is down to chance
+                let players = match rand::random() {
+                    true => [first, second],
+                    false => [second, first],
+                };
+
+                let game = start_match(
+                    &config,
+                    next_game_id,
+                    players,
+                    &ratings,
+                    &shutdown_rx,
+                    &stop_rx,
+                    &metrics,
+                );
+                matches
+                    .spawn(game.instrument(tracing::info_span!("match", game_id = next_game_id)));
+                next_game_id += 1;
+            }
+        }
+    }
+    .instrument(tracing::info_span!("matchmaker"));
+    let matchmaker = tokio::spawn(matchmaker);
+
+    (tx, matchmaker)
+}
+
+/// Stops the matchmaker and every game it started, once every session has finished
+pub(crate) async fn stop_matchmaker(
+    tx: &mpsc::Sender<MatchmakerActions>,
+    matchmaker: JoinHandle<()>,
+) {
+    let (stopped_tx, stopped_rx) = oneshot::channel();
+    tx.send(MatchmakerActions::Shutdown(stopped_tx))
+        .await
+        .unwrap();
+    stopped_rx.await.unwrap();
+    matchmaker.await.unwrap();
+}
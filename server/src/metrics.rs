@@ -0,0 +1,249 @@
+use connect4000_core::GameError;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::outbox::QueueMetrics;
+use crate::Actions;
+
+// Upper bounds of the broadcast latency buckets, in seconds
+const BROADCAST_BUCKETS: [f64; 8] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5];
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_REQUEST_LEN: usize = 4096;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    // Also what `max_connections` is held against
+    pub(crate) sessions: Arc<AtomicU64>,
+    games: AtomicU64,
+    moves: AtomicU64,
+    rejected_moves: Mutex<BTreeMap<String, u64>>,
+    // In microseconds
+    broadcast_buckets: [AtomicU64; BROADCAST_BUCKETS.len()],
+    broadcast_sum: AtomicU64,
+    broadcast_count: AtomicU64,
+    pub(crate) outbound: Arc<QueueMetrics>,
+}
+
+impl Metrics {
+    pub(crate) fn game_started(&self) {
+        self.games.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn game_stopped(&self) {
+        self.games.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn move_placed(&self) {
+        self.moves.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn move_rejected(&self, error: &GameError) {
+        let mut rejected_moves = self.rejected_moves.lock().unwrap();
+        *rejected_moves.entry(format!("{:?}", error)).or_default() += 1;
+    }
+
+    pub(crate) fn broadcast(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bucket, bound) in self.broadcast_buckets.iter().zip(BROADCAST_BUCKETS) {
+            if secs <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.broadcast_sum
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.broadcast_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let mut output = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            writeln!(output, "# HELP connect4000_{} {}", name, help).unwrap();
+            writeln!(output, "# TYPE connect4000_{} {}", name, kind).unwrap();
+            writeln!(output, "connect4000_{} {}", name, value).unwrap();
+        };
+
+        let outbound = self.outbound.snapshot();
+        metric(
+            "sessions",
+            "gauge",
+            "Open sessions, players and spectators alike",
+            self.sessions.load(Ordering::Relaxed),
+        );
+        metric(
+            "games",
+            "gauge",
+            "Games running, hosted and matched",
+            self.games.load(Ordering::Relaxed),
+        );
+        metric(
+            "moves_total",
+            "counter",
+            "Coins placed across every game",
+            self.moves.load(Ordering::Relaxed),
+        );
+        metric(
+            "outbound_queued_views",
+            "gauge",
+            "Views waiting to be written, across every client",
+            outbound.queued_views,
+        );
+        metric(
+            "outbound_deepest_queue",
+            "gauge",
+            "The most views any one client has had waiting at once",
+            outbound.deepest_queue,
+        );
+        metric(
+            "outbound_dropped_views_total",
+            "counter",
+            "Views dropped from full outbound queues",
+            outbound.dropped_views,
+        );
+        metric(
+            "slow_disconnects_total",
+            "counter",
+            "Clients disconnected for letting their outbound queue fill up",
+            outbound.slow_disconnects,
+        );
+
+        output.push_str("# HELP connect4000_rejected_moves_total Coins turned down, by error\n");
+        output.push_str("# TYPE connect4000_rejected_moves_total counter\n");
+        for (error, count) in self.rejected_moves.lock().unwrap().iter() {
+            writeln!(
+                output,
+                "connect4000_rejected_moves_total{{error=\"{}\"}} {}",
+                error, count
+            )
+            .unwrap();
+        }
+
+        let count = self.broadcast_count.load(Ordering::Relaxed);
+        output.push_str(
+            "# HELP connect4000_broadcast_seconds Time taken to queue a view for every client\n",
+        );
+        output.push_str("# TYPE connect4000_broadcast_seconds histogram\n");
+        for (bucket, bound) in self.broadcast_buckets.iter().zip(BROADCAST_BUCKETS) {
+            writeln!(
+                output,
+                "connect4000_broadcast_seconds_bucket{{le=\"{}\"}} {}",
+                bound,
+                bucket.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+        writeln!(
+            output,
+            "connect4000_broadcast_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "connect4000_broadcast_seconds_sum {}",
+            self.broadcast_sum.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        )
+        .unwrap();
+        writeln!(output, "connect4000_broadcast_seconds_count {}", count).unwrap();
+
+        output
+    }
+}
+
+async fn game_alive(tx: &mpsc::Sender<Actions>) -> bool {
+    let (pong_tx, pong_rx) = oneshot::channel();
+    let ping = async {
+        tx.send(Actions::Ping(pong_tx)).await.ok()?;
+        pong_rx.await.ok()
+    };
+
+    matches!(tokio::time::timeout(PING_TIMEOUT, ping).await, Ok(Some(())))
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    metrics: &Metrics,
+    tx: &mpsc::Sender<Actions>,
+    shutdown_rx: &watch::Receiver<bool>,
+) {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+        }
+        if request.len() > MAX_REQUEST_LEN {
+            return;
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or("").split(' ');
+    let (method, path) = (request_line.next(), request_line.next());
+
+    let (status, content_type, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) => {
+            ("200 OK", "text/plain; version=0.0.4", metrics.render())
+        }
+        (Some("GET"), Some("/health")) => match game_alive(tx).await {
+            true => ("200 OK", "text/plain", "ok\n".to_string()),
+            false => (
+                "503 Service Unavailable",
+                "text/plain",
+                "game not responding\n".to_string(),
+            ),
+        },
+        (Some("GET"), Some("/ready")) => match !*shutdown_rx.borrow() && game_alive(tx).await {
+            true => ("200 OK", "text/plain", "ready\n".to_string()),
+            false => (
+                "503 Service Unavailable",
+                "text/plain",
+                "not ready\n".to_string(),
+            ),
+        },
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+// Serves until the task is aborted
+pub(crate) async fn serve_metrics(
+    listener: TcpListener,
+    metrics: Arc<Metrics>,
+    tx: mpsc::Sender<Actions>,
+    shutdown_rx: watch::Receiver<bool>,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                tracing::info!(%error, "metrics accept failed");
+                continue;
+            }
+        };
+
+        let metrics = metrics.clone();
+        let tx = tx.clone();
+        let shutdown_rx = shutdown_rx.clone();
+        tokio::spawn(async move {
+            let response = respond(stream, &metrics, &tx, &shutdown_rx);
+            let _ = tokio::time::timeout(REQUEST_TIMEOUT, response).await;
+        });
+    }
+}
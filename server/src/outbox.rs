@@ -0,0 +1,223 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+
+use crate::transport::SendHalf;
+use crate::{CloseReason, ClosedViewData, View};
+
+/// What happens when a client falls so far behind that its outbound queue is full
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Views that don't fit are dropped, and the client is sent a fresh snapshot once it catches up
+    #[default]
+    Resync,
+    /// The client is disconnected, free to resume its seat once it can keep up
+    Disconnect,
+}
+
+impl OverflowPolicy {
+    pub(crate) fn parse(input: &str) -> Option<Self> {
+        match input {
+            "resync" => Some(OverflowPolicy::Resync),
+            "disconnect" => Some(OverflowPolicy::Disconnect),
+            _ => None,
+        }
+    }
+}
+
+/// Outbound queue counters, shared by every connection
+#[derive(Debug, Default)]
+pub(crate) struct QueueMetrics {
+    queued: AtomicU64,
+    deepest: AtomicU64,
+    dropped: AtomicU64,
+    disconnected: AtomicU64,
+}
+
+impl QueueMetrics {
+    pub(crate) fn snapshot(&self) -> OutboundMetrics {
+        OutboundMetrics {
+            queued_views: self.queued.load(Ordering::Relaxed),
+            deepest_queue: self.deepest.load(Ordering::Relaxed),
+            dropped_views: self.dropped.load(Ordering::Relaxed),
+            slow_disconnects: self.disconnected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point in time view of every client's outbound queue
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OutboundMetrics {
+    /// Views waiting to be written, across every client
+    pub queued_views: u64,
+    /// The most views any one client has had waiting at once
+    pub deepest_queue: u64,
+    /// Views dropped from full queues
+    pub dropped_views: u64,
+    /// Clients disconnected for letting their queue fill up
+    pub slow_disconnects: u64,
+}
+
+/// How every connection's outbound queue is set up
+#[derive(Debug, Clone)]
+pub(crate) struct OutboxConfig {
+    pub(crate) size: usize,
+    pub(crate) policy: OverflowPolicy,
+    pub(crate) metrics: Arc<QueueMetrics>,
+}
+
+enum Outbound {
+    View(Arc<[u8]>),
+    Close(CloseReason),
+}
+
+/// A connection's bounded queue of views, written to its stream by a writer task of its own so a
+/// slow client never holds up anyone else
+#[derive(Clone)]
+pub(crate) struct Outbox {
+    tx: mpsc::Sender<Outbound>,
+    policy: OverflowPolicy,
+    overflowed: Arc<AtomicBool>,
+    overflow: Arc<Notify>,
+    kicked: Arc<Notify>,
+    /// Stops the writer, even part way through a write
+    stop: Arc<Notify>,
+    metrics: Arc<QueueMetrics>,
+}
+
+impl Outbox {
+    /// Starts a writer task draining a new queue into the stream
+    pub(crate) fn spawn(
+        mut socket_tx: Box<dyn SendHalf>,
+        config: &OutboxConfig,
+    ) -> (Self, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel(config.size);
+
+        let outbox = Outbox {
+            tx,
+            policy: config.policy,
+            overflowed: Arc::new(AtomicBool::new(false)),
+            overflow: Arc::new(Notify::new()),
+            kicked: Arc::new(Notify::new()),
+            stop: Arc::new(Notify::new()),
+            metrics: config.metrics.clone(),
+        };
+
+        let overflowed = outbox.overflowed.clone();
+        let overflow = outbox.overflow.clone();
+        let stop = outbox.stop.clone();
+        let metrics = outbox.metrics.clone();
+        let writer = tokio::spawn(async move {
+            let drain = async {
+                while let Some(outbound) = rx.recv().await {
+                    metrics.queued.fetch_sub(1, Ordering::Relaxed);
+
+                    match outbound {
+                        Outbound::View(view) => {
+                            if socket_tx.write_all(&view).await.is_err() {
+                                break;
+                            }
+                        }
+                        Outbound::Close(reason) => {
+                            let closed = View::serialize(View::Closed(ClosedViewData { reason }));
+                            if socket_tx.write_all(&closed).await.is_ok() {
+                                let _ = socket_tx.finish().await;
+                            }
+                            break;
+                        }
+                    }
+
+                    // Caught up after dropping views, time for a fresh snapshot
+                    if rx.is_empty() && overflowed.swap(false, Ordering::Relaxed) {
+                        overflow.notify_one();
+                    }
+                }
+            };
+            tokio::select! {
+                _ = drain => {}
+                _ = stop.notified() => {}
+            }
+
+            // Anything left unwritten is no longer queued
+            rx.close();
+            let mut unwritten = 0;
+            while rx.try_recv().is_ok() {
+                unwritten += 1;
+            }
+            metrics.queued.fetch_sub(unwritten, Ordering::Relaxed);
+        });
+
+        (outbox, writer)
+    }
+
+    /// Queues a view without waiting, returning false once the writer has stopped. A view that
+    /// doesn't fit is dropped and handled by the overflow policy.
+    pub(crate) fn push(&self, view: Arc<[u8]>) -> bool {
+        // Counted before sending, so the writer never takes away a view that hasn't been added
+        self.metrics.queued.fetch_add(1, Ordering::Relaxed);
+
+        match self.tx.try_send(Outbound::View(view)) {
+            Ok(()) => {
+                let depth = (self.tx.max_capacity() - self.tx.capacity()) as u64;
+                self.metrics.deepest.fetch_max(depth, Ordering::Relaxed);
+                true
+            }
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+                self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                match self.policy {
+                    // The writer asks for the snapshot once the queue has drained
+                    OverflowPolicy::Resync => self.overflowed.store(true, Ordering::Relaxed),
+                    OverflowPolicy::Disconnect => self.overflow.notify_one(),
+                }
+                true
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Resolves once the overflow policy needs the session to step in, to send a snapshot or to
+    /// disconnect
+    pub(crate) async fn overflowed(&self) {
+        self.overflow.notified().await
+    }
+
+    pub(crate) fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    pub(crate) fn count_disconnect(&self) {
+        self.metrics.disconnected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Queues a `Closed` view behind everything already queued, after which the stream is closed.
+    /// Never waits, a client too far behind to fit the view has its stream dropped as it is.
+    pub(crate) fn close(&self, reason: CloseReason) {
+        self.metrics.queued.fetch_add(1, Ordering::Relaxed);
+        match self.tx.try_send(Outbound::Close(reason)) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+                self.stop.notify_one();
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Closes the stream from outside the session, which stops reading commands once it's told
+    pub(crate) fn kick(&self, reason: CloseReason) {
+        self.close(reason);
+        self.kicked.notify_one();
+    }
+
+    /// Resolves once the connection has been kicked
+    pub(crate) async fn kicked(&self) {
+        self.kicked.notified().await
+    }
+}
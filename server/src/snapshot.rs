@@ -0,0 +1,217 @@
+use connect4000_core::{Coin, Coins, Color};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+/// How the coins in a snapshot are laid out, as negotiated with the client
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotEncoding {
+    /// A dense `columns * rows` grid, one byte per space
+    #[default]
+    Dense,
+    /// Runs of column heights, followed by runs of coin colours
+    Runs,
+    /// The `Runs` encoding, deflate compressed
+    DeflatedRuns,
+}
+
+impl SnapshotEncoding {
+    pub fn serialize(&self) -> u8 {
+        match self {
+            SnapshotEncoding::Dense => 0,
+            SnapshotEncoding::Runs => 1,
+            SnapshotEncoding::DeflatedRuns => 2,
+        }
+    }
+
+    pub fn deserialize(input: &u8) -> Option<Self> {
+        match input {
+            0 => Some(SnapshotEncoding::Dense),
+            1 => Some(SnapshotEncoding::Runs),
+            2 => Some(SnapshotEncoding::DeflatedRuns),
+            _ => None,
+        }
+    }
+}
+
+fn serialize_coin_column(column: &[Coin]) -> Vec<u8> {
+    let mut result = Vec::new();
+
+    for coin in column.iter() {
+        result.push(coin.color.serialize());
+    }
+
+    result
+}
+
+pub fn serialize_coins(coins: &Coins) -> (Vec<u8>, u64, u64) {
+    let mut data = Vec::new();
+
+    let number_of_columns: u64 = coins.len() as u64;
+    let number_of_rows: u64 = coins.iter().fold(0, |acc, column| {
+        if column.len() > acc {
+            column.len()
+        } else {
+            acc
+        }
+    }) as u64;
+
+    for column_index in 0..number_of_columns {
+        let v: Vec<Coin> = Vec::new();
+        let column = coins.get(column_index as usize).unwrap_or(&v);
+        let mut column_result = serialize_coin_column(column);
+        if column_result.len() > number_of_rows as usize {
+            panic!("invalid column serialize");
+        }
+        column_result.resize(number_of_rows as usize, 0);
+        data.append(&mut column_result);
+    }
+
+    (data, number_of_columns, number_of_rows)
+}
+
+/// Reads the dense grid `serialize_coins` produces back into columns of coins. Coins come back
+/// without their groups, which a snapshot doesn't carry.
+pub fn deserialize_coins(data: &[u8], col_count: u64, row_count: u64) -> Option<Coins> {
+    if col_count.checked_mul(row_count) != Some(data.len() as u64) {
+        return None;
+    }
+
+    let mut coins = Vec::new();
+    for column_index in 0..col_count as usize {
+        let start = column_index * row_count as usize;
+        let mut column = Vec::new();
+
+        for color in data[start..start + row_count as usize].iter() {
+            match color {
+                0 => break,
+                1..=5 => column.push(Coin {
+                    color: Color::deserialize(color),
+                    group: 0,
+                }),
+                _ => return None,
+            }
+        }
+        coins.push(column);
+    }
+
+    Some(coins)
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &[u8], offset: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+
+    loop {
+        if shift >= 64 {
+            return None;
+        }
+
+        let byte = *data.get(*offset)?;
+        *offset += 1;
+
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+
+        shift += 7;
+    }
+}
+
+/// Encodes coins as (height, repeat) runs of column heights, followed by runs of coin colours read
+/// column by column from the bottom up.
+///
+/// Every number is a LEB128 varint, and each colour run packs its length above the 3 bit colour, so
+/// long stretches of empty columns or same coloured coins only take a couple of bytes.
+pub fn serialize_coins_runs(coins: &Coins) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    let mut heights = coins.iter().map(|column| column.len() as u64).peekable();
+    while let Some(height) = heights.next() {
+        let mut repeat: u64 = 1;
+        while heights.next_if_eq(&height).is_some() {
+            repeat += 1;
+        }
+        write_varint(&mut data, height);
+        write_varint(&mut data, repeat);
+    }
+
+    let mut colors = coins
+        .iter()
+        .flatten()
+        .map(|coin| coin.color.serialize())
+        .peekable();
+    while let Some(color) = colors.next() {
+        let mut length: u64 = 1;
+        while colors.next_if_eq(&color).is_some() {
+            length += 1;
+        }
+        write_varint(&mut data, (length << 3) | color as u64);
+    }
+
+    data
+}
+
+/// Expands a `Runs` encoded body back into the dense grid `serialize_coins` produces
+pub fn deserialize_coins_runs(data: &[u8], col_count: u64, row_count: u64) -> Option<Vec<u8>> {
+    let mut offset = 0;
+
+    let mut heights: Vec<u64> = Vec::new();
+    while (heights.len() as u64) < col_count {
+        let height = read_varint(data, &mut offset)?;
+        let repeat = read_varint(data, &mut offset)?;
+        if height > row_count || repeat == 0 || heights.len() as u64 + repeat > col_count {
+            return None;
+        }
+        heights.resize(heights.len() + repeat as usize, height);
+    }
+
+    let mut grid = vec![0; (col_count * row_count) as usize];
+    let mut color = 0;
+    let mut remaining: u64 = 0;
+
+    for (column_index, height) in heights.iter().enumerate() {
+        for row_index in 0..*height {
+            if remaining == 0 {
+                let run = read_varint(data, &mut offset)?;
+                color = (run & 0b111) as u8;
+                remaining = run >> 3;
+                if remaining == 0 {
+                    return None;
+                }
+            }
+
+            grid[column_index * row_count as usize + row_index as usize] = color;
+            remaining -= 1;
+        }
+    }
+
+    if remaining != 0 || offset != data.len() {
+        return None;
+    }
+
+    Some(grid)
+}
+
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+pub fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decoder = DeflateDecoder::new(data);
+    let mut result = Vec::new();
+    decoder.read_to_end(&mut result).ok()?;
+    Some(result)
+}
@@ -0,0 +1,2449 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use connect4000_core::{Coin, Coins, Color, Game, Player};
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncBufReadExt, BufReader, DuplexStream};
+use tokio::net::{TcpListener, TcpStream, UnixStream};
+use tokio::sync::{oneshot, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::protocol::{Role, WebSocketConfig};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::Instrument;
+use wtransport::tls::Sha256Digest;
+
+use crate::admin::{bind_admin, serve_admin};
+use crate::clock::TurnClock;
+use crate::identity::{Credentials, Profile};
+use crate::journal::{FsyncPolicy, Journal, JournalError, Record};
+use crate::limits::Limits;
+use crate::matchmaking::{elo, pair, spawn_matchmaker, stop_matchmaker, Ratings, Ticket, Waiting};
+use crate::metrics::{serve_metrics, Metrics};
+use crate::snapshot::{
+    deflate, deserialize_coins_runs, inflate, serialize_coins, serialize_coins_runs,
+};
+use crate::transport::{duplex, websocket, DuplexRecv, DuplexSend, SendHalf};
+use crate::{
+    free_color, run_session, session_span, snapshot_view, spawn_game, start_server, Blocklist,
+    BotStrategy, CertificateHash, ClientConfig, CoinPlacedViewData, Command, ConfigError, Endpoint,
+    GameState, GameTasks, IdentityConfig, LeavePolicy, MatchPreferences, OverflowPolicy, RateLimit,
+    ScoreViewData, ServerConfig, SessionContext, SnapshotEncoding, TimeoutPenalty, View, GAME_ID,
+    MAX_CHAT_LEN, MAX_NAME_LEN,
+};
+
+fn ragged_game() -> (Game, Coins) {
+    let mut game = Game::default();
+    let orange_player = Player::orange(1);
+    let blue_player = Player::blue(2);
+
+    let column_count = 9;
+    let mut coins = game.set_columns(column_count).unwrap();
+    let mut groups = HashMap::new();
+
+    for (index, column_index) in [0, 0, 0, 3, 3, 7, 8, 8, 0].iter().enumerate() {
+        let player = if index % 3 == 0 {
+            &blue_player
+        } else {
+            &orange_player
+        };
+        let mut coin = player.coin(groups.len() as u64);
+        game.play_coin(player.id, *column_index, &mut coin, &mut groups, &mut coins)
+            .unwrap();
+    }
+
+    (game, coins)
+}
+
+#[test]
+fn test_runs_round_trip_ragged_columns() {
+    let (_, coins) = ragged_game();
+
+    let (dense, col_count, row_count) = serialize_coins(&coins);
+    let runs = serialize_coins_runs(&coins);
+
+    assert_eq!(
+        deserialize_coins_runs(&runs, col_count, row_count),
+        Some(dense)
+    );
+}
+
+#[test]
+fn test_runs_round_trip_deflated() {
+    let (_, coins) = ragged_game();
+
+    let (dense, col_count, row_count) = serialize_coins(&coins);
+    let runs = inflate(&deflate(&serialize_coins_runs(&coins))).unwrap();
+
+    assert_eq!(
+        deserialize_coins_runs(&runs, col_count, row_count),
+        Some(dense)
+    );
+}
+
+#[test]
+fn test_runs_round_trip_wide_empty_board() {
+    let mut game = Game::default();
+    let mut coins = game.set_columns(1_000_000).unwrap();
+    coins[999_999].push(Coin {
+        color: Color::Purple,
+        group: 0,
+    });
+
+    let (dense, col_count, row_count) = serialize_coins(&coins);
+    let runs = serialize_coins_runs(&coins);
+
+    assert!(runs.len() < 16);
+    assert_eq!(
+        deserialize_coins_runs(&runs, col_count, row_count),
+        Some(dense)
+    );
+}
+
+#[test]
+fn test_runs_rejects_truncated_body() {
+    let (_, coins) = ragged_game();
+
+    let (_, col_count, row_count) = serialize_coins(&coins);
+    let runs = serialize_coins_runs(&coins);
+
+    assert_eq!(
+        deserialize_coins_runs(&runs[..runs.len() - 1], col_count, row_count),
+        None
+    );
+}
+
+#[test]
+fn test_expand_encoded_snapshot_views() {
+    let (game, coins) = ragged_game();
+
+    let dense = snapshot_view(&game, &coins, 9, SnapshotEncoding::Dense);
+
+    for encoding in [SnapshotEncoding::Runs, SnapshotEncoding::DeflatedRuns] {
+        let encoded = snapshot_view(&game, &coins, 9, encoding);
+
+        assert_eq!(View::expand_snapshot(&encoded), Some(dense.clone()));
+    }
+}
+
+#[test]
+fn test_snapshot_coins_from_views() {
+    let (game, coins) = ragged_game();
+
+    let colors = |coins: &Coins| -> Vec<Vec<Color>> {
+        coins
+            .iter()
+            .map(|column| column.iter().map(|coin| coin.color.clone()).collect())
+            .collect()
+    };
+
+    for encoding in [SnapshotEncoding::Dense, SnapshotEncoding::DeflatedRuns] {
+        let view = snapshot_view(&game, &coins, 9, encoding);
+
+        let read = View::snapshot_coins(&view).unwrap();
+        assert_eq!(colors(&read), colors(&coins));
+    }
+}
+
+/// A board of five columns with the given colours stacked in each
+fn stacked_coins(columns: [&[Color]; 5]) -> Coins {
+    columns
+        .iter()
+        .map(|column| {
+            column
+                .iter()
+                .map(|color| Coin {
+                    color: color.clone(),
+                    group: 0,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_greedy_bot_grows_then_blocks() {
+    let orange = [Color::Orange, Color::Orange, Color::Orange];
+    let mut bot = BotStrategy::Greedy.bot();
+
+    // Dropping next to its pair grows it, whatever the orange group threatens
+    let coins = stacked_coins([&[Color::Blue, Color::Blue], &[], &[], &[], &orange]);
+    for _ in 0..16 {
+        assert!(matches!(bot.choose_column(&coins, &Color::Blue), 0 | 1));
+    }
+
+    // With nothing of its own to grow, it cuts off the biggest group instead, beside it or on top
+    let coins = stacked_coins([&[], &[], &[], &[], &orange]);
+    for _ in 0..16 {
+        assert!(matches!(bot.choose_column(&coins, &Color::Blue), 3 | 4));
+    }
+
+    let mut bot = BotStrategy::Random.bot();
+    for _ in 0..16 {
+        assert!(bot.choose_column(&coins, &Color::Blue) < 5);
+    }
+}
+
+#[tokio::test]
+async fn test_clients_pin_the_certificate_hash() {
+    let server = start_server(ServerConfig {
+        bind_address: SocketAddr::from(([127, 0, 0, 1], 0)),
+        websocket_bind_address: None,
+        metrics_bind_address: None,
+        ..ServerConfig::default()
+    })
+    .await
+    .unwrap();
+    let url = format!("https://localhost:{}", server.local_addr().port());
+
+    let client = |hash: CertificateHash| {
+        let config = ClientConfig::builder()
+            .with_bind_default()
+            .with_server_certificate_hashes([Sha256Digest::new(hash)])
+            .build();
+        Endpoint::client(config).unwrap()
+    };
+
+    // The hash the server hands out is trusted in place of a certificate chain
+    let connection = client(server.certificate_hash()).connect(&url).await;
+    assert!(connection.is_ok());
+    drop(connection);
+
+    // Any other hash is refused during the handshake
+    let mut wrong = server.certificate_hash();
+    wrong[0] ^= 0xff;
+    assert!(client(wrong).connect(&url).await.is_err());
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_shutdown_closes_every_session() {
+    let server = start_server(ServerConfig {
+        bind_address: SocketAddr::from(([127, 0, 0, 1], 0)),
+        websocket_bind_address: Some(SocketAddr::from(([127, 0, 0, 1], 0))),
+        metrics_bind_address: None,
+        ..ServerConfig::default()
+    })
+    .await
+    .unwrap();
+
+    let stream = TcpStream::connect(server.websocket_addr().unwrap())
+        .await
+        .unwrap();
+    let (mut client, _) = tokio_tungstenite::client_async("ws://localhost/", stream)
+        .await
+        .unwrap();
+    client
+        .send(Message::Binary(Command::Join.serialize()))
+        .await
+        .unwrap();
+    let joined = client.next().await.unwrap().unwrap().into_data();
+    assert_eq!(joined[0], 0);
+
+    // Every session is sent a `Closed` view before shutting down finishes
+    server.shutdown().await;
+    loop {
+        let view = client.next().await.unwrap().unwrap().into_data();
+        if view[0] == 12 {
+            assert_eq!(view, vec![12, 3]);
+            break;
+        }
+    }
+}
+
+#[test]
+fn test_config_from_toml() {
+    let config = ServerConfig::from_toml(
+        r#"
+        bind_address = "127.0.0.1:4101"
+        cert_path = "../tls/cert.pem"
+        key_path = "../tls/key.pem"
+        columns = 7
+        win_size = 4
+        seats = 3
+        colors = ["red", "yellow", "purple"]
+        leave_policy = "forfeit"
+        idle_timeout_secs = 60
+        websocket_bind_address = "off"
+        bot = "greedy"
+        metrics_bind_address = "127.0.0.1:9100"
+        admin_socket_path = "admin.sock"
+        admin_token = "hunter2"
+        chat_rate_limit = 3
+        chat_blocklist = ["Darn"]
+        max_sessions_per_ip = 4
+        connection_rate_limit = { per_sec = 20, burst = 40 }
+        ip_rate_limit = { per_sec = 50 }
+        credential_secret_path = "credential.secret"
+        require_credentials = true
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.bind_address.port(), 4101);
+    assert_eq!(config.websocket_bind_address, None);
+    assert_eq!(
+        config.metrics_bind_address,
+        Some("127.0.0.1:9100".parse().unwrap())
+    );
+    assert_eq!(
+        config.identity,
+        IdentityConfig::PemFiles {
+            cert_path: "../tls/cert.pem".into(),
+            key_path: "../tls/key.pem".into(),
+        }
+    );
+    assert_eq!(config.columns, 7);
+    assert_eq!(config.win_size, Some(4));
+    assert_eq!(config.seats, Some(3));
+    assert_eq!(
+        config.colors,
+        vec![Color::Red, Color::Yellow, Color::Purple]
+    );
+    assert_eq!(config.leave_policy, LeavePolicy::Forfeit);
+    assert_eq!(config.idle_timeout, Duration::from_secs(60));
+    assert_eq!(config.bot, Some(BotStrategy::Greedy));
+    assert_eq!(config.admin_socket_path, Some("admin.sock".into()));
+    assert_eq!(config.admin_token.as_deref(), Some("hunter2"));
+    assert!(!format!("{:?}", config).contains("hunter2"));
+    assert_eq!(config.chat_rate_limit, Some(3));
+    assert_eq!(
+        config.credential_secret_path,
+        Some("credential.secret".into())
+    );
+    assert!(config.require_credentials);
+    assert_eq!(config.max_sessions_per_ip, Some(4));
+    assert_eq!(
+        config.connection_rate_limit,
+        Some(RateLimit {
+            per_sec: 20,
+            burst: Some(40),
+        })
+    );
+    assert_eq!(
+        config.ip_rate_limit,
+        Some(RateLimit {
+            per_sec: 50,
+            burst: None,
+        })
+    );
+    assert_eq!(
+        config
+            .chat_filter
+            .unwrap()
+            .filter(1, "darn it, DARN, darned"),
+        Some("**** it, ****, darned".to_string())
+    );
+    assert_eq!(config.channel_size, ServerConfig::default().channel_size);
+}
+
+#[test]
+fn test_config_validation_errors() {
+    let invalid = [
+        ("columns = 0", "NoColumns"),
+        ("win_size = 0", "NoWinSize"),
+        ("seats = 0", "NoSeats"),
+        ("colors = []", "NoColors"),
+        ("colors = [\"red\", \"red\"]", "DuplicateColor(Red)"),
+        ("colors = [\"green\"]", "UnknownColor(\"green\")"),
+        ("channel_size = 0", "NoChannelSize"),
+        ("outbound_queue_size = 0", "NoOutboundQueueSize"),
+        (
+            "overflow_policy = \"block\"",
+            "UnknownOverflowPolicy(\"block\")",
+        ),
+        (
+            "keep_alive_interval_secs = 30",
+            "KeepAliveNotBelowIdleTimeout",
+        ),
+        (
+            "bind_address = \"localhost\"",
+            "InvalidBindAddress(\"localhost\")",
+        ),
+        ("cert_path = \"cert.pem\"", "IncompleteIdentity"),
+        ("self_signed_names = []", "NoSubjectAltNames"),
+        ("move_timeout_secs = 0", "NoMoveTimeout"),
+        ("clock_secs = 0", "NoClock"),
+        ("clock_increment_secs = 2", "IncrementWithoutClock"),
+        ("max_match_columns = 0", "NoMatchColumns"),
+        ("bot = \"minimax\"", "UnknownBotStrategy(\"minimax\")"),
+        ("admin_socket_path = \"admin.sock\"", "AdminWithoutToken"),
+        ("chat_rate_limit = 0", "NoChatRateLimit"),
+        ("max_sessions_per_ip = 0", "NoSessionsPerIp"),
+        ("ip_rate_limit = { per_sec = 0 }", "NoRateLimit"),
+        (
+            "connection_rate_limit = { per_sec = 5, burst = 0 }",
+            "NoRateLimit",
+        ),
+        ("max_frame_size = 0", "NoFrameSize"),
+        (
+            "timeout_penalty = \"resign\"",
+            "UnknownTimeoutPenalty(\"resign\")",
+        ),
+    ];
+
+    for (input, expected) in invalid {
+        let error = ServerConfig::from_toml(input).unwrap_err();
+        assert_eq!(format!("{:?}", error), expected);
+    }
+
+    assert!(matches!(
+        ServerConfig::from_toml("colums = 7"),
+        Err(ConfigError::Parse(_))
+    ));
+}
+
+fn journal_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "connect4000-{}-{}.journal",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn journal_records() -> Vec<Record> {
+    vec![
+        Record::Create {
+            columns: 4,
+            win_size: Some(3),
+        },
+        Record::Join {
+            player_id: 1,
+            color: Color::Blue,
+            token: [1; 16],
+        },
+        Record::Join {
+            player_id: 2,
+            color: Color::Orange,
+            token: [2; 16],
+        },
+        Record::PlayCoin {
+            player_id: 1,
+            column: 0,
+        },
+        Record::PlayCoin {
+            player_id: 2,
+            column: 3,
+        },
+        Record::PlayCoin {
+            player_id: 1,
+            column: 0,
+        },
+    ]
+}
+
+fn write_journal(path: &Path, records: &[Record]) {
+    let (mut journal, existing) = Journal::open(path, FsyncPolicy::Never).unwrap();
+    assert!(existing.is_empty());
+
+    for record in records {
+        journal.append(record).unwrap();
+    }
+    journal.flush().unwrap();
+}
+
+#[test]
+fn test_journal_round_trip() {
+    let path = journal_path("round-trip");
+    write_journal(&path, &journal_records());
+
+    let (_, records) = Journal::open(&path, FsyncPolicy::Always).unwrap();
+    assert_eq!(records, journal_records());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_journal_round_trips_the_longest_name() {
+    let path = journal_path("longest-name");
+
+    // Every byte of a name held to the limit, taken by someone else so the id is added to it
+    let profile = Profile {
+        name: "é".repeat(MAX_NAME_LEN as usize / 2),
+        identity: Some([0xff; 16]),
+    };
+    let profiles = HashMap::from([(1, profile.clone())]);
+    let mut records = journal_records();
+    records.push(Record::Profile {
+        player_id: u64::MAX,
+        identity: profile.identity,
+        name: profile.unique_name(u64::MAX, &profiles),
+    });
+    write_journal(&path, &records);
+
+    let (mut journal, replayed) = Journal::open(&path, FsyncPolicy::Always).unwrap();
+    assert_eq!(replayed, records);
+
+    // Anything longer is refused as it's written, leaving the journal readable
+    let longer = Record::Profile {
+        player_id: u64::MAX,
+        identity: profile.identity,
+        name: format!("{}#{}", "é".repeat(MAX_NAME_LEN as usize), u64::MAX),
+    };
+    assert!(journal.append(&longer).is_err());
+    drop(journal);
+    let (_, replayed) = Journal::open(&path, FsyncPolicy::Always).unwrap();
+    assert_eq!(replayed, records);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_journal_truncates_torn_tail() {
+    let path = journal_path("torn-tail");
+    write_journal(&path, &journal_records());
+
+    let len = std::fs::metadata(&path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len - 3).unwrap();
+
+    let (mut journal, records) = Journal::open(&path, FsyncPolicy::Always).unwrap();
+    assert_eq!(records, journal_records()[..5]);
+
+    // Appends carry on from the last whole record
+    let last = journal_records().pop().unwrap();
+    journal.append(&last).unwrap();
+    drop(journal);
+
+    let (_, records) = Journal::open(&path, FsyncPolicy::Always).unwrap();
+    assert_eq!(records, journal_records());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_journal_rejects_corrupt_record() {
+    let path = journal_path("corrupt");
+    write_journal(&path, &journal_records());
+
+    // Flip a byte inside the first join record, with whole records still after it
+    let mut data = std::fs::read(&path).unwrap();
+    data[30] ^= 0xff;
+    std::fs::write(&path, &data).unwrap();
+
+    let error = Journal::open(&path, FsyncPolicy::Always).unwrap_err();
+    assert!(matches!(error, JournalError::Corrupt { offset: 25 }));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_journal_rejects_corrupt_length() {
+    let path = journal_path("corrupt-length");
+    write_journal(&path, &journal_records());
+    let data = std::fs::read(&path).unwrap();
+
+    // A length running past the end of the file, in the first join record rather than the last
+    for byte in [25, 28] {
+        let mut corrupt = data.clone();
+        corrupt[byte] = 0xff;
+        std::fs::write(&path, &corrupt).unwrap();
+
+        let error = Journal::open(&path, FsyncPolicy::Always).unwrap_err();
+        assert!(matches!(error, JournalError::Corrupt { offset: 25 }));
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_replay_turn_timeouts() {
+    let mut records = journal_records();
+    records.push(Record::Skip { player_id: 2 });
+    let state = GameState::replay(records.clone()).unwrap();
+    assert_eq!(state.turns.current(), Some(1));
+
+    records.push(Record::Forfeit { player_id: 1 });
+    let state = GameState::replay(records.clone()).unwrap();
+    assert_eq!(state.game_data.0.winner_id, Some(2));
+    // Still connected players keep their session after timing out
+    assert!(state.sessions.contains_key(&1));
+
+    // Only the player on turn can run out of time
+    records.truncate(6);
+    records.push(Record::Skip { player_id: 1 });
+    assert!(matches!(
+        GameState::replay(records),
+        Err(JournalError::Unreplayable { record: 6 })
+    ));
+}
+
+#[test]
+fn test_replay_rematch() {
+    let mut records = journal_records();
+    // A rematch can only follow a win
+    records.push(Record::Rematch);
+    assert!(matches!(
+        GameState::replay(records.clone()),
+        Err(JournalError::Unreplayable { record: 6 })
+    ));
+
+    records.pop();
+    records.push(Record::PlayCoin {
+        player_id: 2,
+        column: 3,
+    });
+    records.push(Record::PlayCoin {
+        player_id: 1,
+        column: 0,
+    });
+    records.push(Record::Rematch);
+    let state = GameState::replay(records).unwrap();
+
+    let (game, coins, _) = &state.game_data;
+    assert_eq!(game.winner_id, None);
+    assert_eq!(game.win_size, Some(3));
+    assert!(coins.iter().all(|column| column.is_empty()));
+    assert_eq!(state.sequence, 6);
+    // The second player goes first in the second round
+    assert_eq!(state.turns.seated(), &[2, 1]);
+    assert_eq!(state.score.round, 1);
+    assert_eq!(state.score.wins.get(&1), Some(&1));
+}
+
+#[test]
+fn test_replay_admin_reset_and_resize() {
+    let mut records = journal_records();
+    records.push(Record::Resize { columns: 6 });
+    let state = GameState::replay(records.clone()).unwrap();
+    // Resizing waits for the next fresh board
+    assert_eq!(state.game_data.1.len(), 4);
+    assert_eq!(state.next_columns, Some(6));
+
+    // Unlike a rematch, a reset doesn't need the game to be won
+    records.push(Record::Reset);
+    let state = GameState::replay(records).unwrap();
+
+    let (game, coins, _) = &state.game_data;
+    assert_eq!(game.winner_id, None);
+    assert_eq!(coins.len(), 6);
+    assert!(coins.iter().all(|column| column.is_empty()));
+    assert_eq!(state.next_columns, None);
+    assert_eq!(state.score.round, 1);
+}
+
+#[test]
+fn test_replay_profiles() {
+    let mut records = journal_records();
+    records.push(Record::Profile {
+        player_id: 1,
+        identity: Some([7; 16]),
+        name: "Ada".to_string(),
+    });
+    records.push(Record::Profile {
+        player_id: 2,
+        identity: None,
+        name: "Bob".to_string(),
+    });
+
+    let path = journal_path("profiles");
+    write_journal(&path, &records);
+    let (_, replayed) = Journal::open(&path, FsyncPolicy::Always).unwrap();
+    assert_eq!(replayed, records);
+    std::fs::remove_file(&path).unwrap();
+
+    let state = GameState::replay(records.clone()).unwrap();
+    assert_eq!(
+        state.profiles.get(&1),
+        Some(&Profile {
+            name: "Ada".to_string(),
+            identity: Some([7; 16]),
+        })
+    );
+    assert_eq!(state.profiles[&2].identity, None);
+
+    // Only players in the game have a name
+    records.push(Record::Profile {
+        player_id: 9,
+        identity: None,
+        name: "Eve".to_string(),
+    });
+    assert!(matches!(
+        GameState::replay(records),
+        Err(JournalError::Unreplayable { record: 8 })
+    ));
+}
+
+#[test]
+fn test_replay_color_change() {
+    let mut records = journal_records()[..3].to_vec();
+    records.push(Record::Color {
+        player_id: 2,
+        color: Color::Yellow,
+    });
+
+    let path = journal_path("color");
+    write_journal(&path, &records);
+    let (_, replayed) = Journal::open(&path, FsyncPolicy::Always).unwrap();
+    assert_eq!(replayed, records);
+    std::fs::remove_file(&path).unwrap();
+
+    let state = GameState::replay(records).unwrap();
+    assert_eq!(state.players[&1].color, Color::Blue);
+    assert_eq!(state.players[&2].color, Color::Yellow);
+}
+
+#[test]
+fn test_free_color_falls_back_to_the_next_free_one() {
+    let colors = [Color::Orange, Color::Blue, Color::Red];
+    let mut players = HashMap::new();
+    players.insert(1, Player::from_color(1, Color::Blue));
+
+    // Asked for and free, asked for and taken, and where the palette puts the player
+    assert_eq!(
+        free_color(&colors, &players, 2, Some(&Color::Orange)),
+        Color::Orange
+    );
+    assert_eq!(
+        free_color(&colors, &players, 2, Some(&Color::Blue)),
+        Color::Red
+    );
+    assert_eq!(free_color(&colors, &players, 3, None), Color::Orange);
+    // Colours outside the palette aren't handed out
+    assert_eq!(
+        free_color(&colors, &players, 2, Some(&Color::Purple)),
+        Color::Red
+    );
+
+    // With every colour taken, they're shared
+    players.insert(2, Player::from_color(2, Color::Red));
+    players.insert(3, Player::from_color(3, Color::Orange));
+    assert_eq!(free_color(&colors, &players, 4, None), Color::Blue);
+}
+
+#[test]
+fn test_credentials_signed_by_the_secret() {
+    let path = std::env::temp_dir().join(format!(
+        "connect4000-credential-{}.secret",
+        std::process::id()
+    ));
+    let credentials = Credentials::load(Some(&path)).unwrap();
+    let credential = credentials.issue([3; 16]);
+    assert_eq!(credentials.verify(&credential), Some([3; 16]));
+
+    // The secret outlives the server, but not a credential made up by someone else
+    let reloaded = Credentials::load(Some(&path)).unwrap();
+    assert_eq!(reloaded.verify(&credential), Some([3; 16]));
+    let mut forged = credential;
+    forged[0] ^= 1;
+    assert_eq!(reloaded.verify(&forged), None);
+    assert_eq!(Credentials::load(None).unwrap().verify(&credential), None);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_turn_clock_charges_and_increments() {
+    let mut clock = TurnClock::new(
+        Some(Duration::from_secs(5)),
+        Some(Duration::from_secs(10)),
+        Duration::from_secs(2),
+    );
+    let start = tokio::time::Instant::now();
+
+    assert!(clock.sync(Some(1), start));
+    assert!(!clock.sync(Some(1), start + Duration::from_secs(1)));
+    // The move timeout is shorter than the clock
+    assert_eq!(clock.deadline(), Some((1, start + Duration::from_secs(5))));
+
+    // 10s - 3s taken + 2s increment
+    clock.moved(start + Duration::from_secs(3));
+    assert!(clock.sync(Some(2), start + Duration::from_secs(3)));
+    assert!(clock.sync(Some(1), start + Duration::from_secs(4)));
+    assert_eq!(clock.deadline(), Some((1, start + Duration::from_secs(9))));
+
+    // Timing out takes the whole clock, with no increment
+    clock.stop(start + Duration::from_secs(13), Duration::ZERO);
+    assert!(clock.sync(Some(1), start + Duration::from_secs(13)));
+    assert_eq!(clock.deadline(), Some((1, start + Duration::from_secs(13))));
+
+    assert!(clock.sync(None, start + Duration::from_secs(14)));
+    assert_eq!(clock.deadline(), None);
+}
+
+#[test]
+fn test_replay_rebuilds_game() {
+    let state = GameState::replay(journal_records()).unwrap();
+
+    assert_eq!(state.sequence, 3);
+    assert_eq!(state.next_player_id, 3);
+    assert_eq!(state.turns.current(), Some(2));
+    assert_eq!(state.tokens.get(&[1; 16]), Some(&1));
+    assert!(state
+        .sessions
+        .values()
+        .all(|session| session.connection_id.is_none()));
+
+    let (game, coins, _) = &state.game_data;
+    assert_eq!(game.win_size, Some(3));
+    assert_eq!(coins[0].len(), 2);
+    assert_eq!(coins[3][0].color, Color::Orange);
+
+    let mut records = journal_records();
+    records.push(Record::PlayCoin {
+        player_id: 9,
+        column: 1,
+    });
+    assert!(matches!(
+        GameState::replay(records),
+        Err(JournalError::Unreplayable { record: 6 })
+    ));
+}
+
+#[test]
+fn test_elo_moves_ratings_by_expectation() {
+    assert_eq!(elo(1500.0, 1500.0), (1516.0, 1484.0));
+
+    // Beating a much weaker player earns next to nothing
+    let (winner, loser) = elo(2100.0, 1500.0);
+    assert!(winner - 2100.0 < 1.0);
+    assert_eq!(winner + loser, 3600.0);
+}
+
+#[test]
+fn test_ratings_persist() {
+    let path = journal_path("ratings");
+
+    let mut ratings = Ratings::load(Some(path.clone())).unwrap();
+    ratings.record_win(&[1; 16], &[2; 16]);
+    ratings.record_win(&[1; 16], &[3; 16]);
+
+    let loaded = Ratings::load(Some(path.clone())).unwrap();
+    assert_eq!(loaded.get(&[1; 16]), ratings.get(&[1; 16]));
+    assert_eq!(loaded.get(&[1; 16]).games, 2);
+    assert_eq!(loaded.get(&[2; 16]).rating, 1484.0);
+    assert_eq!(loaded.get(&[4; 16]).rating, 1500.0);
+
+    std::fs::write(&path, "not a rating\n").unwrap();
+    assert!(Ratings::load(Some(path.clone())).is_err());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn waiting(identity: Option<u8>, rating: f64, columns: Option<u64>, since: Instant) -> Waiting {
+    let (reply, _) = oneshot::channel();
+
+    Waiting {
+        ticket: Ticket {
+            connection_id: identity.unwrap_or(0) as u64,
+            identity: identity.map(|identity| [identity; 16]),
+            preferences: MatchPreferences {
+                columns,
+                win_size: None,
+            },
+            reply,
+        },
+        rating,
+        since,
+    }
+}
+
+#[test]
+fn test_pair_closest_agreeing_ratings() {
+    let now = Instant::now();
+
+    // Different boards, or the same player twice, never make a match
+    let queue = [
+        waiting(Some(1), 1500.0, Some(7), now),
+        waiting(Some(2), 1500.0, Some(9), now),
+    ];
+    assert_eq!(pair(&queue, now), None);
+    let queue = [
+        waiting(Some(1), 1500.0, None, now),
+        waiting(Some(1), 1500.0, None, now),
+    ];
+    assert_eq!(pair(&queue, now), None);
+
+    // Players who haven't identified can't be told apart, so nothing stops them playing each other
+    let queue = [
+        waiting(None, 1500.0, None, now),
+        waiting(None, 1500.0, None, now),
+    ];
+    assert_eq!(pair(&queue, now), Some((0, 1)));
+
+    let queue = [
+        waiting(Some(1), 1500.0, Some(7), now),
+        waiting(Some(2), 1700.0, None, now),
+        waiting(Some(3), 1550.0, None, now),
+        waiting(Some(4), 1620.0, Some(9), now),
+    ];
+    assert_eq!(pair(&queue, now), Some((0, 2)));
+
+    // Too far apart at first, close enough once both have waited a while
+    let queue = [
+        waiting(Some(1), 1500.0, None, now),
+        waiting(Some(2), 1800.0, None, now),
+    ];
+    assert_eq!(pair(&queue, now), None);
+    assert_eq!(pair(&queue, now + Duration::from_secs(4)), Some((0, 1)));
+}
+
+/// A game with clients connected over in memory streams instead of WebTransport
+struct TestServer {
+    context: SessionContext,
+    tasks: GameTasks,
+    matchmaker: JoinHandle<()>,
+    metrics: Arc<Metrics>,
+    shutdown_tx: watch::Sender<bool>,
+    sessions: JoinSet<()>,
+    next_connection_id: u64,
+}
+
+impl TestServer {
+    fn start(config: ServerConfig) -> Self {
+        let game_state = GameState::new(config.columns, config.win_size);
+        Self::start_from(config, game_state)
+    }
+
+    /// Starts the game from a state replayed from its journal, as after a restart
+    fn start_from(config: ServerConfig, game_state: GameState) -> Self {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let metrics = Arc::new(Metrics::default());
+        let (mut context, tasks) = spawn_game(
+            &config,
+            GAME_ID,
+            None,
+            game_state,
+            shutdown_rx.clone(),
+            None,
+            metrics.clone(),
+        );
+        let ratings = Ratings::load(config.ratings_path.clone()).unwrap();
+        let (matchmaker_tx, matchmaker) =
+            spawn_matchmaker(&config, ratings, 2, shutdown_rx, metrics.clone());
+        context.matchmaker = Some(matchmaker_tx);
+        let credentials = Credentials::load(config.credential_secret_path.as_deref()).unwrap();
+        context.credentials = Some(Arc::new(credentials));
+
+        TestServer {
+            context,
+            tasks,
+            matchmaker,
+            metrics,
+            shutdown_tx,
+            sessions: JoinSet::new(),
+            next_connection_id: 0,
+        }
+    }
+
+    async fn connect(&mut self, handshake: Command) -> (DuplexSend, DuplexRecv) {
+        self.connect_with_buffer(handshake, 4096).await
+    }
+
+    /// Connects a client whose stream only holds this many unread bytes before writes wait
+    async fn connect_with_buffer(
+        &mut self,
+        handshake: Command,
+        max_buf_size: usize,
+    ) -> (DuplexSend, DuplexRecv) {
+        self.connect_from(handshake, max_buf_size, None).await
+    }
+
+    /// Connects a client as if from an address, only clients with one are limited by address
+    async fn connect_from(
+        &mut self,
+        handshake: Command,
+        max_buf_size: usize,
+        peer: Option<IpAddr>,
+    ) -> (DuplexSend, DuplexRecv) {
+        let ((mut client_tx, client_rx), (server_tx, server_rx)) = duplex(max_buf_size);
+
+        let connection_id = self.next_connection_id;
+        self.next_connection_id += 1;
+        let session = run_session(
+            self.context.clone(),
+            connection_id,
+            peer,
+            Box::new(server_tx),
+            Box::new(server_rx),
+        );
+        self.sessions
+            .spawn(session.instrument(session_span(connection_id, "duplex")));
+
+        client_tx.write_all(&handshake.serialize()).await.unwrap();
+        (client_tx, client_rx)
+    }
+
+    /// Connects a player that reads its views up to the end of the handshake, then stops reading
+    /// while spectators keep joining until its outbound queue overflows
+    async fn overflow_slow_player(&mut self) -> (DuplexSend, DuplexRecv) {
+        let (mut slow_tx, mut slow_rx) = self.connect_with_buffer(Command::Join, 64).await;
+        skip_to_snapshot(&mut slow_rx).await;
+        // Once resynced the player is registered for broadcasts
+        slow_tx
+            .write_all(&Command::Resync.serialize())
+            .await
+            .unwrap();
+        skip_to_snapshot(&mut slow_rx).await;
+
+        let metrics = self.context.outbox.metrics.clone();
+        while metrics.snapshot().dropped_views == 0 {
+            self.connect(Command::Spectate).await;
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        (slow_tx, slow_rx)
+    }
+
+    async fn stop(mut self) {
+        self.shutdown_tx.send_replace(true);
+        while self.sessions.join_next().await.is_some() {}
+        let matchmaker_tx = self.context.matchmaker.clone().unwrap();
+        stop_matchmaker(&matchmaker_tx, self.matchmaker).await;
+        self.tasks.stop(self.context).await;
+    }
+}
+
+/// Reads the next view, skipping spectator counts which can arrive at any point
+async fn next_view(client_rx: &mut DuplexRecv) -> Vec<u8> {
+    loop {
+        let view = tokio::time::timeout(Duration::from_secs(5), View::read(client_rx))
+            .await
+            .expect("timed out waiting for a view")
+            .expect("stream closed");
+        if view[0] != 7 {
+            return view;
+        }
+    }
+}
+
+/// Reads views up to and including the snapshot every session starts with
+async fn skip_to_snapshot(client_rx: &mut DuplexRecv) {
+    while next_view(client_rx).await[0] != 1 {}
+}
+
+/// Reads views until one of the given payload type arrives
+async fn next_view_of(client_rx: &mut DuplexRecv, payload_type: u8) -> Vec<u8> {
+    loop {
+        let view = next_view(client_rx).await;
+        if view[0] == payload_type {
+            return view;
+        }
+    }
+}
+
+fn view_player_id(view: &[u8]) -> u64 {
+    u64::from_be_bytes(view[1..9].try_into().unwrap())
+}
+
+fn coin_placed(sequence: u64, column: u64, color: &Color, player_id: u64) -> Vec<u8> {
+    View::serialize(View::CoinPlaced(CoinPlacedViewData {
+        sequence,
+        column,
+        row: 0,
+        color: color.serialize(),
+        player_id,
+        winner_id: None,
+    }))
+}
+
+/// Formatted log lines, shared with the test that checks them
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl LogBuffer {
+    /// The first line logged with the message
+    fn line(&self, message: &str) -> String {
+        let logs = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        logs.lines()
+            .find(|line| line.contains(message))
+            .unwrap_or_else(|| panic!("nothing logged with {:?}", message))
+            .to_string()
+    }
+}
+
+#[tokio::test]
+async fn test_player_traced_from_session_through_broadcast() {
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .with_max_level(tracing::Level::DEBUG)
+        .finish();
+    // Every task runs on the test's thread, so they all log here
+    let _subscriber = tracing::subscriber::set_default(subscriber);
+
+    let mut server = TestServer::start(ServerConfig::default());
+    let (mut player_tx, mut player_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut player_rx).await;
+    player_tx
+        .write_all(&Command::PlayCoin(0).serialize())
+        .await
+        .unwrap();
+    assert_eq!(
+        next_view(&mut player_rx).await,
+        coin_placed(1, 0, &Color::Blue, 1)
+    );
+    server.stop().await;
+
+    let session = r#"session{connection_id=0 transport="duplex" game_id=1 player_id=1}"#;
+    assert!(logs.line("command=PlayCoin(0)").contains(session));
+    assert!(logs
+        .line("player joined")
+        .contains("game{game_id=1}:join{connection_id=0 player_id=1}"));
+    assert!(logs
+        .line("player dropped coin")
+        .contains("game{game_id=1}:play_coin{player_id=1 column=0}"));
+    // The coin's broadcast is traced back to the action that placed it
+    assert!(logs
+        .line("view queued")
+        .contains("play_coin{player_id=1 column=0}:broadcast{view=8}"));
+}
+
+#[tokio::test]
+async fn test_session_join_gets_seat_and_snapshot() {
+    let mut server = TestServer::start(ServerConfig::default());
+
+    let (_player_tx, mut player_rx) = server.connect(Command::Join).await;
+    let joined = next_view(&mut player_rx).await;
+    assert_eq!(joined[0], 0);
+    assert_eq!(view_player_id(&joined), 1);
+    assert_eq!(joined[9], Color::Blue.serialize());
+
+    let snapshot = next_view(&mut player_rx).await;
+    assert_eq!(snapshot[0], 1);
+    // Four empty columns
+    assert_eq!(u64::from_be_bytes(snapshot[9..17].try_into().unwrap()), 4);
+    assert_eq!(u64::from_be_bytes(snapshot[17..25].try_into().unwrap()), 0);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_session_coin_broadcast_to_everyone() {
+    let mut server = TestServer::start(ServerConfig::default());
+
+    let (mut first_tx, mut first_rx) = server.connect(Command::Join).await;
+    let (mut second_tx, mut second_rx) = server.connect(Command::Join).await;
+    let (_spectator_tx, mut spectator_rx) = server.connect(Command::Spectate).await;
+    for client_rx in [&mut first_rx, &mut second_rx, &mut spectator_rx] {
+        skip_to_snapshot(client_rx).await;
+    }
+
+    // Out of turn, nothing is placed, the resync proves the coin was handled first
+    second_tx
+        .write_all(&Command::PlayCoin(1).serialize())
+        .await
+        .unwrap();
+    second_tx
+        .write_all(&Command::Resync.serialize())
+        .await
+        .unwrap();
+    let snapshot = next_view(&mut second_rx).await;
+    assert_eq!(snapshot[0], 1);
+    assert_eq!(u64::from_be_bytes(snapshot[25..33].try_into().unwrap()), 0);
+
+    first_tx
+        .write_all(&Command::PlayCoin(2).serialize())
+        .await
+        .unwrap();
+    let expected = coin_placed(1, 2, &Color::Blue, 1);
+    for client_rx in [&mut first_rx, &mut second_rx, &mut spectator_rx] {
+        assert_eq!(next_view(client_rx).await, expected);
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_session_disconnect_broadcasts_player_left() {
+    let mut server = TestServer::start(ServerConfig::default());
+
+    let (_first_tx, mut first_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut first_rx).await;
+
+    let (second_tx, mut second_rx) = server.connect(Command::Join).await;
+    let joined = next_view(&mut second_rx).await;
+    skip_to_snapshot(&mut second_rx).await;
+    drop((second_tx, second_rx));
+
+    let left = next_view(&mut first_rx).await;
+    assert_eq!(left[0], 3);
+    assert_eq!(view_player_id(&left), view_player_id(&joined));
+
+    server.stop().await;
+}
+
+/// Two seated players on a board won by two in a row, the first of them gone as soon as they've
+/// joined. The second is returned once they've heard the first leave.
+async fn first_player_left(leave_policy: LeavePolicy) -> (TestServer, DuplexSend, DuplexRecv) {
+    let mut server = TestServer::start(ServerConfig {
+        win_size: Some(2),
+        leave_policy,
+        resume_grace_period: Duration::ZERO,
+        ..ServerConfig::default()
+    });
+
+    let (first_tx, mut first_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut first_rx).await;
+    let (second_tx, mut second_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut second_rx).await;
+
+    drop((first_tx, first_rx));
+    let left = next_view(&mut second_rx).await;
+    assert_eq!((left[0], view_player_id(&left)), (3, 1));
+
+    (server, second_tx, second_rx)
+}
+
+/// Drops a coin, returning the sequence of the snapshot resynced straight after it
+async fn play_and_resync(
+    client_tx: &mut DuplexSend,
+    client_rx: &mut DuplexRecv,
+    column: u64,
+) -> u64 {
+    client_tx
+        .write_all(&Command::PlayCoin(column).serialize())
+        .await
+        .unwrap();
+    client_tx
+        .write_all(&Command::Resync.serialize())
+        .await
+        .unwrap();
+    let snapshot = next_view_of(client_rx, 1).await;
+    u64::from_be_bytes(snapshot[25..33].try_into().unwrap())
+}
+
+#[tokio::test]
+async fn test_leave_policy_decides_the_departed_players_turn() {
+    // Skipped, the turn passes to the player still seated once the grace period is up
+    let (server, mut second_tx, mut second_rx) = first_player_left(LeavePolicy::SkipTurn).await;
+    while play_and_resync(&mut second_tx, &mut second_rx, 0).await == 0 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    server.stop().await;
+
+    // Forfeited, the player still seated wins
+    let (server, _second_tx, mut second_rx) = first_player_left(LeavePolicy::Forfeit).await;
+    let snapshot = next_view_of(&mut second_rx, 1).await;
+    assert_eq!(u64::from_be_bytes(snapshot[1..9].try_into().unwrap()), 2);
+    server.stop().await;
+
+    // Held, the game waits on the departed player's turn however long they're gone
+    let (server, mut second_tx, mut second_rx) = first_player_left(LeavePolicy::HoldSeat).await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(play_and_resync(&mut second_tx, &mut second_rx, 0).await, 0);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_resume_token_reattaches_to_the_same_seat() {
+    let mut server = TestServer::start(ServerConfig::default());
+
+    let (mut first_tx, mut first_rx) = server.connect(Command::Join).await;
+    let joined = next_view(&mut first_rx).await;
+    let token: [u8; 16] = joined[10..26].try_into().unwrap();
+    skip_to_snapshot(&mut first_rx).await;
+    let (_second_tx, mut second_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut second_rx).await;
+
+    first_tx
+        .write_all(&Command::PlayCoin(0).serialize())
+        .await
+        .unwrap();
+    next_view_of(&mut second_rx, 8).await;
+    drop((first_tx, first_rx));
+    assert_eq!(next_view(&mut second_rx).await[0], 3);
+
+    // Within the grace period the token gets the same player and colour back, and the board so far
+    let (_resumed_tx, mut resumed_rx) = server.connect(Command::Resume(token)).await;
+    let resumed = next_view(&mut resumed_rx).await;
+    assert_eq!(resumed, joined);
+    let snapshot = next_view(&mut resumed_rx).await;
+    assert_eq!(snapshot[0], 1);
+    assert_eq!(u64::from_be_bytes(snapshot[25..33].try_into().unwrap()), 1);
+
+    // A token the game doesn't know joins as a new player instead
+    let (_unknown_tx, mut unknown_rx) = server.connect(Command::Resume([9; 16])).await;
+    let joined = next_view(&mut unknown_rx).await;
+    assert_eq!((joined[0], view_player_id(&joined)), (0, 3));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_moves_broadcast_as_sequenced_deltas() {
+    let mut server = TestServer::start(ServerConfig {
+        win_size: Some(2),
+        ..ServerConfig::default()
+    });
+
+    let (mut first_tx, mut first_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut first_rx).await;
+    let (mut second_tx, mut second_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut second_rx).await;
+
+    // Each move is a delta carrying where the coin landed, numbered one after another, and the
+    // winner once there is one
+    let moves = [
+        (1, 0, 0, Color::Blue, None),
+        (2, 0, 1, Color::Red, None),
+        (1, 1, 0, Color::Blue, Some(1)),
+    ];
+    for (sequence, (player_id, column, row, color, winner_id)) in moves.into_iter().enumerate() {
+        let client_tx = match player_id {
+            1 => &mut first_tx,
+            _ => &mut second_tx,
+        };
+        client_tx
+            .write_all(&Command::PlayCoin(column).serialize())
+            .await
+            .unwrap();
+
+        let expected = View::serialize(View::CoinPlaced(CoinPlacedViewData {
+            sequence: sequence as u64 + 1,
+            column,
+            row,
+            color: color.serialize(),
+            player_id,
+            winner_id,
+        }));
+        for client_rx in [&mut first_rx, &mut second_rx] {
+            assert_eq!(next_view(client_rx).await, expected);
+        }
+    }
+
+    // A client that missed some asks for a snapshot, which picks up from the latest delta
+    second_tx
+        .write_all(&Command::Resync.serialize())
+        .await
+        .unwrap();
+    let snapshot = next_view_of(&mut second_rx, 1).await;
+    assert_eq!(u64::from_be_bytes(snapshot[1..9].try_into().unwrap()), 1);
+    assert_eq!(u64::from_be_bytes(snapshot[25..33].try_into().unwrap()), 3);
+    assert_eq!(u64::from_be_bytes(snapshot[17..25].try_into().unwrap()), 2);
+
+    server.stop().await;
+}
+
+/// Reads views until a spectator count arrives, returning the count
+async fn next_spectators(client_rx: &mut DuplexRecv) -> u64 {
+    loop {
+        let view = tokio::time::timeout(Duration::from_secs(5), View::read(client_rx))
+            .await
+            .expect("timed out waiting for a view")
+            .expect("stream closed");
+        if view[0] == 7 {
+            return view_player_id(&view);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_spectators_watch_without_taking_part() {
+    let mut server = TestServer::start(ServerConfig::default());
+
+    // A spectator is greeted with the count rather than a seat, ahead of the snapshot
+    let (mut spectator_tx, mut spectator_rx) = server.connect(Command::Spectate).await;
+    assert_eq!(next_spectators(&mut spectator_rx).await, 1);
+    skip_to_snapshot(&mut spectator_rx).await;
+
+    // And leaves the first player id and colour for the first player
+    let (_player_tx, mut player_rx) = server.connect(Command::Join).await;
+    let joined = next_view(&mut player_rx).await;
+    assert_eq!(view_player_id(&joined), 1);
+    assert_eq!(joined[9], Color::Blue.serialize());
+    skip_to_snapshot(&mut player_rx).await;
+
+    // Coins from a spectator go nowhere
+    assert_eq!(
+        play_and_resync(&mut spectator_tx, &mut spectator_rx, 0).await,
+        0
+    );
+
+    // Everyone hears the count go up and down as spectators come and go, the first spectator's
+    // own count may still be on its way to them
+    let (second_tx, second_rx) = server.connect(Command::Spectate).await;
+    for client_rx in [&mut player_rx, &mut spectator_rx] {
+        while next_spectators(client_rx).await != 2 {}
+    }
+    drop((second_tx, second_rx));
+    assert_eq!(next_spectators(&mut player_rx).await, 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_session_closed_when_game_full_and_on_shutdown() {
+    let mut server = TestServer::start(ServerConfig {
+        seats: Some(1),
+        ..ServerConfig::default()
+    });
+
+    let (_first_tx, mut first_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut first_rx).await;
+
+    let (_second_tx, mut second_rx) = server.connect(Command::Join).await;
+    assert_eq!(next_view(&mut second_rx).await, vec![12, 1]);
+
+    server.shutdown_tx.send_replace(true);
+    assert_eq!(next_view(&mut first_rx).await, vec![12, 3]);
+
+    server.stop().await;
+}
+
+async fn next_frame(client: &mut WebSocketStream<DuplexStream>) -> Vec<u8> {
+    match client.next().await {
+        Some(Ok(Message::Binary(frame))) => frame,
+        frame => panic!("expected a binary frame - {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn test_session_over_websocket_frames() {
+    let mut server = TestServer::start(ServerConfig::default());
+
+    let (client, server_stream) = tokio::io::duplex(4096);
+    let mut client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+    let server_socket = WebSocketStream::from_raw_socket(server_stream, Role::Server, None).await;
+    let (socket_tx, socket_rx) = websocket(server_socket);
+    server.sessions.spawn(run_session(
+        server.context.clone(),
+        0,
+        None,
+        Box::new(socket_tx),
+        Box::new(socket_rx),
+    ));
+
+    // A command split across frames, an unknown token joins as a new player
+    let resume = Command::Resume([7; 16]).serialize();
+    for frame in [&resume[..3], &resume[3..]] {
+        client.send(Message::Binary(frame.to_vec())).await.unwrap();
+    }
+
+    // Each view arrives as a frame of its own
+    let joined = next_frame(&mut client).await;
+    assert_eq!((joined[0], joined.len()), (0, 26));
+    assert_eq!(next_frame(&mut client).await[0], 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_websocket_frame_too_large_closes_session() {
+    let mut server = TestServer::start(ServerConfig::default());
+
+    let (client, server_stream) = tokio::io::duplex(4096);
+    let mut client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+    let websocket_config = WebSocketConfig {
+        max_frame_size: Some(64),
+        max_message_size: Some(64),
+        ..WebSocketConfig::default()
+    };
+    let server_socket =
+        WebSocketStream::from_raw_socket(server_stream, Role::Server, Some(websocket_config)).await;
+    let (socket_tx, socket_rx) = websocket(server_socket);
+    server.sessions.spawn(run_session(
+        server.context.clone(),
+        0,
+        None,
+        Box::new(socket_tx),
+        Box::new(socket_rx),
+    ));
+
+    client
+        .send(Message::Binary(Command::Spectate.serialize()))
+        .await
+        .unwrap();
+    while next_frame(&mut client).await[0] != 1 {}
+
+    client.send(Message::Binary(vec![9; 65])).await.unwrap();
+    loop {
+        let frame = next_frame(&mut client).await;
+        if frame[0] == 12 {
+            assert_eq!(frame, vec![12, 8]);
+            break;
+        }
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_slow_client_resynced_after_overflow() {
+    let mut server = TestServer::start(ServerConfig {
+        outbound_queue_size: 2,
+        ..ServerConfig::default()
+    });
+
+    let (_slow_tx, mut slow_rx) = server.overflow_slow_player().await;
+
+    // Only spectator counts were queued, so the next view is the snapshot covering those dropped
+    let snapshot = next_view(&mut slow_rx).await;
+    assert_eq!(snapshot[0], 1);
+
+    let metrics = server.context.outbox.metrics.snapshot();
+    assert_eq!(metrics.deepest_queue, 2);
+    assert_eq!(metrics.slow_disconnects, 0);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_slow_client_disconnected_after_overflow() {
+    let mut server = TestServer::start(ServerConfig {
+        outbound_queue_size: 2,
+        overflow_policy: OverflowPolicy::Disconnect,
+        ..ServerConfig::default()
+    });
+
+    let (_player_tx, mut player_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut player_rx).await;
+
+    let (_slow_tx, _slow_rx) = server.overflow_slow_player().await;
+
+    // The other player hears straight away, without waiting on the slow player's stream
+    let left = next_view(&mut player_rx).await;
+    assert_eq!(left[0], 3);
+    assert_eq!(view_player_id(&left), 2);
+    assert_eq!(server.context.outbox.metrics.snapshot().slow_disconnects, 1);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_stalled_client_replaced_without_waiting_on_it() {
+    let mut server = TestServer::start(ServerConfig {
+        outbound_queue_size: 2,
+        ..ServerConfig::default()
+    });
+
+    let (mut slow_tx, mut slow_rx) = server.connect_with_buffer(Command::Join, 64).await;
+    let joined = next_view(&mut slow_rx).await;
+    let token: [u8; 16] = joined[10..26].try_into().unwrap();
+    skip_to_snapshot(&mut slow_rx).await;
+    slow_tx
+        .write_all(&Command::Resync.serialize())
+        .await
+        .unwrap();
+    skip_to_snapshot(&mut slow_rx).await;
+
+    // The slow player stops reading until its queue is full and its writer is stuck
+    let metrics = server.context.outbox.metrics.clone();
+    while metrics.snapshot().dropped_views == 0 {
+        server.connect(Command::Spectate).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    // Taking over the seat closes the stalled stream rather than queueing behind it
+    let (_player_tx, mut player_rx) = server.connect(Command::Resume(token)).await;
+    let joined = next_view(&mut player_rx).await;
+    assert_eq!((joined[0], view_player_id(&joined)), (0, 1));
+
+    server.stop().await;
+}
+
+/// A game with two players seated on a short move timeout
+async fn timed_game(timeout_penalty: TimeoutPenalty) -> (TestServer, DuplexRecv, DuplexRecv) {
+    let mut server = TestServer::start(ServerConfig {
+        move_timeout: Some(Duration::from_millis(50)),
+        timeout_penalty,
+        ..ServerConfig::default()
+    });
+
+    let (_first_tx, mut first_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut first_rx).await;
+    let (_second_tx, mut second_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut second_rx).await;
+
+    (server, first_rx, second_rx)
+}
+
+#[tokio::test]
+async fn test_turn_timeout_skips_turn() {
+    let (server, mut first_rx, mut second_rx) = timed_game(TimeoutPenalty::SkipTurn).await;
+
+    // The first player's clock starts once the second player joins, the turn passes on once time
+    // runs out, and no coin is ever placed
+    let mut turns = Vec::new();
+    while turns.last() != Some(&2) {
+        let clock = next_view(&mut first_rx).await;
+        assert_eq!(clock[0], 13);
+        turns.push(view_player_id(&clock));
+    }
+    assert_eq!(turns[turns.len() - 2], 1);
+
+    let clock = next_view_of(&mut second_rx, 13).await;
+    assert!(matches!(view_player_id(&clock), 1 | 2));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_turn_timeout_plays_random_move() {
+    let (server, mut first_rx, _second_rx) = timed_game(TimeoutPenalty::RandomMove).await;
+
+    let coin_placed = next_view_of(&mut first_rx, 8).await;
+    assert_eq!(
+        u64::from_be_bytes(coin_placed[26..34].try_into().unwrap()),
+        1
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_turn_timeout_forfeits() {
+    let (server, mut first_rx, _second_rx) = timed_game(TimeoutPenalty::Forfeit).await;
+
+    // The first player loses their seat, leaving the second as the last player seated
+    let snapshot = next_view_of(&mut first_rx, 1).await;
+    assert_eq!(view_player_id(&snapshot), 2);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_clock_running_out_forfeits_whatever_the_penalty() {
+    for timeout_penalty in [TimeoutPenalty::SkipTurn, TimeoutPenalty::RandomMove] {
+        let mut server = TestServer::start(ServerConfig {
+            clock: Some(Duration::from_millis(50)),
+            timeout_penalty,
+            ..ServerConfig::default()
+        });
+        let (_first_tx, mut first_rx) = server.connect(Command::Join).await;
+        skip_to_snapshot(&mut first_rx).await;
+        let (_second_tx, _second_rx) = server.connect(Command::Join).await;
+
+        // Rather than being skipped on every turn after, the first player loses their seat
+        let snapshot = next_view_of(&mut first_rx, 1).await;
+        assert_eq!(view_player_id(&snapshot), 2);
+
+        server.stop().await;
+    }
+}
+
+#[tokio::test]
+async fn test_bot_fills_empty_seat_until_a_player_joins() {
+    let mut server = TestServer::start(ServerConfig {
+        bot: Some(BotStrategy::Greedy),
+        bot_move_delay: Duration::ZERO,
+        ..ServerConfig::default()
+    });
+
+    let (mut first_tx, mut first_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut first_rx).await;
+
+    // The bot seated opposite answers the player's coin with one of its own
+    first_tx
+        .write_all(&Command::PlayCoin(0).serialize())
+        .await
+        .unwrap();
+    let coin_placed = next_view_of(&mut first_rx, 8).await;
+    assert_eq!(
+        u64::from_be_bytes(coin_placed[26..34].try_into().unwrap()),
+        1
+    );
+    let coin_placed = next_view_of(&mut first_rx, 8).await;
+    assert_eq!(
+        u64::from_be_bytes(coin_placed[26..34].try_into().unwrap()),
+        2
+    );
+
+    // Then gives its seat up to the next player
+    let (_second_tx, mut second_rx) = server.connect(Command::Join).await;
+    let joined = next_view(&mut second_rx).await;
+    assert_eq!(view_player_id(&joined), 3);
+
+    let left = next_view_of(&mut first_rx, 3).await;
+    assert_eq!(view_player_id(&left), 2);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_bot_plays_on_after_a_restart() {
+    let mut records = journal_records();
+    records.truncate(2);
+    records.push(Record::Bot {
+        player_id: 2,
+        color: Color::Orange,
+        strategy: BotStrategy::Greedy,
+    });
+    records.push(Record::PlayCoin {
+        player_id: 1,
+        column: 0,
+    });
+    let path = journal_path("bot-restart");
+    write_journal(&path, &records);
+    let (_, replayed) = Journal::open(&path, FsyncPolicy::Always).unwrap();
+    assert_eq!(replayed, records);
+    std::fs::remove_file(&path).unwrap();
+
+    let state = GameState::replay(records).unwrap();
+    assert_eq!(state.bots.get(&2), Some(&BotStrategy::Greedy));
+    assert!(!state.sessions.contains_key(&2));
+
+    let mut server = TestServer::start_from(
+        ServerConfig {
+            columns: 4,
+            leave_policy: LeavePolicy::HoldSeat,
+            bot: Some(BotStrategy::Greedy),
+            bot_move_delay: Duration::ZERO,
+            ..ServerConfig::default()
+        },
+        state,
+    );
+
+    // The bot was on turn when the game stopped, and takes it without waiting for anyone
+    let (mut player_tx, mut player_rx) = server.connect(Command::Resume([1; 16])).await;
+    let snapshot = next_view_of(&mut player_rx, 1).await;
+    if u64::from_be_bytes(snapshot[25..33].try_into().unwrap()) == 1 {
+        let coin_placed = next_view_of(&mut player_rx, 8).await;
+        assert_eq!(
+            u64::from_be_bytes(coin_placed[26..34].try_into().unwrap()),
+            2
+        );
+    }
+
+    // And goes on answering the player's coins
+    player_tx
+        .write_all(&Command::PlayCoin(3).serialize())
+        .await
+        .unwrap();
+    let coin_placed = next_view_of(&mut player_rx, 8).await;
+    assert_eq!(
+        u64::from_be_bytes(coin_placed[26..34].try_into().unwrap()),
+        1
+    );
+    let coin_placed = next_view_of(&mut player_rx, 8).await;
+    assert_eq!(
+        u64::from_be_bytes(coin_placed[26..34].try_into().unwrap()),
+        2
+    );
+
+    server.stop().await;
+}
+
+/// Sends a bare HTTP request for the path, returning the whole response
+async fn http_get(address: SocketAddr, path: &str) -> String {
+    // Imported here, `SendHalf` has a `write_all` of its own
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn test_metrics_count_moves_and_health_follows_the_game() {
+    let mut server = TestServer::start(ServerConfig::default());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let metrics_server = tokio::spawn(serve_metrics(
+        listener,
+        server.metrics.clone(),
+        server.context.tx.clone(),
+        server.shutdown_tx.subscribe(),
+    ));
+
+    let (mut first_tx, mut first_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut first_rx).await;
+    let (_second_tx, mut second_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut second_rx).await;
+
+    // One coin placed, then one turned down for being out of turn
+    for _ in 0..2 {
+        first_tx
+            .write_all(&Command::PlayCoin(0).serialize())
+            .await
+            .unwrap();
+    }
+    first_tx
+        .write_all(&Command::Resync.serialize())
+        .await
+        .unwrap();
+    next_view_of(&mut first_rx, 1).await;
+
+    let metrics = http_get(address, "/metrics").await;
+    assert!(metrics.starts_with("HTTP/1.1 200 OK"));
+    assert!(metrics.contains("\nconnect4000_games 1\n"));
+    assert!(metrics.contains("\nconnect4000_moves_total 1\n"));
+    assert!(metrics.contains("\nconnect4000_rejected_moves_total{error=\"NotPlayersTurn\"} 1\n"));
+    assert!(metrics.contains("\nconnect4000_broadcast_seconds_bucket{le=\"+Inf\"} "));
+
+    assert!(http_get(address, "/health")
+        .await
+        .starts_with("HTTP/1.1 200 OK"));
+    assert!(http_get(address, "/ready")
+        .await
+        .starts_with("HTTP/1.1 200 OK"));
+    assert!(http_get(address, "/nope").await.starts_with("HTTP/1.1 404"));
+
+    // Once the game has stopped, its actor no longer answers
+    let metrics = server.metrics.clone();
+    server.stop().await;
+    assert!(http_get(address, "/health")
+        .await
+        .starts_with("HTTP/1.1 503"));
+    assert!(metrics.render().contains("\nconnect4000_games 0\n"));
+
+    metrics_server.abort();
+}
+
+#[tokio::test]
+async fn test_rematch_after_win_keeps_score() {
+    let mut server = TestServer::start(ServerConfig {
+        win_size: Some(2),
+        ..ServerConfig::default()
+    });
+
+    let (mut first_tx, mut first_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut first_rx).await;
+    let (mut second_tx, mut second_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut second_rx).await;
+
+    // No rematch while the game is still being played
+    first_tx
+        .write_all(&Command::Rematch.serialize())
+        .await
+        .unwrap();
+
+    // Each coin is placed before the next is played, the moves come in over different streams
+    for (first, column) in [(true, 0), (false, 1), (true, 0)] {
+        let client_tx = if first { &mut first_tx } else { &mut second_tx };
+        client_tx
+            .write_all(&Command::PlayCoin(column).serialize())
+            .await
+            .unwrap();
+        next_view_of(&mut first_rx, 8).await;
+    }
+    let score = next_view_of(&mut first_rx, 16).await;
+    assert_eq!(
+        score,
+        View::serialize(View::Score(ScoreViewData {
+            round: 0,
+            wins: vec![(1, 1)],
+        }))
+    );
+
+    first_tx
+        .write_all(&Command::Rematch.serialize())
+        .await
+        .unwrap();
+    let votes = next_view_of(&mut second_rx, 15).await;
+    assert_eq!(view_player_id(&votes), 1);
+    assert_eq!(u64::from_be_bytes(votes[9..17].try_into().unwrap()), 2);
+
+    second_tx
+        .write_all(&Command::Rematch.serialize())
+        .await
+        .unwrap();
+    let snapshot = next_view_of(&mut second_rx, 1).await;
+    assert_eq!(view_player_id(&snapshot), 0);
+    assert_eq!(u64::from_be_bytes(snapshot[17..25].try_into().unwrap()), 0);
+    let score = next_view(&mut second_rx).await;
+    assert_eq!(score[0], 16);
+    assert_eq!(u64::from_be_bytes(score[1..9].try_into().unwrap()), 1);
+
+    next_view_of(&mut first_rx, 16).await;
+
+    // The second player moves first this round, the resync proves the first player's coin was
+    // turned away
+    first_tx
+        .write_all(&Command::PlayCoin(0).serialize())
+        .await
+        .unwrap();
+    first_tx
+        .write_all(&Command::Resync.serialize())
+        .await
+        .unwrap();
+    next_view_of(&mut first_rx, 1).await;
+    second_tx
+        .write_all(&Command::PlayCoin(3).serialize())
+        .await
+        .unwrap();
+    let coin_placed = next_view_of(&mut first_rx, 8).await;
+    assert_eq!(
+        u64::from_be_bytes(coin_placed[26..34].try_into().unwrap()),
+        2
+    );
+    assert_eq!(
+        u64::from_be_bytes(coin_placed[9..17].try_into().unwrap()),
+        3
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_find_match_pairs_players_and_rates_the_game() {
+    let path = journal_path("match-ratings");
+    let mut server = TestServer::start(ServerConfig {
+        ratings_path: Some(path.clone()),
+        ..ServerConfig::default()
+    });
+
+    let preferences = MatchPreferences {
+        columns: Some(5),
+        win_size: Some(2),
+    };
+    let (mut first_tx, mut first_rx) = server
+        .connect(Command::Identify("Ada".to_string(), None))
+        .await;
+    first_tx
+        .write_all(&Command::FindMatch(preferences).serialize())
+        .await
+        .unwrap();
+    let (mut second_tx, mut second_rx) = server
+        .connect(Command::Identify("Bob".to_string(), None))
+        .await;
+    second_tx
+        .write_all(&Command::FindMatch(MatchPreferences::default()).serialize())
+        .await
+        .unwrap();
+
+    // Ratings are kept against the identity each credential proves
+    let mut identities = Vec::new();
+    let mut goes_first = Vec::new();
+    for client_rx in [&mut first_rx, &mut second_rx] {
+        let credential = next_view(client_rx).await;
+        assert_eq!(credential[0], 23);
+        let identity: [u8; 16] = credential[1..17].try_into().unwrap();
+        identities.push(identity);
+
+        let found = next_view(client_rx).await;
+        assert_eq!(found[0], 18);
+        assert_eq!(view_player_id(&found), 2);
+        // Both asked for a board the other accepts
+        assert_eq!(u64::from_be_bytes(found[33..41].try_into().unwrap()), 2);
+        assert_eq!(u64::from_be_bytes(found[25..33].try_into().unwrap()), 5);
+        goes_first.push(found[41] == 1);
+
+        let joined = next_view(client_rx).await;
+        assert_eq!(joined[0], 0);
+        let snapshot = next_view(client_rx).await;
+        assert_eq!(u64::from_be_bytes(snapshot[9..17].try_into().unwrap()), 5);
+    }
+
+    assert_ne!(goes_first[0], goes_first[1]);
+
+    // Whoever goes first wins straight away on a win size of two
+    let (winner, loser) = match goes_first[0] {
+        true => (identities[0], identities[1]),
+        false => (identities[1], identities[0]),
+    };
+    for (mover, column) in [(true, 0), (false, 1), (true, 0)] {
+        let client_tx = match mover == goes_first[0] {
+            true => &mut first_tx,
+            false => &mut second_tx,
+        };
+        client_tx
+            .write_all(&Command::PlayCoin(column).serialize())
+            .await
+            .unwrap();
+        next_view_of(&mut first_rx, 8).await;
+    }
+    next_view_of(&mut second_rx, 16).await;
+
+    // Ratings are written once the match reports its winner
+    let mut rated = Ratings::load(Some(path.clone())).unwrap();
+    while rated.get(&winner).games == 0 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        rated = Ratings::load(Some(path.clone())).unwrap();
+    }
+    assert_eq!(rated.get(&winner).rating, 1516.0);
+    assert_eq!(rated.get(&loser).rating, 1484.0);
+
+    server.stop().await;
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Sends an admin command, returning the lines answered up to and including `ok` or the error
+async fn admin_command(admin: &mut BufReader<UnixStream>, command: &str) -> Vec<String> {
+    let line = format!("{}\n", command);
+    tokio::io::AsyncWriteExt::write_all(admin.get_mut(), line.as_bytes())
+        .await
+        .unwrap();
+
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        admin.read_line(&mut line).await.unwrap();
+        let line = line.trim_end().to_string();
+        let done = line == "ok" || line.starts_with("error: ") || line.is_empty();
+        lines.push(line);
+        if done {
+            return lines;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_admin_socket_controls_the_game() {
+    let mut server = TestServer::start(ServerConfig::default());
+    let path = std::env::temp_dir().join(format!("connect4000-admin-{}.sock", std::process::id()));
+    let listener = bind_admin(&path).unwrap();
+    let admin_server = tokio::spawn(serve_admin(
+        listener,
+        server.context.clone(),
+        "hunter2".to_string(),
+    ));
+
+    let (mut first_tx, mut first_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut first_rx).await;
+    let (_second_tx, mut second_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut second_rx).await;
+
+    // Nothing is answered without the token
+    let mut admin = BufReader::new(UnixStream::connect(&path).await.unwrap());
+    assert_eq!(
+        admin_command(&mut admin, "auth wrong").await,
+        vec!["error: not authorized"]
+    );
+
+    let mut admin = BufReader::new(UnixStream::connect(&path).await.unwrap());
+    assert_eq!(admin_command(&mut admin, "auth hunter2").await, vec!["ok"]);
+    assert_eq!(
+        admin_command(&mut admin, "players").await,
+        vec![
+            "player 1 Blue connected on-turn",
+            "player 2 Red connected",
+            "spectators 0",
+            "ok"
+        ]
+    );
+    assert_eq!(
+        admin_command(&mut admin, "kick 9").await,
+        vec!["error: no player 9 to kick"]
+    );
+
+    assert_eq!(admin_command(&mut admin, "say hello").await, vec!["ok"]);
+    let message = next_view_of(&mut second_rx, 19).await;
+    assert_eq!(&message[9..], b"hello");
+
+    first_tx
+        .write_all(&Command::PlayCoin(2).serialize())
+        .await
+        .unwrap();
+    next_view_of(&mut second_rx, 8).await;
+    assert_eq!(admin_command(&mut admin, "columns 5").await, vec!["ok"]);
+    assert_eq!(
+        admin_command(&mut admin, "dump").await[..3],
+        ["columns 4 next 5", "winner None", "column 2 Blue/0"]
+    );
+
+    // Resetting starts the next board at the new size, with the same players
+    assert_eq!(admin_command(&mut admin, "reset").await, vec!["ok"]);
+    let snapshot = next_view_of(&mut second_rx, 1).await;
+    assert_eq!(u64::from_be_bytes(snapshot[9..17].try_into().unwrap()), 5);
+
+    // The kicked player's connection is closed, everyone else sees them leave
+    assert_eq!(admin_command(&mut admin, "kick 1").await, vec!["ok"]);
+    assert_eq!(next_view_of(&mut first_rx, 12).await, vec![12, 4]);
+    let left = next_view_of(&mut second_rx, 3).await;
+    assert_eq!(view_player_id(&left), 1);
+
+    assert_eq!(admin_command(&mut admin, "end").await, vec!["ok"]);
+    assert_eq!(next_view_of(&mut second_rx, 12).await, vec![12, 5]);
+
+    admin_server.abort();
+    let _ = admin_server.await;
+    std::fs::remove_file(&path).unwrap();
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_admin_kicks_a_bot() {
+    let mut server = TestServer::start(ServerConfig {
+        bot: Some(BotStrategy::Random),
+        bot_move_delay: Duration::from_secs(60),
+        ..ServerConfig::default()
+    });
+    let path =
+        std::env::temp_dir().join(format!("connect4000-admin-bot-{}.sock", std::process::id()));
+    let listener = bind_admin(&path).unwrap();
+    let admin_server = tokio::spawn(serve_admin(
+        listener,
+        server.context.clone(),
+        "hunter2".to_string(),
+    ));
+
+    let (_player_tx, mut player_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut player_rx).await;
+
+    let mut admin = BufReader::new(UnixStream::connect(&path).await.unwrap());
+    assert_eq!(admin_command(&mut admin, "auth hunter2").await, vec!["ok"]);
+    assert_eq!(
+        admin_command(&mut admin, "players").await[1],
+        "player 2 Red bot name Random bot"
+    );
+
+    // The bot leaves like a kicked player, and the player still on their own is given a fresh one
+    assert_eq!(admin_command(&mut admin, "kick 2").await, vec!["ok"]);
+    let left = next_view_of(&mut player_rx, 3).await;
+    assert_eq!(view_player_id(&left), 2);
+    assert_eq!(
+        admin_command(&mut admin, "players").await[1..3],
+        ["player 3 Yellow bot name Random bot", "spectators 0"]
+    );
+
+    admin_server.abort();
+    let _ = admin_server.await;
+    std::fs::remove_file(&path).unwrap();
+    server.stop().await;
+}
+
+fn chat_message(view: &[u8]) -> (u64, &[u8]) {
+    (view_player_id(view), &view[25..])
+}
+
+#[tokio::test]
+async fn test_chat_relayed_rate_limited_and_filtered() {
+    let mut server = TestServer::start(ServerConfig {
+        chat_rate_limit: Some(2),
+        chat_filter: Some(Arc::new(Blocklist::new(vec!["darn".to_string()]))),
+        ..ServerConfig::default()
+    });
+
+    let (mut first_tx, mut first_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut first_rx).await;
+    let (mut second_tx, mut second_rx) = server.connect(Command::Join).await;
+    skip_to_snapshot(&mut second_rx).await;
+    let (mut spectator_tx, mut spectator_rx) = server.connect(Command::Spectate).await;
+    skip_to_snapshot(&mut spectator_rx).await;
+
+    // Spectators can read the chat but not write to it
+    spectator_tx
+        .write_all(&Command::Chat("hi".to_string()).serialize())
+        .await
+        .unwrap();
+    for message in ["hello", "darn it"] {
+        first_tx
+            .write_all(&Command::Chat(message.to_string()).serialize())
+            .await
+            .unwrap();
+    }
+
+    let chat = next_view_of(&mut spectator_rx, 21).await;
+    assert_eq!(chat_message(&chat), (1, b"hello".as_slice()));
+    assert!(u64::from_be_bytes(chat[9..17].try_into().unwrap()) > 0);
+    let chat = next_view_of(&mut second_rx, 21).await;
+    assert_eq!(chat_message(&chat), (1, b"hello".as_slice()));
+    let chat = next_view_of(&mut second_rx, 21).await;
+    assert_eq!(chat_message(&chat), (1, b"**** it".as_slice()));
+
+    // A third message within the minute is dropped, the coin after it still comes through
+    first_tx
+        .write_all(&Command::Chat("again".to_string()).serialize())
+        .await
+        .unwrap();
+    first_tx
+        .write_all(&Command::PlayCoin(0).serialize())
+        .await
+        .unwrap();
+    assert_eq!(next_view(&mut second_rx).await[0], 8);
+    next_view_of(&mut first_rx, 8).await;
+
+    // Each player has their own limit, and a message over the length limit closes the stream
+    second_tx
+        .write_all(&Command::Chat("hi".to_string()).serialize())
+        .await
+        .unwrap();
+    let chat = next_view_of(&mut first_rx, 21).await;
+    assert_eq!(chat_message(&chat), (2, b"hi".as_slice()));
+
+    let long = "a".repeat(MAX_CHAT_LEN as usize + 1);
+    second_tx
+        .write_all(&Command::Chat(long).serialize())
+        .await
+        .unwrap();
+    let left = next_view_of(&mut first_rx, 3).await;
+    assert_eq!(view_player_id(&left), 2);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_rate_limits_disconnect_flooding_clients() {
+    let mut server = TestServer::start(ServerConfig {
+        connection_rate_limit: Some(RateLimit {
+            per_sec: 1,
+            burst: Some(3),
+        }),
+        ip_rate_limit: Some(RateLimit {
+            per_sec: 1,
+            burst: Some(4),
+        }),
+        max_sessions_per_ip: Some(2),
+        ..ServerConfig::default()
+    });
+
+    // The handshake counts, along with the burst of resyncs after it
+    let (mut flood_tx, mut flood_rx) = server.connect(Command::Spectate).await;
+    skip_to_snapshot(&mut flood_rx).await;
+    for _ in 0..3 {
+        flood_tx
+            .write_all(&Command::Resync.serialize())
+            .await
+            .unwrap();
+    }
+    for _ in 0..2 {
+        next_view_of(&mut flood_rx, 1).await;
+    }
+    assert_eq!(next_view(&mut flood_rx).await, vec![12, 6]);
+
+    // So do the commands ahead of the handshake, and those sent while waiting for a match
+    let encoding = Command::SnapshotEncoding(SnapshotEncoding::Dense);
+    let (mut early_tx, mut early_rx) = server.connect(encoding).await;
+    for _ in 0..3 {
+        let encoding = Command::SnapshotEncoding(SnapshotEncoding::Dense);
+        early_tx.write_all(&encoding.serialize()).await.unwrap();
+    }
+    assert_eq!(next_view(&mut early_rx).await, vec![12, 6]);
+    let (mut waiting_tx, mut waiting_rx) = server
+        .connect(Command::FindMatch(MatchPreferences::default()))
+        .await;
+    for _ in 0..3 {
+        waiting_tx
+            .write_all(&Command::Resync.serialize())
+            .await
+            .unwrap();
+    }
+    assert_eq!(next_view(&mut waiting_rx).await, vec![12, 6]);
+
+    // Sessions from one address share its slots and its commands
+    let address: IpAddr = "192.0.2.1".parse().unwrap();
+    let (mut first_tx, mut first_rx) = server
+        .connect_from(Command::Spectate, 4096, Some(address))
+        .await;
+    skip_to_snapshot(&mut first_rx).await;
+    let (mut second_tx, mut second_rx) = server
+        .connect_from(Command::Spectate, 4096, Some(address))
+        .await;
+    skip_to_snapshot(&mut second_rx).await;
+
+    let (_third_tx, mut third_rx) = server
+        .connect_from(Command::Spectate, 4096, Some(address))
+        .await;
+    assert_eq!(next_view(&mut third_rx).await, vec![12, 7]);
+    let other: IpAddr = "192.0.2.2".parse().unwrap();
+    let (_other_tx, mut other_rx) = server
+        .connect_from(Command::Spectate, 4096, Some(other))
+        .await;
+    skip_to_snapshot(&mut other_rx).await;
+
+    for client_tx in [&mut first_tx, &mut second_tx] {
+        client_tx
+            .write_all(&Command::Resync.serialize())
+            .await
+            .unwrap();
+    }
+    for client_rx in [&mut first_rx, &mut second_rx] {
+        next_view_of(client_rx, 1).await;
+    }
+    second_tx
+        .write_all(&Command::Resync.serialize())
+        .await
+        .unwrap();
+    assert_eq!(next_view_of(&mut second_rx, 12).await, vec![12, 6]);
+
+    server.stop().await;
+}
+
+#[test]
+fn test_ip_rate_limit_outlives_sessions() {
+    let limits = Limits::new(&ServerConfig {
+        ip_rate_limit: Some(RateLimit {
+            per_sec: 1,
+            burst: Some(2),
+        }),
+        ..ServerConfig::default()
+    });
+    let address: IpAddr = "192.0.2.1".parse().unwrap();
+
+    let now = Instant::now();
+    let mut limiter = limits.acquire(Some(address)).unwrap();
+    assert!(limiter.allow(now));
+    assert!(limiter.allow(now));
+    assert!(!limiter.allow(now));
+    drop(limiter);
+
+    // Reconnecting straight away doesn't refill the bucket, waiting does
+    let mut limiter = limits.acquire(Some(address)).unwrap();
+    assert!(!limiter.allow(now));
+    drop(limiter);
+    let mut limiter = limits.acquire(Some(address)).unwrap();
+    assert!(limiter.allow(now + Duration::from_secs(2)));
+}
+
+/// The id, verified flag and name a PlayerInfo view carries
+fn player_info(view: &[u8]) -> (u64, bool, &[u8]) {
+    (view_player_id(view), view[10] == 1, &view[19..])
+}
+
+#[tokio::test]
+async fn test_identified_players_named_and_keep_their_seat() {
+    let mut server = TestServer::start(ServerConfig {
+        require_credentials: true,
+        ..ServerConfig::default()
+    });
+
+    // Players have to identify themselves, spectators needn't
+    let (_anonymous_tx, mut anonymous_rx) = server.connect(Command::Join).await;
+    assert_eq!(next_view(&mut anonymous_rx).await, vec![12, 9]);
+    let (_spectator_tx, mut spectator_rx) = server.connect(Command::Spectate).await;
+    skip_to_snapshot(&mut spectator_rx).await;
+
+    // Without a credential the server issues one
+    let identify = Command::Identify("Ada".to_string(), None);
+    let (mut first_tx, mut first_rx) = server.connect(identify).await;
+    first_tx
+        .write_all(&Command::Join.serialize())
+        .await
+        .unwrap();
+    let credential = next_view(&mut first_rx).await;
+    assert_eq!(credential[0], 23);
+    let credential: [u8; 48] = credential[1..].try_into().unwrap();
+    assert_eq!(next_view(&mut first_rx).await[0], 0);
+    let info = next_view_of(&mut first_rx, 24).await;
+    assert_eq!(player_info(&info), (1, true, b"Ada".as_slice()));
+
+    // A name already taken is told apart by the player's id
+    let identify = Command::Identify("ada".to_string(), None);
+    let (mut second_tx, mut second_rx) = server.connect(identify).await;
+    second_tx
+        .write_all(&Command::Join.serialize())
+        .await
+        .unwrap();
+    skip_to_snapshot(&mut second_rx).await;
+    let info = next_view_of(&mut first_rx, 24).await;
+    assert_eq!(player_info(&info), (2, true, b"ada#2".as_slice()));
+    let info = next_view_of(&mut spectator_rx, 24).await;
+    assert_eq!(player_info(&info), (1, true, b"Ada".as_slice()));
+    let info = next_view_of(&mut spectator_rx, 24).await;
+    assert_eq!(player_info(&info), (2, true, b"ada#2".as_slice()));
+
+    // The credential gets the player their own seat back, from whichever client held it
+    let identify = Command::Identify("Ada".to_string(), Some(credential));
+    let (mut third_tx, mut third_rx) = server.connect(identify).await;
+    third_tx
+        .write_all(&Command::Join.serialize())
+        .await
+        .unwrap();
+    let joined = next_view(&mut third_rx).await;
+    assert_eq!((joined[0], view_player_id(&joined)), (0, 1));
+    assert_eq!(next_view_of(&mut first_rx, 12).await, vec![12, 10]);
+
+    // Everyone already in the game is named to a client as it joins
+    let info = next_view_of(&mut third_rx, 24).await;
+    assert_eq!(player_info(&info), (1, true, b"Ada".as_slice()));
+    let info = next_view(&mut third_rx).await;
+    assert_eq!(player_info(&info), (2, true, b"ada#2".as_slice()));
+
+    // A credential the server didn't sign, or a name too long, gets nowhere
+    let identify = Command::Identify("Eve".to_string(), Some([0; 48]));
+    let (mut forged_tx, mut forged_rx) = server.connect(identify).await;
+    forged_tx
+        .write_all(&Command::Join.serialize())
+        .await
+        .unwrap();
+    assert_eq!(next_view(&mut forged_rx).await, vec![12, 9]);
+
+    let long = "a".repeat(MAX_NAME_LEN as usize + 1);
+    let (mut long_tx, mut long_rx) = server.connect(Command::Identify(long, None)).await;
+    long_tx.write_all(&Command::Join.serialize()).await.unwrap();
+    assert!(View::read(&mut long_rx).await.is_none());
+
+    // Nor does one that isn't utf-8, which can't be told apart by its bytes
+    let mut invalid = Command::Identify("ab".to_string(), None).serialize();
+    let len = invalid.len();
+    invalid[len - 2..].copy_from_slice(&[0xff, 0xfe]);
+    let encoding = Command::SnapshotEncoding(SnapshotEncoding::Dense);
+    let (mut invalid_tx, mut invalid_rx) = server.connect(encoding).await;
+    invalid.extend(Command::Join.serialize());
+    invalid_tx.write_all(&invalid).await.unwrap();
+    assert!(View::read(&mut invalid_rx).await.is_none());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_players_pick_and_change_colors_before_the_first_coin() {
+    let mut server = TestServer::start(ServerConfig::default());
+
+    // Asked for ahead of the handshake, and granted
+    let (mut first_tx, mut first_rx) = server.connect(Command::ChangeColor(Color::Purple)).await;
+    first_tx
+        .write_all(&Command::Join.serialize())
+        .await
+        .unwrap();
+    let joined = next_view(&mut first_rx).await;
+    assert_eq!(joined[9], Color::Purple.serialize());
+    skip_to_snapshot(&mut first_rx).await;
+
+    // Already taken, so the next free one after it
+    let (mut second_tx, mut second_rx) = server.connect(Command::ChangeColor(Color::Purple)).await;
+    second_tx
+        .write_all(&Command::Join.serialize())
+        .await
+        .unwrap();
+    let joined = next_view(&mut second_rx).await;
+    assert_eq!(joined[9], Color::Orange.serialize());
+    skip_to_snapshot(&mut second_rx).await;
+
+    // A free colour can be changed to, a taken one can't
+    for color in [Color::Red, Color::Purple] {
+        second_tx
+            .write_all(&Command::ChangeColor(color).serialize())
+            .await
+            .unwrap();
+    }
+    let changed = next_view(&mut first_rx).await;
+    assert_eq!(changed[0], 26);
+    assert_eq!(view_player_id(&changed), 2);
+    assert_eq!(changed[9], Color::Red.serialize());
+    assert_eq!(next_view(&mut second_rx).await, changed);
+
+    first_tx
+        .write_all(&Command::PlayCoin(0).serialize())
+        .await
+        .unwrap();
+    let coin = next_view(&mut second_rx).await;
+    assert_eq!(coin, coin_placed(1, 0, &Color::Purple, 1));
+
+    // Once a coin is down, colours are fixed
+    second_tx
+        .write_all(&Command::ChangeColor(Color::Yellow).serialize())
+        .await
+        .unwrap();
+    second_tx
+        .write_all(&Command::PlayCoin(1).serialize())
+        .await
+        .unwrap();
+    next_view_of(&mut first_rx, 8).await;
+    let coin = next_view(&mut first_rx).await;
+    assert_eq!(coin, coin_placed(2, 1, &Color::Red, 2));
+
+    // A colour that doesn't exist closes the stream
+    second_tx.write_all(&[25, 9]).await.unwrap();
+    let left = next_view_of(&mut first_rx, 3).await;
+    assert_eq!(view_player_id(&left), 2);
+
+    server.stop().await;
+}
@@ -0,0 +1,180 @@
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use std::future::Future;
+use std::pin::Pin;
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf,
+};
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::WebSocketStream;
+use wtransport::{RecvStream, SendStream};
+
+/// The stream can no longer be read from or written to
+#[derive(Debug)]
+pub struct StreamClosed;
+
+pub type TransportFuture<'a> = Pin<Box<dyn Future<Output = Result<(), StreamClosed>> + Send + 'a>>;
+
+/// The half of a client's stream that views are written to
+pub trait SendHalf: Send + Sync {
+    fn write_all<'a>(&'a mut self, buffer: &'a [u8]) -> TransportFuture<'a>;
+
+    /// Gracefully closes the stream, after everything written so far
+    fn finish(&mut self) -> TransportFuture<'_>;
+}
+
+/// The half of a client's stream that commands are read from
+pub trait RecvHalf: Send {
+    fn read_exact<'a>(&'a mut self, buffer: &'a mut [u8]) -> TransportFuture<'a>;
+
+    /// Whether the stream was closed because the client sent more than the transport accepts at once
+    fn frame_too_large(&self) -> bool {
+        false
+    }
+}
+
+// WebTransport - a bidirectional stream on an http3 session
+
+impl SendHalf for SendStream {
+    fn write_all<'a>(&'a mut self, buffer: &'a [u8]) -> TransportFuture<'a> {
+        Box::pin(async move {
+            SendStream::write_all(self, buffer)
+                .await
+                .map_err(|_| StreamClosed)
+        })
+    }
+
+    fn finish(&mut self) -> TransportFuture<'_> {
+        Box::pin(async move { SendStream::finish(self).await.map_err(|_| StreamClosed) })
+    }
+}
+
+impl RecvHalf for RecvStream {
+    fn read_exact<'a>(&'a mut self, buffer: &'a mut [u8]) -> TransportFuture<'a> {
+        Box::pin(async move {
+            RecvStream::read_exact(self, buffer)
+                .await
+                .map_err(|_| StreamClosed)
+        })
+    }
+}
+
+// WebSocket - binary frames over tcp, for networks and browsers without WebTransport
+
+/// Sends every write as a single binary frame
+pub struct WebSocketSend<S> {
+    sink: SplitSink<WebSocketStream<S>, Message>,
+}
+
+/// Reads commands and views out of binary frames, which may split or join them at any point
+pub struct WebSocketRecv<S> {
+    stream: SplitStream<WebSocketStream<S>>,
+    buffer: Vec<u8>,
+    frame_too_large: bool,
+}
+
+/// Splits an open WebSocket into the halves a session reads and writes through
+pub fn websocket<S>(socket: WebSocketStream<S>) -> (WebSocketSend<S>, WebSocketRecv<S>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (sink, stream) = socket.split();
+
+    (
+        WebSocketSend { sink },
+        WebSocketRecv {
+            stream,
+            buffer: Vec::new(),
+            frame_too_large: false,
+        },
+    )
+}
+
+impl<S> SendHalf for WebSocketSend<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn write_all<'a>(&'a mut self, buffer: &'a [u8]) -> TransportFuture<'a> {
+        Box::pin(async move {
+            self.sink
+                .send(Message::Binary(buffer.to_vec()))
+                .await
+                .map_err(|_| StreamClosed)
+        })
+    }
+
+    fn finish(&mut self) -> TransportFuture<'_> {
+        Box::pin(async move { self.sink.close().await.map_err(|_| StreamClosed) })
+    }
+}
+
+impl<S> RecvHalf for WebSocketRecv<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn read_exact<'a>(&'a mut self, buffer: &'a mut [u8]) -> TransportFuture<'a> {
+        Box::pin(async move {
+            while self.buffer.len() < buffer.len() {
+                match self.stream.next().await {
+                    Some(Ok(Message::Binary(data))) => self.buffer.extend_from_slice(&data),
+                    // Pings are answered by tungstenite itself
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => {}
+                    // Refused by tungstenite against the max frame size it was configured with
+                    Some(Err(Error::Capacity(_))) => {
+                        self.frame_too_large = true;
+                        return Err(StreamClosed);
+                    }
+                    _ => return Err(StreamClosed),
+                }
+            }
+
+            buffer.copy_from_slice(&self.buffer[..buffer.len()]);
+            self.buffer.drain(..buffer.len());
+
+            Ok(())
+        })
+    }
+
+    fn frame_too_large(&self) -> bool {
+        self.frame_too_large
+    }
+}
+
+// In memory - a pipe between a client and the server in the same process, for tests
+
+pub type DuplexSend = WriteHalf<DuplexStream>;
+pub type DuplexRecv = ReadHalf<DuplexStream>;
+
+/// Creates a connected pair of in memory streams, one for the client and one for the server
+pub fn duplex(max_buf_size: usize) -> ((DuplexSend, DuplexRecv), (DuplexSend, DuplexRecv)) {
+    let (client, server) = tokio::io::duplex(max_buf_size);
+    let (client_rx, client_tx) = tokio::io::split(client);
+    let (server_rx, server_tx) = tokio::io::split(server);
+
+    ((client_tx, client_rx), (server_tx, server_rx))
+}
+
+impl SendHalf for DuplexSend {
+    fn write_all<'a>(&'a mut self, buffer: &'a [u8]) -> TransportFuture<'a> {
+        Box::pin(async move {
+            AsyncWriteExt::write_all(self, buffer)
+                .await
+                .map_err(|_| StreamClosed)
+        })
+    }
+
+    fn finish(&mut self) -> TransportFuture<'_> {
+        Box::pin(async move { self.shutdown().await.map_err(|_| StreamClosed) })
+    }
+}
+
+impl RecvHalf for DuplexRecv {
+    fn read_exact<'a>(&'a mut self, buffer: &'a mut [u8]) -> TransportFuture<'a> {
+        Box::pin(async move {
+            AsyncReadExt::read_exact(self, buffer)
+                .await
+                .map(|_| ())
+                .map_err(|_| StreamClosed)
+        })
+    }
+}
@@ -1,4 +1,5 @@
 import {
+  ChatCommand,
   PlayCoinCommand,
   PublishCommand,
   ViewSubscription,
@@ -17,10 +18,38 @@ export const App = async ({
   const canvas = document.createElement('canvas');
   canvas.id = 'canvas';
 
+  const chat = document.createElement('div');
+  chat.id = 'chat';
+  const chatLog = document.createElement('ul');
+  const chatInput = document.createElement('input');
+  chatInput.placeholder = 'Chat';
+  chat.append(chatLog, chatInput);
+
+  chatInput.addEventListener('keydown', (event) => {
+    if (event.key !== 'Enter') {
+      return;
+    }
+
+    const message = chatInput.value.trim();
+    chatInput.value = '';
+
+    if (message) {
+      publishCommand(new ChatCommand(message));
+    }
+  });
+
+  const showMessage = (message: string) => {
+    const item = document.createElement('li');
+    item.textContent = message;
+    chatLog.append(item);
+    chatLog.scrollTop = chatLog.scrollHeight;
+  };
+
   const init = async () => {
     const result = runApp({
       viewSubscription,
       canvas,
+      showMessage,
       dropCoin: async (column: bigint) => {
         await publishCommand(new PlayCoinCommand(column));
       },
@@ -29,5 +58,5 @@ export const App = async ({
     return result;
   };
 
-  return { canvas, init };
+  return { canvas, chat, init };
 };
@@ -16,13 +16,13 @@ export const mount = async ({
     throw new Error('No mount point');
   }
 
-  const { canvas, init } = await App({
+  const { canvas, chat, init } = await App({
     document,
     publishCommand,
     viewSubscription,
   });
 
-  mount.append(canvas);
+  mount.append(canvas, chat);
 
   const result = init();
 
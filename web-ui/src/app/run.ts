@@ -1,5 +1,5 @@
 import { Color } from '../colors';
-import { PayloadType, ViewSubscription } from '../net/stream';
+import { Coin, PayloadType, ViewSubscription } from '../net/stream';
 import { initWebGPU, resizeCanvasToDisplaySize } from '../render/gpu';
 import {
   GridDimensions,
@@ -27,15 +27,18 @@ export interface Coords {
 export const runApp = async ({
   canvas,
   dropCoin,
+  showMessage,
   viewSubscription,
 }: {
   canvas: HTMLCanvasElement;
   dropCoin: (column: bigint) => void;
+  showMessage: (message: string) => void;
   viewSubscription: ViewSubscription;
 }) => {
   const { device, context, pipelines } = await initWebGPU(canvas);
 
   let winnerId = 0n;
+  let board: Coin[][] = [];
   let coins: RenderableCoin[] = [];
 
   let aspectRatio = canvas.width / canvas.height;
@@ -50,12 +53,18 @@ export const runApp = async ({
   let camera = resetCamera(dimensions);
 
   const currentPlayer = { id: 1n, color: new Color('red') };
+  const names = new Map<bigint, string>();
+
+  /** The name a player goes by, or their id if the server hasn't named them */
+  const playerName = (playerId: bigint) =>
+    names.get(playerId) ?? `Player ${playerId}`;
 
   const subscription = viewSubscription({
     onView: (view) => {
       switch (view.type) {
         case PayloadType.SNAPSHOT:
-          coins = makeRenderableCoins(view.coins);
+          board = view.coins;
+          coins = makeRenderableCoins(board);
 
           if (view.columns > Number.MAX_SAFE_INTEGER) {
             throw new Error(
@@ -79,6 +88,58 @@ export const runApp = async ({
           currentPlayer.id = view.playerId;
           currentPlayer.color = Color.deserialize(view.color);
           break;
+        case PayloadType.COIN_PLACED:
+          board[Number(view.column)]?.push({
+            color: Color.deserialize(view.color),
+          });
+          coins = makeRenderableCoins(board);
+
+          dimensions.rows = Math.max(Number(view.row) + 1, dimensions.rows);
+          winnerId = view.winnerId;
+          break;
+        case PayloadType.PLAYER_LEFT:
+          console.log('PLAYER LEFT', view.playerId);
+          break;
+        case PayloadType.SPECTATORS:
+          console.log('SPECTATORS', view.count);
+          break;
+        case PayloadType.CLOSED:
+          console.log('CLOSED', view.reason);
+          break;
+        case PayloadType.CLOCK:
+          console.log('CLOCK', view.onTurn, view.turnRemainingMs, view.clocks);
+          break;
+        case PayloadType.REMATCH_VOTES:
+          console.log('REMATCH VOTES', view.votes, view.needed);
+          break;
+        case PayloadType.SCORE:
+          console.log('SCORE', view.round, view.wins);
+          break;
+        case PayloadType.SERVER_MESSAGE:
+          showMessage(`Server: ${view.message}`);
+          break;
+        case PayloadType.CHAT_MESSAGE: {
+          // The time of day it was sent, in UTC
+          const time = new Date(Number(view.timestamp))
+            .toISOString()
+            .slice(11, 19);
+          showMessage(
+            `[${time}] ${playerName(view.playerId)}: ${view.message}`
+          );
+          break;
+        }
+        case PayloadType.CREDENTIAL:
+          // Only sent to clients that identified, which this one doesn't yet
+          break;
+        case PayloadType.PLAYER_INFO:
+          names.set(view.playerId, view.name);
+          break;
+        case PayloadType.COLOR_CHANGED:
+          // Only allowed before the first coin, so there are no placed coins to recolour
+          if (view.playerId === currentPlayer.id) {
+            currentPlayer.color = Color.deserialize(view.color);
+          }
+          break;
         default:
           throw new Error('Unsupported view type');
       }
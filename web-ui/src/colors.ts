@@ -3,15 +3,14 @@ const coinColors: Record<number, string> = {
   2: 'blue',
   3: 'red',
   4: 'yellow',
-  5: 'orange',
-  6: 'purple',
+  5: 'purple',
 };
 
 const colorRgb: Record<string, [number, number, number]> = {
   orange: [1.0, 0.5, 0.0],
   blue: [0.0, 0.0, 1.0],
   red: [1.0, 0.0, 0.0],
-  yellow: [1.0, 0.0, 0.0],
+  yellow: [1.0, 1.0, 0.0],
   purple: [0.5, 0.0, 1.0],
 };
 
@@ -1,13 +1,254 @@
 import { Color } from '../colors';
-import { Coin, PayloadType, SnapshotView } from './stream';
+import {
+  ChatMessageView,
+  ClockView,
+  Coin,
+  CoinPlacedView,
+  ColorChangedView,
+  CredentialView,
+  JoinedView,
+  PayloadType,
+  PlayerClock,
+  PlayerInfoView,
+  PlayerLeftView,
+  PlayerWins,
+  RematchVotesView,
+  ScoreView,
+  ServerMessageView,
+  SnapshotView,
+  SpectatorsView,
+  View,
+} from './stream';
+
+/**
+ * Byte length of the view at the start of `bytes`, or null if not enough of it
+ * has arrived to tell.
+ */
+export function viewLength(bytes: Uint8Array): number | null {
+  if (bytes.length < 1) {
+    return null;
+  }
+
+  const payloadType = bytes[0];
+
+  switch (payloadType) {
+    case PayloadType.JOINED:
+      return 26;
+    case PayloadType.PLAYER_LEFT:
+    case PayloadType.SPECTATORS:
+      return 9;
+    case PayloadType.COIN_PLACED:
+      return 42;
+    case PayloadType.CLOSED:
+      return 2;
+    case PayloadType.REMATCH_VOTES:
+      return 17;
+    case PayloadType.SCORE: {
+      if (bytes.length < 17) {
+        return null;
+      }
+      const count = u64FromBigEndianBytes(bytes.slice(9, 17));
+      return 17 + Number(count) * 16;
+    }
+    case PayloadType.CLOCK: {
+      if (bytes.length < 25) {
+        return null;
+      }
+      const count = u64FromBigEndianBytes(bytes.slice(17, 25));
+      return 25 + Number(count) * 16;
+    }
+    case PayloadType.SNAPSHOT: {
+      if (bytes.length < 33) {
+        return null;
+      }
+      const columns = u64FromBigEndianBytes(bytes.slice(9, 17));
+      const rows = u64FromBigEndianBytes(bytes.slice(17, 25));
+      return 33 + Number(columns * rows);
+    }
+    case PayloadType.SERVER_MESSAGE: {
+      if (bytes.length < 9) {
+        return null;
+      }
+      const length = u64FromBigEndianBytes(bytes.slice(1, 9));
+      return 9 + Number(length);
+    }
+    case PayloadType.CHAT_MESSAGE: {
+      if (bytes.length < 25) {
+        return null;
+      }
+      const length = u64FromBigEndianBytes(bytes.slice(17, 25));
+      return 25 + Number(length);
+    }
+    case PayloadType.CREDENTIAL:
+      return 49;
+    case PayloadType.PLAYER_INFO: {
+      if (bytes.length < 19) {
+        return null;
+      }
+      const length = u64FromBigEndianBytes(bytes.slice(11, 19));
+      return 19 + Number(length);
+    }
+    case PayloadType.COLOR_CHANGED:
+      return 10;
+    default:
+      throw new Error(`invalid view payload type ${payloadType}`);
+  }
+}
+
+export function deserializeView(view: Uint8Array): View {
+  const payloadType = view[0];
+
+  switch (payloadType) {
+    case PayloadType.JOINED:
+      return deserializeJoined(view);
+    case PayloadType.SNAPSHOT:
+      return deserializeSnapshot(view);
+    case PayloadType.PLAYER_LEFT:
+      return deserializePlayerLeft(view);
+    case PayloadType.SPECTATORS:
+      return deserializeSpectators(view);
+    case PayloadType.COIN_PLACED:
+      return deserializeCoinPlaced(view);
+    case PayloadType.CLOSED:
+      return { type: PayloadType.CLOSED, reason: view[1] };
+    case PayloadType.CLOCK:
+      return deserializeClock(view);
+    case PayloadType.REMATCH_VOTES:
+      return deserializeRematchVotes(view);
+    case PayloadType.SCORE:
+      return deserializeScore(view);
+    case PayloadType.SERVER_MESSAGE:
+      return deserializeServerMessage(view);
+    case PayloadType.CHAT_MESSAGE:
+      return deserializeChatMessage(view);
+    case PayloadType.CREDENTIAL:
+      return { type: PayloadType.CREDENTIAL, credential: view.slice(1, 49) };
+    case PayloadType.PLAYER_INFO:
+      return deserializePlayerInfo(view);
+    case PayloadType.COLOR_CHANGED:
+      return deserializeColorChanged(view);
+    default:
+      throw new Error(`invalid view payload type ${payloadType}`);
+  }
+}
+
+export function deserializeJoined(view: Uint8Array): JoinedView {
+  const playerId = u64FromBigEndianBytes(view.slice(1, 9));
+  const color = view[9];
+  const token = view.slice(10, 26);
+
+  return { type: PayloadType.JOINED, playerId, color, token };
+}
+
+export function deserializeCoinPlaced(view: Uint8Array): CoinPlacedView {
+  const sequence = u64FromBigEndianBytes(view.slice(1, 9));
+  const column = u64FromBigEndianBytes(view.slice(9, 17));
+  const row = u64FromBigEndianBytes(view.slice(17, 25));
+  const color = view[25];
+  const playerId = u64FromBigEndianBytes(view.slice(26, 34));
+  const winnerId = u64FromBigEndianBytes(view.slice(34, 42));
+
+  return {
+    type: PayloadType.COIN_PLACED,
+    sequence,
+    column,
+    row,
+    color,
+    playerId,
+    winnerId,
+  };
+}
+
+export function deserializePlayerLeft(view: Uint8Array): PlayerLeftView {
+  const playerId = u64FromBigEndianBytes(view.slice(1, 9));
+
+  return { type: PayloadType.PLAYER_LEFT, playerId };
+}
+
+export function deserializeSpectators(view: Uint8Array): SpectatorsView {
+  const count = u64FromBigEndianBytes(view.slice(1, 9));
+
+  return { type: PayloadType.SPECTATORS, count };
+}
+
+export function deserializeClock(view: Uint8Array): ClockView {
+  const onTurn = u64FromBigEndianBytes(view.slice(1, 9));
+  const turnRemainingMs = u64FromBigEndianBytes(view.slice(9, 17));
+  const count = Number(u64FromBigEndianBytes(view.slice(17, 25)));
+
+  const clocks: PlayerClock[] = [];
+  for (let index = 0; index < count; index++) {
+    const offset = 25 + index * 16;
+    clocks.push({
+      playerId: u64FromBigEndianBytes(view.slice(offset, offset + 8)),
+      remainingMs: u64FromBigEndianBytes(view.slice(offset + 8, offset + 16)),
+    });
+  }
+
+  return { type: PayloadType.CLOCK, onTurn, turnRemainingMs, clocks };
+}
+
+export function deserializeRematchVotes(view: Uint8Array): RematchVotesView {
+  const votes = u64FromBigEndianBytes(view.slice(1, 9));
+  const needed = u64FromBigEndianBytes(view.slice(9, 17));
+
+  return { type: PayloadType.REMATCH_VOTES, votes, needed };
+}
+
+export function deserializeScore(view: Uint8Array): ScoreView {
+  const round = u64FromBigEndianBytes(view.slice(1, 9));
+  const count = Number(u64FromBigEndianBytes(view.slice(9, 17)));
+
+  const wins: PlayerWins[] = [];
+  for (let index = 0; index < count; index++) {
+    const offset = 17 + index * 16;
+    wins.push({
+      playerId: u64FromBigEndianBytes(view.slice(offset, offset + 8)),
+      wins: u64FromBigEndianBytes(view.slice(offset + 8, offset + 16)),
+    });
+  }
+
+  return { type: PayloadType.SCORE, round, wins };
+}
+
+export function deserializeServerMessage(view: Uint8Array): ServerMessageView {
+  const message = new TextDecoder().decode(view.slice(9));
+
+  return { type: PayloadType.SERVER_MESSAGE, message };
+}
+
+export function deserializeChatMessage(view: Uint8Array): ChatMessageView {
+  const playerId = u64FromBigEndianBytes(view.slice(1, 9));
+  const timestamp = u64FromBigEndianBytes(view.slice(9, 17));
+  const message = new TextDecoder().decode(view.slice(25));
+
+  return { type: PayloadType.CHAT_MESSAGE, playerId, timestamp, message };
+}
+
+export function deserializePlayerInfo(view: Uint8Array): PlayerInfoView {
+  const playerId = u64FromBigEndianBytes(view.slice(1, 9));
+  const color = view[9];
+  const verified = view[10] === 1;
+  const name = new TextDecoder().decode(view.slice(19));
+
+  return { type: PayloadType.PLAYER_INFO, playerId, color, verified, name };
+}
+
+export function deserializeColorChanged(view: Uint8Array): ColorChangedView {
+  const playerId = u64FromBigEndianBytes(view.slice(1, 9));
+  const color = view[9];
+
+  return { type: PayloadType.COLOR_CHANGED, playerId, color };
+}
 
 export function deserializeSnapshot(snapshot: Uint8Array): SnapshotView {
   const coins: Coin[][] = [];
   const winnerId = u64FromBigEndianBytes(snapshot.slice(1, 9));
   const columns = u64FromBigEndianBytes(snapshot.slice(9, 17));
   const rows = u64FromBigEndianBytes(snapshot.slice(17, 25));
+  const sequence = u64FromBigEndianBytes(snapshot.slice(25, 33));
 
-  const headerOffset = BigInt(25);
+  const headerOffset = BigInt(33);
 
   for (let columnIndex = BigInt(0); columnIndex < columns; columnIndex++) {
     const newColumn = [];
@@ -24,7 +265,14 @@ export function deserializeSnapshot(snapshot: Uint8Array): SnapshotView {
     coins.push(newColumn);
   }
 
-  return { type: PayloadType.SNAPSHOT, coins, winnerId, columns, rows };
+  return {
+    type: PayloadType.SNAPSHOT,
+    coins,
+    winnerId,
+    columns,
+    rows,
+    sequence,
+  };
 }
 
 export function u64FromBigEndianBytes(array: Uint8Array) {
@@ -32,6 +280,13 @@ export function u64FromBigEndianBytes(array: Uint8Array) {
   return view.getBigUint64(0);
 }
 
+export function concatBytes(a: Uint8Array, b: Uint8Array): Uint8Array {
+  const result = new Uint8Array(a.length + b.length);
+  result.set(a);
+  result.set(b, a.length);
+  return result;
+}
+
 export function u64ToBigEndianBytes(input: bigint): Uint8Array {
   const buffer = new ArrayBuffer(8);
   const view = new DataView(buffer);
@@ -1,16 +1,36 @@
 import { Color } from '../colors';
 import {
-  deserializeSnapshot,
-  u64FromBigEndianBytes,
+  concatBytes,
+  deserializeView,
   u64ToBigEndianBytes,
+  viewLength,
 } from './serialize';
 
 export enum PayloadType {
   JOINED = 0,
   SNAPSHOT = 1,
   PLAY_COIN = 2,
+  PLAYER_LEFT = 3,
+  JOIN = 4,
+  SPECTATORS = 7,
+  COIN_PLACED = 8,
+  RESYNC = 9,
+  CLOSED = 12,
+  CLOCK = 13,
+  REMATCH = 14,
+  REMATCH_VOTES = 15,
+  SCORE = 16,
+  SERVER_MESSAGE = 19,
+  CHAT = 20,
+  CHAT_MESSAGE = 21,
+  CREDENTIAL = 23,
+  PLAYER_INFO = 24,
+  COLOR_CHANGED = 26,
 }
 
+/** Longest chat message the server accepts, in UTF-8 bytes */
+export const MAX_CHAT_LEN = 280;
+
 export interface NetEvent {
   type: PayloadType;
 }
@@ -29,23 +49,178 @@ export class PlayCoinCommand implements NetEvent {
   }
 }
 
+export class JoinCommand implements NetEvent {
+  type = PayloadType.JOIN;
+
+  serialize(): ArrayBuffer {
+    return new Int8Array([this.type]);
+  }
+}
+
+export class ResyncCommand implements NetEvent {
+  type = PayloadType.RESYNC;
+
+  serialize(): ArrayBuffer {
+    return new Int8Array([this.type]);
+  }
+}
+
+export class RematchCommand implements NetEvent {
+  type = PayloadType.REMATCH;
+
+  serialize(): ArrayBuffer {
+    return new Int8Array([this.type]);
+  }
+}
+
+export class ChatCommand implements NetEvent {
+  type = PayloadType.CHAT;
+
+  constructor(public readonly message: string) {}
+
+  serialize(): ArrayBuffer {
+    const encoder = new TextEncoder();
+    const chars = Array.from(this.message);
+    let message = encoder.encode(this.message);
+    // The server drops anyone sending more, so trim whole characters off the end
+    while (message.length > MAX_CHAT_LEN) {
+      chars.pop();
+      message = encoder.encode(chars.join(''));
+    }
+
+    const length = u64ToBigEndianBytes(BigInt(message.length));
+
+    const buffer = new Int8Array([this.type, ...length, ...message]);
+
+    return buffer;
+  }
+}
+
+export type Command =
+  | PlayCoinCommand
+  | JoinCommand
+  | ResyncCommand
+  | RematchCommand
+  | ChatCommand;
+
 export interface SnapshotView extends NetEvent {
   type: PayloadType.SNAPSHOT;
   winnerId: bigint;
   columns: bigint;
   rows: bigint;
+  sequence: bigint;
   coins: Coin[][];
 }
 
+export interface CoinPlacedView extends NetEvent {
+  type: PayloadType.COIN_PLACED;
+  sequence: bigint;
+  column: bigint;
+  row: bigint;
+  color: number;
+  playerId: bigint;
+  winnerId: bigint;
+}
+
 export interface JoinedView extends NetEvent {
   type: PayloadType.JOINED;
   playerId: bigint;
   color: number;
+  token: Uint8Array;
+}
+
+export interface PlayerLeftView extends NetEvent {
+  type: PayloadType.PLAYER_LEFT;
+  playerId: bigint;
+}
+
+export interface SpectatorsView extends NetEvent {
+  type: PayloadType.SPECTATORS;
+  count: bigint;
+}
+
+export interface ClosedView extends NetEvent {
+  type: PayloadType.CLOSED;
+  reason: number;
 }
 
-export type View = SnapshotView | JoinedView;
+export interface PlayerClock {
+  playerId: bigint;
+  remainingMs: bigint;
+}
 
-export type PublishCommand = (data: PlayCoinCommand) => Promise<void>;
+export interface ClockView extends NetEvent {
+  type: PayloadType.CLOCK;
+  onTurn: bigint;
+  turnRemainingMs: bigint;
+  clocks: PlayerClock[];
+}
+
+export interface RematchVotesView extends NetEvent {
+  type: PayloadType.REMATCH_VOTES;
+  votes: bigint;
+  needed: bigint;
+}
+
+export interface PlayerWins {
+  playerId: bigint;
+  wins: bigint;
+}
+
+export interface ScoreView extends NetEvent {
+  type: PayloadType.SCORE;
+  round: bigint;
+  wins: PlayerWins[];
+}
+
+export interface ServerMessageView extends NetEvent {
+  type: PayloadType.SERVER_MESSAGE;
+  message: string;
+}
+
+export interface ChatMessageView extends NetEvent {
+  type: PayloadType.CHAT_MESSAGE;
+  playerId: bigint;
+  timestamp: bigint;
+  message: string;
+}
+
+export interface CredentialView extends NetEvent {
+  type: PayloadType.CREDENTIAL;
+  credential: Uint8Array;
+}
+
+export interface PlayerInfoView extends NetEvent {
+  type: PayloadType.PLAYER_INFO;
+  playerId: bigint;
+  color: number;
+  verified: boolean;
+  name: string;
+}
+
+export interface ColorChangedView extends NetEvent {
+  type: PayloadType.COLOR_CHANGED;
+  playerId: bigint;
+  color: number;
+}
+
+export type View =
+  | SnapshotView
+  | JoinedView
+  | PlayerLeftView
+  | SpectatorsView
+  | CoinPlacedView
+  | ClosedView
+  | ClockView
+  | RematchVotesView
+  | ScoreView
+  | ServerMessageView
+  | ChatMessageView
+  | CredentialView
+  | PlayerInfoView
+  | ColorChangedView;
+
+export type PublishCommand = (data: Command) => Promise<void>;
 export type ViewSubscription = (config: { onView: OnView }) => {
   run: () => Promise<void>;
   abortController: AbortController;
@@ -63,46 +238,44 @@ export class StreamClosedError extends Error {
   }
 }
 
-const joinServer = async (reader: ReadableStreamDefaultReader<Uint8Array>) => {
-  const { value: bytes, done } = await reader.read();
-
-  if (done) {
-    throw new StreamClosedError();
-  }
-
-  const payloadType = bytes[0];
-  if (payloadType !== 0) {
-    throw new Error(`invalid joined payload type ${payloadType}`);
-  }
-  const playerIdSegment = bytes.slice(1, 9);
-  const playerId = u64FromBigEndianBytes(playerIdSegment);
-  const color = Color.deserialize(bytes[9]);
-
-  const remainingBytes = bytes.slice(10);
-
-  return { playerId, color, remainingBytes };
-};
-
 const readThread = ({
   readable,
   onView,
+  publishCommand,
 }: {
   readable: ReadableStream;
   onView: OnView;
+  publishCommand: PublishCommand;
 }) => {
   const abortController = new AbortController();
 
   const run = async () => {
     const reader = readable.getReader();
 
-    const { color, playerId, remainingBytes } = await joinServer(reader);
+    let pending = new Uint8Array(0);
+    let sequence: bigint | null = null;
+
+    const handleView = (view: View) => {
+      if (view.type === PayloadType.SNAPSHOT) {
+        sequence = view.sequence;
+      }
+
+      if (view.type === PayloadType.COIN_PLACED) {
+        if (sequence === null || view.sequence <= sequence) {
+          return;
+        }
+
+        if (view.sequence !== sequence + 1n) {
+          sequence = null;
+          publishCommand(new ResyncCommand());
+          return;
+        }
 
-    onView({ type: PayloadType.JOINED, color: color.serialize(), playerId });
+        sequence = view.sequence;
+      }
 
-    if (remainingBytes) {
-      const view = deserializeSnapshot(remainingBytes);
       onView(view);
-    }
+    };
 
     while (!abortController.signal.aborted) {
       const { value, done } = await reader.read();
@@ -111,9 +284,14 @@ const readThread = ({
         throw new StreamClosedError();
       }
 
-      const view = deserializeSnapshot(value);
+      pending = concatBytes(pending, value);
 
-      onView(view);
+      let length = viewLength(pending);
+      while (length !== null && pending.length >= length) {
+        handleView(deserializeView(pending.slice(0, length)));
+        pending = pending.slice(length);
+        length = viewLength(pending);
+      }
     }
   };
 
@@ -129,14 +307,17 @@ export const initNetStream = ({
 }) => {
   const writer = writable.getWriter();
 
-  const publishCommand = async (data: PlayCoinCommand) => {
+  const publishCommand = async (data: Command) => {
     await writer.write(data.serialize());
   };
 
   const viewSubscription: ViewSubscription = ({ onView }) => {
+    writer.write(new JoinCommand().serialize());
+
     const thread = readThread({
       readable,
       onView,
+      publishCommand,
     });
 
     return thread;
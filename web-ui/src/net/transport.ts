@@ -1,7 +1,24 @@
+function pinnedCertificateHashes(): WebTransportHash[] | undefined {
+  const certHash = new URLSearchParams(window.location.search).get('certHash');
+  if (!certHash) {
+    return undefined;
+  }
+
+  const hex = certHash.replace(/:/g, '');
+  const value = new Uint8Array(hex.length / 2);
+  for (let index = 0; index < value.length; index++) {
+    value[index] = parseInt(hex.slice(index * 2, index * 2 + 2), 16);
+  }
+
+  return [{ algorithm: 'sha-256', value }];
+}
+
 export async function connect() {
   const port = 4001;
   const url = new URL(`https://localhost:${port}`);
-  const transport = new WebTransport(url);
+  const transport = new WebTransport(url, {
+    serverCertificateHashes: pinnedCertificateHashes(),
+  });
 
   await transport.ready;
 